@@ -1,7 +1,5 @@
 use async_trait::async_trait;
-use organix::{
-    service, IntercomMsg, Organix, Service, ServiceIdentifier, ServiceState, WatchdogBuilder,
-};
+use organix::{service, IntercomMsg, Organix, Service, ServiceIdentifier, ServiceState};
 use tokio::{
     io::{stdin, stdout, AsyncBufReadExt as _, AsyncWriteExt as _, BufReader},
     stream::StreamExt as _,
@@ -46,7 +44,9 @@ impl Service for StdinReader {
                 Ok(line) => {
                     tracing::debug!(%line, "read from stdin");
                     if let Err(err) = stdout.send(WriteMsg(line)).await {
-                        tracing::error!(%err);
+                        let reason = err.reason().to_string();
+                        let WriteMsg(line) = err.into_inner();
+                        tracing::error!(%reason, %line, "line not echoed");
                         break;
                     }
                 }
@@ -66,16 +66,17 @@ impl Service for StdoutWriter {
     }
 
     async fn start(mut self) {
-        let mut stdout = stdout();
-
-        while let Some(WriteMsg(msg)) = self.state.intercom_mut().recv().await {
-            if let Err(err) = stdout.write_all(msg.as_bytes()).await {
-                tracing::error!(%err);
-                break;
-            }
-            stdout.write_all(b"\n").await.unwrap();
-            stdout.flush().await.unwrap();
-        }
+        self.state
+            .run_loop(|WriteMsg(msg)| async move {
+                let mut stdout = stdout();
+                if let Err(err) = stdout.write_all(msg.as_bytes()).await {
+                    tracing::error!(%err);
+                    return;
+                }
+                stdout.write_all(b"\n").await.unwrap();
+                stdout.flush().await.unwrap();
+            })
+            .await
     }
 }
 
@@ -94,12 +95,5 @@ fn main() {
 
     tracing::subscriber::set_global_default(subscriber).expect("setting tracing default failed");
 
-    let watchdog = WatchdogBuilder::<StdEcho>::new().build();
-
-    let mut controller = watchdog.control();
-    watchdog.spawn(async move {
-        controller.start::<StdoutWriter>().await.unwrap();
-        controller.start::<StdinReader>().await.unwrap();
-    });
-    watchdog.wait_finished();
+    organix::run!(StdEcho, start: [StdoutWriter, StdinReader]).unwrap();
 }
@@ -1,4 +1,5 @@
-use crate::symbol::*;
+use crate::{duration, symbol::*};
+use std::time::Duration;
 use syn::{Attribute, Data, DataStruct, DeriveInput, Error, Fields, Ident, Member, Result, Type};
 
 pub enum Input<'a> {
@@ -15,17 +16,34 @@ pub struct Struct<'a> {
 #[derive(Default)]
 pub struct Attrs {
     pub shared: Option<bool>,
+    /// the pool of `#[runtime(shared = "...")]`
+    pub shared_pool: Option<String>,
     pub skip: Option<bool>,
     pub io_driver: Option<bool>,
     pub time_driver: Option<bool>,
+    #[allow(dead_code)]
     pub core_threads: Option<usize>,
+    #[allow(dead_code)]
     pub max_threads: Option<usize>,
+    #[allow(dead_code)]
     pub thread_stack_size: Option<usize>,
+    pub max_tasks: Option<usize>,
+    pub max_intercom_queue: Option<usize>,
+    pub max_concurrent_tasks: Option<usize>,
+    pub shutdown_group: Option<String>,
+    /// the timeout of `#[shutdown(finalize_on_abort)]`, `None` for the
+    /// default one
+    pub finalize_on_abort: Option<Option<Duration>>,
+    pub fair_intercom: Option<bool>,
+    pub ordered_intercom: Option<bool>,
+    /// `max` and `window` of the `crash_loop`
+    pub crash_loop: Option<(usize, Duration)>,
 }
 
 pub struct Field<'a> {
     pub original: &'a syn::Field,
     pub attrs: Attrs,
+    #[allow(dead_code)]
     pub member: Member,
     pub ty: &'a Type,
 }
@@ -55,11 +73,16 @@ impl<'a> Struct<'a> {
     pub fn default_is_shared(&self) -> bool {
         self.attrs.shared(false)
     }
+
+    /// the `#[runtime(shared = "...")]` pool of the app, if any
+    pub fn default_shared_pool(&self) -> Option<&str> {
+        self.attrs.shared_pool.as_deref()
+    }
 }
 
 impl<'a> Field<'a> {
     fn multiple_from_syn(fields: &'a Fields) -> Result<Vec<Self>> {
-        fields.iter().map(|field| Field::from_syn(field)).collect()
+        fields.iter().map(Field::from_syn).collect()
     }
 
     fn from_syn(node: &'a syn::Field) -> Result<Self> {
@@ -83,6 +106,16 @@ impl<'a> Field<'a> {
         self.attrs.shared(default_value)
     }
 
+    /// the named shared pool of the field, the one of the app (`default`)
+    /// unless the field is marked `#[runtime(shared)]` itself
+    pub fn shared_pool<'b>(&'b self, default: Option<&'b str>) -> Option<&'b str> {
+        if self.attrs.shared.is_some() {
+            self.attrs.shared_pool.as_deref()
+        } else {
+            default
+        }
+    }
+
     pub fn io_driver(&self) -> bool {
         self.attrs.io_driver()
     }
@@ -90,6 +123,45 @@ impl<'a> Field<'a> {
     pub fn time_driver(&self) -> bool {
         self.attrs.time_driver()
     }
+
+    /// the `#[shutdown(group = "...")]` of the field, if any
+    pub fn shutdown_group(&self) -> Option<&str> {
+        self.attrs.shutdown_group.as_deref()
+    }
+
+    /// the `#[shutdown(finalize_on_abort)]` of the field, if any
+    pub fn finalize_on_abort(&self) -> Option<Option<Duration>> {
+        self.attrs.finalize_on_abort
+    }
+
+    /// the field is marked `#[intercom(fair)]`
+    pub fn fair_intercom(&self) -> bool {
+        self.attrs.fair_intercom.unwrap_or_default()
+    }
+
+    /// `#[runtime(max_concurrent_tasks = N)]` of the field
+    pub fn max_concurrent_tasks(&self) -> Option<usize> {
+        self.attrs.max_concurrent_tasks
+    }
+
+    /// the field is marked `#[intercom(ordered)]`
+    pub fn ordered_intercom(&self) -> bool {
+        self.attrs.ordered_intercom.unwrap_or_default()
+    }
+
+    /// the `#[restart(crash_loop(...))]` of the field, if any
+    pub fn crash_loop(&self) -> Option<(usize, Duration)> {
+        self.attrs.crash_loop
+    }
+
+    /// the `#[budget(...)]` of the field, if any
+    pub fn budget(&self) -> Option<(Option<usize>, Option<usize>)> {
+        if self.attrs.max_tasks.is_some() || self.attrs.max_intercom_queue.is_some() {
+            Some((self.attrs.max_tasks, self.attrs.max_intercom_queue))
+        } else {
+            None
+        }
+    }
 }
 
 impl Attrs {
@@ -127,6 +199,23 @@ impl Attrs {
                                     ));
                                 }
                             }
+                            // Parse `#[runtime(shared = "io-pool")]`
+                            Meta(NameValue(m)) if m.path == SHARED => {
+                                let value = parse_str(&m.lit)?;
+                                if value.is_empty() {
+                                    return Err(Error::new_spanned(
+                                        &m.lit,
+                                        "the shared pool needs a name",
+                                    ));
+                                }
+                                if attrs.shared.replace(true).is_some() {
+                                    return Err(Error::new_spanned(
+                                        element,
+                                        "duplicated #[runtime(shared)]",
+                                    ));
+                                }
+                                attrs.shared_pool = Some(value);
+                            }
                             // Parse `#[runtime(skip)]`
                             Meta(Path(word)) if word == SKIP => {
                                 if attrs.skip.replace(true).is_some() {
@@ -154,6 +243,22 @@ impl Attrs {
                                     ));
                                 }
                             }
+                            // Parse `#[runtime(max_concurrent_tasks = 100)]`
+                            Meta(NameValue(m)) if m.path == MAX_CONCURRENT_TASKS => {
+                                let value = parse_usize(&m.lit)?;
+                                if value == 0 {
+                                    return Err(Error::new_spanned(
+                                        &m.lit,
+                                        "max_concurrent_tasks must be at least 1",
+                                    ));
+                                }
+                                if attrs.max_concurrent_tasks.replace(value).is_some() {
+                                    return Err(Error::new_spanned(
+                                        element,
+                                        "duplicated #[runtime(max_concurrent_tasks)]",
+                                    ));
+                                }
+                            }
                             _ => return Err(Error::new_spanned(element, "unexpected attribute")),
                         }
                     }
@@ -162,6 +267,183 @@ impl Attrs {
             }
         }
 
+        for attr in input.iter().filter(|f| f.path == BUDGET) {
+            match attr.parse_meta()? {
+                syn::Meta::List(meta_list) => {
+                    for element in meta_list.nested {
+                        use syn::{Meta::*, NestedMeta::*};
+                        match &element {
+                            // Parse `#[budget(max_tasks = 1000)]`
+                            Meta(NameValue(m)) if m.path == MAX_TASKS => {
+                                let value = parse_usize(&m.lit)?;
+                                if attrs.max_tasks.replace(value).is_some() {
+                                    return Err(Error::new_spanned(
+                                        element,
+                                        "duplicated #[budget(max_tasks)]",
+                                    ));
+                                }
+                            }
+                            // Parse `#[budget(max_intercom_queue = 100)]`
+                            Meta(NameValue(m)) if m.path == MAX_INTERCOM_QUEUE => {
+                                let value = parse_usize(&m.lit)?;
+                                if attrs.max_intercom_queue.replace(value).is_some() {
+                                    return Err(Error::new_spanned(
+                                        element,
+                                        "duplicated #[budget(max_intercom_queue)]",
+                                    ));
+                                }
+                            }
+                            _ => return Err(Error::new_spanned(element, "unexpected attribute")),
+                        }
+                    }
+                }
+                other => return Err(Error::new_spanned(other, "expected #[budget(...)]")),
+            }
+        }
+
+        for attr in input.iter().filter(|f| f.path == SHUTDOWN) {
+            match attr.parse_meta()? {
+                syn::Meta::List(meta_list) => {
+                    for element in meta_list.nested {
+                        use syn::{Meta::*, NestedMeta::*};
+                        match &element {
+                            // Parse `#[shutdown(group = "ingress")]`
+                            Meta(NameValue(m)) if m.path == GROUP => {
+                                let value = parse_str(&m.lit)?;
+                                if attrs.shutdown_group.replace(value).is_some() {
+                                    return Err(Error::new_spanned(
+                                        element,
+                                        "duplicated #[shutdown(group)]",
+                                    ));
+                                }
+                            }
+                            // Parse `#[shutdown(finalize_on_abort)]`
+                            Meta(Path(word)) if word == FINALIZE_ON_ABORT => {
+                                if attrs.finalize_on_abort.replace(None).is_some() {
+                                    return Err(Error::new_spanned(
+                                        element,
+                                        "duplicated #[shutdown(finalize_on_abort)]",
+                                    ));
+                                }
+                            }
+                            // Parse `#[shutdown(finalize_on_abort = "500ms")]`
+                            Meta(NameValue(m)) if m.path == FINALIZE_ON_ABORT => {
+                                let timeout = duration::parse(&m.lit)?;
+                                if attrs.finalize_on_abort.replace(Some(timeout)).is_some() {
+                                    return Err(Error::new_spanned(
+                                        element,
+                                        "duplicated #[shutdown(finalize_on_abort)]",
+                                    ));
+                                }
+                            }
+                            _ => return Err(Error::new_spanned(element, "unexpected attribute")),
+                        }
+                    }
+                }
+                other => return Err(Error::new_spanned(other, "expected #[shutdown(...)]")),
+            }
+        }
+
+        for attr in input.iter().filter(|f| f.path == INTERCOM) {
+            match attr.parse_meta()? {
+                syn::Meta::List(meta_list) => {
+                    for element in meta_list.nested {
+                        use syn::{Meta::*, NestedMeta::*};
+                        match &element {
+                            // Parse `#[intercom(fair)]`
+                            Meta(Path(word)) if word == FAIR => {
+                                if attrs.fair_intercom.replace(true).is_some() {
+                                    return Err(Error::new_spanned(
+                                        element,
+                                        "duplicated #[intercom(fair)]",
+                                    ));
+                                }
+                            }
+                            // Parse `#[intercom(ordered)]`
+                            Meta(Path(word)) if word == ORDERED => {
+                                if attrs.ordered_intercom.replace(true).is_some() {
+                                    return Err(Error::new_spanned(
+                                        element,
+                                        "duplicated #[intercom(ordered)]",
+                                    ));
+                                }
+                            }
+                            _ => return Err(Error::new_spanned(element, "unexpected attribute")),
+                        }
+                    }
+                }
+                other => return Err(Error::new_spanned(other, "expected #[intercom(...)]")),
+            }
+        }
+
+        for attr in input.iter().filter(|f| f.path == RESTART) {
+            match attr.parse_meta()? {
+                syn::Meta::List(meta_list) => {
+                    for element in meta_list.nested {
+                        use syn::{Meta::*, NestedMeta::*};
+                        match &element {
+                            // Parse `#[restart(crash_loop(max = 5, window = "60s"))]`
+                            Meta(List(list)) if list.path == CRASH_LOOP => {
+                                let crash_loop = parse_crash_loop(list)?;
+                                if attrs.crash_loop.replace(crash_loop).is_some() {
+                                    return Err(Error::new_spanned(
+                                        element,
+                                        "duplicated #[restart(crash_loop)]",
+                                    ));
+                                }
+                            }
+                            _ => return Err(Error::new_spanned(element, "unexpected attribute")),
+                        }
+                    }
+                }
+                other => return Err(Error::new_spanned(other, "expected #[restart(...)]")),
+            }
+        }
+
         Ok(attrs)
     }
 }
+
+fn parse_crash_loop(list: &syn::MetaList) -> Result<(usize, Duration)> {
+    let mut max = None;
+    let mut window = None;
+
+    for element in &list.nested {
+        use syn::{Meta::*, NestedMeta::*};
+        match element {
+            Meta(NameValue(m)) if m.path == MAX => {
+                if max.replace(parse_usize(&m.lit)?).is_some() {
+                    return Err(Error::new_spanned(element, "duplicated crash_loop(max)"));
+                }
+            }
+            Meta(NameValue(m)) if m.path == WINDOW => {
+                if window.replace(duration::parse(&m.lit)?).is_some() {
+                    return Err(Error::new_spanned(element, "duplicated crash_loop(window)"));
+                }
+            }
+            _ => return Err(Error::new_spanned(element, "unexpected attribute")),
+        }
+    }
+
+    match (max, window) {
+        (Some(max), Some(window)) => Ok((max, window)),
+        _ => Err(Error::new_spanned(
+            list,
+            "expected #[restart(crash_loop(max = ..., window = \"...\"))]",
+        )),
+    }
+}
+
+fn parse_str(lit: &syn::Lit) -> Result<String> {
+    match lit {
+        syn::Lit::Str(s) => Ok(s.value()),
+        other => Err(Error::new_spanned(other, "expected a string")),
+    }
+}
+
+fn parse_usize(lit: &syn::Lit) -> Result<usize> {
+    match lit {
+        syn::Lit::Int(int) => int.base10_parse(),
+        other => Err(Error::new_spanned(other, "expected an integer")),
+    }
+}
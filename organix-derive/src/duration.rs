@@ -0,0 +1,124 @@
+//! humanized durations in the attributes, e.g. `window = "60s"`
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use std::time::Duration;
+use syn::{Error, Lit, Result};
+
+/// parse a string literal such as `"500ms"`, `"10s"`, `"5m"` or `"1h"`
+///
+/// the errors are spanned at the literal.
+pub fn parse(lit: &Lit) -> Result<Duration> {
+    let value = match lit {
+        Lit::Str(s) => s.value(),
+        other => {
+            return Err(Error::new_spanned(
+                other,
+                "expected a duration such as \"10s\"",
+            ))
+        }
+    };
+
+    let index = value.find(|c: char| !c.is_ascii_digit()).ok_or_else(|| {
+        Error::new_spanned(lit, "missing the unit of the duration (ms, s, m or h)")
+    })?;
+    let (number, unit) = value.split_at(index);
+    if number.is_empty() {
+        return Err(Error::new_spanned(
+            lit,
+            "expected a duration such as \"10s\"",
+        ));
+    }
+    let number: u64 = number
+        .parse()
+        .map_err(|_| Error::new_spanned(lit, "duration too large"))?;
+
+    let factor = match unit {
+        "ms" => 1,
+        "s" => 1_000,
+        "m" => 60_000,
+        "h" => 3_600_000,
+        _ => {
+            return Err(Error::new_spanned(
+                lit,
+                format!("unknown unit `{}`, expected ms, s, m or h", unit),
+            ))
+        }
+    };
+
+    number
+        .checked_mul(factor)
+        .map(Duration::from_millis)
+        .ok_or_else(|| Error::new_spanned(lit, "duration too large"))
+}
+
+/// the expression building `duration` in the generated code
+pub fn to_tokens(duration: Duration) -> TokenStream {
+    let millis = duration.as_millis() as u64;
+    quote! {
+        ::std::time::Duration::from_millis(#millis)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_str(input: &str) -> Result<Duration> {
+        parse(&syn::parse_str(input).unwrap())
+    }
+
+    fn error(input: &str) -> String {
+        parse_str(input).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn units() {
+        assert_eq!(parse_str(r#""250ms""#).unwrap(), Duration::from_millis(250));
+        assert_eq!(parse_str(r#""10s""#).unwrap(), Duration::from_secs(10));
+        assert_eq!(parse_str(r#""5m""#).unwrap(), Duration::from_secs(300));
+        assert_eq!(parse_str(r#""1h""#).unwrap(), Duration::from_secs(3_600));
+        assert_eq!(parse_str(r#""0s""#).unwrap(), Duration::default());
+    }
+
+    #[test]
+    fn malformed() {
+        assert_eq!(error("10"), "expected a duration such as \"10s\"");
+        assert_eq!(
+            error(r#""""#),
+            "missing the unit of the duration (ms, s, m or h)"
+        );
+        assert_eq!(
+            error(r#""10""#),
+            "missing the unit of the duration (ms, s, m or h)"
+        );
+        assert_eq!(error(r#""s""#), "expected a duration such as \"10s\"");
+        assert_eq!(error(r#""-1s""#), "expected a duration such as \"10s\"");
+        assert_eq!(
+            error(r#""1.5s""#),
+            "unknown unit `.5s`, expected ms, s, m or h"
+        );
+        assert_eq!(
+            error(r#""10 s""#),
+            "unknown unit ` s`, expected ms, s, m or h"
+        );
+        assert_eq!(
+            error(r#""10d""#),
+            "unknown unit `d`, expected ms, s, m or h"
+        );
+        assert_eq!(
+            error(r#""10S""#),
+            "unknown unit `S`, expected ms, s, m or h"
+        );
+        assert_eq!(error(r#""99999999999999999999s""#), "duration too large");
+        assert_eq!(error(r#""9999999999999999h""#), "duration too large");
+    }
+
+    #[test]
+    fn tokens() {
+        assert_eq!(
+            to_tokens(Duration::from_secs(60)).to_string(),
+            quote! { ::std::time::Duration::from_millis(60000u64) }.to_string()
+        );
+    }
+}
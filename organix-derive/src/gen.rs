@@ -1,6 +1,7 @@
-use crate::ast::*;
+use crate::{ast::*, duration};
 use proc_macro2::TokenStream;
-use quote::quote;
+use quote::{format_ident, quote, quote_spanned};
+use syn::spanned::Spanned as _;
 
 pub fn gen(input: Input<'_>) -> TokenStream {
     match input {
@@ -11,21 +12,62 @@ pub fn gen(input: Input<'_>) -> TokenStream {
 fn gen_input(input: Struct<'_>) -> TokenStream {
     let struct_name = &input.ident;
     let status = input.status();
+    let has_ever_started = input.has_ever_started();
+    let current_status = input.current_status();
+    let status_reader = input.status_reader();
+    let shutdown_group = input.shutdown_group();
     let intercom = input.intercom();
+    let admin = input.admin();
     let stop = input.stop();
     let start = input.start();
     let new = input.new();
+    let placement = input.placement();
+    let services_dyn = input.services_dyn();
+    let service_count = input.fields().count();
+    let possible_values = input.possible_values();
+    let service_catalog = input.service_catalog();
+    let assertions = input.assertions();
+    let handles = input.handles();
+    let (impl_generics, ty_generics, where_clause) = input.original.generics.split_for_impl();
 
     quote! {
+        #assertions
+
         #[async_trait::async_trait]
         #[allow(clippy::unit_arg)]
-        impl ::organix::Organix for #struct_name {
+        impl #impl_generics ::organix::Organix for #struct_name #ty_generics #where_clause {
+            const SERVICE_COUNT: usize = #service_count;
+            const SERVICE_IDENTIFIERS: &'static [::organix::ServiceIdentifier] =
+                &[#( #possible_values ),*];
+
             #new
+            #shutdown_group
+            #placement
+            #services_dyn
             #start
             #status
+            #has_ever_started
+            #current_status
+            #status_reader
             #intercom
+            #admin
             #stop
         }
+
+        impl #impl_generics #struct_name #ty_generics #where_clause {
+            #service_catalog
+
+            /// build the watchdog of the app with the default settings, see
+            /// `WatchdogBuilder` to configure it
+            pub fn watchdog() -> ::organix::WatchdogMonitor
+            where
+                Self: 'static,
+            {
+                ::organix::WatchdogBuilder::<Self>::new().build()
+            }
+        }
+
+        #handles
     }
 }
 
@@ -34,6 +76,33 @@ impl<'a> Struct<'a> {
         self.fields.iter().filter(|field| !field.skip())
     }
 
+    /// assert every fields manage a service and that its intercom messages
+    /// can be sent to it from the other services (see `intercoms`), the
+    /// error is reported at the field's type rather than somewhere in the
+    /// generated code
+    fn assertions(&self) -> TokenStream {
+        let assertions = self.fields().map(|field| {
+            let ty = field.ty;
+            quote_spanned! {ty.span()=>
+                assert_manage_service::<#ty>();
+                assert_intercom_msg::<<#ty as ::organix::service::ManageService>::IntercomMsg>();
+            }
+        });
+
+        let (impl_generics, _, where_clause) = self.original.generics.split_for_impl();
+
+        quote! {
+            const _: () = {
+                fn assert_manage_service<T: ::organix::service::ManageService>() {}
+                fn assert_intercom_msg<M: ::organix::service::IntercomMsg + Send>() {}
+                #[allow(dead_code)]
+                fn assertions #impl_generics () #where_clause {
+                    #( #assertions )*
+                }
+            };
+        }
+    }
+
     fn possible_values(&self) -> Vec<TokenStream> {
         self.fields()
             .map(|field| {
@@ -46,31 +115,175 @@ impl<'a> Struct<'a> {
             .collect()
     }
 
-    #[allow(clippy::new_ret_no_self)]
+    fn service_catalog(&self) -> TokenStream {
+        let entries = self.fields().map(|field| {
+            let field_name = field.original.ident.as_ref().unwrap();
+            let entry = field_name.to_string();
+            let ty = field.ty;
+            quote! {
+                (#entry, <#ty as ::organix::service::ManageService>::SERVICE_IDENTIFIER)
+            }
+        });
+
+        // an associated constant rather than a local one so it can use the
+        // type parameters of a generic app
+        quote! {
+            #[doc(hidden)]
+            const __SERVICE_CATALOG: &'static [(&'static str, ::organix::ServiceIdentifier)] =
+                &[#( #entries ),*];
+
+            /// list the services of the app: the name of the field and the
+            /// identifier of the service it manages.
+            pub fn service_catalog() -> &'static [(&'static str, ::organix::ServiceIdentifier)] {
+                Self::__SERVICE_CATALOG
+            }
+        }
+    }
+
+    /// the `{App}Handles` struct, with the `IntercomSender` of every
+    /// services of the app
+    fn handles(&self) -> TokenStream {
+        let struct_name = &self.ident;
+        let vis = &self.original.vis;
+        let handles_name = format_ident!("{}Handles", struct_name);
+        let fields = self
+            .fields()
+            .map(|field| field.original.ident.as_ref().unwrap())
+            .collect::<Vec<_>>();
+        let types = self.fields().map(|field| field.ty).collect::<Vec<_>>();
+        let (impl_generics, ty_generics, where_clause) = self.original.generics.split_for_impl();
+        // the type parameters of a generic app are only used through the
+        // intercom messages of its services
+        let marker = if self.original.generics.params.is_empty() {
+            None
+        } else {
+            Some(quote! {
+                _app: ::std::marker::PhantomData<fn() -> #struct_name #ty_generics>,
+            })
+        };
+        let marker_value = marker
+            .as_ref()
+            .map(|_| quote! { _app: ::std::marker::PhantomData, });
+        let doc = format!(
+            "the `IntercomSender` of every services of [`{}`], see [`{}::handles`]",
+            struct_name, struct_name
+        );
+
+        quote! {
+            #[doc = #doc]
+            #vis struct #handles_name #impl_generics #where_clause {
+                #(
+                    pub #fields: ::organix::service::IntercomSender<
+                        <#types as ::organix::service::ManageService>::IntercomMsg,
+                    >,
+                )*
+                #marker
+            }
+
+            impl #impl_generics Clone for #handles_name #ty_generics #where_clause {
+                fn clone(&self) -> Self {
+                    #handles_name {
+                        #( #fields: self.#fields.clone(), )*
+                        #marker_value
+                    }
+                }
+            }
+
+            impl #impl_generics #handles_name #ty_generics #where_clause {
+                /// connect to every services of the running app
+                ///
+                /// the senders are the ones of the current run of the
+                /// services: connect again once a service restarted.
+                pub async fn connect(
+                    query: &mut ::organix::WatchdogQuery,
+                ) -> ::std::result::Result<Self, ::organix::WatchdogError> {
+                    ::std::result::Result::Ok(#handles_name {
+                        #(
+                            #fields: query
+                                .intercom_sender(
+                                    <#types as ::organix::service::ManageService>::SERVICE_IDENTIFIER,
+                                )
+                                .await?,
+                        )*
+                        #marker_value
+                    })
+                }
+            }
+
+            impl #impl_generics #struct_name #ty_generics #where_clause {
+                /// the `IntercomSender` of every services of the running
+                /// app, to send them messages without going through the
+                /// `WatchdogQuery`, see `{App}Handles::connect`
+                pub async fn handles(
+                    query: &mut ::organix::WatchdogQuery,
+                ) -> ::std::result::Result<#handles_name #ty_generics, ::organix::WatchdogError> {
+                    #handles_name::connect(query).await
+                }
+
+            }
+        }
+    }
+
+    #[allow(clippy::new_ret_no_self, clippy::wrong_self_convention)]
     fn new(&self) -> TokenStream {
         let default_is_shared = self.default_is_shared();
+        let default_shared_pool = self.default_shared_pool();
         let cases = self.fields().map(|field| {
             let field_name = field.original.ident.as_ref().unwrap();
             let thread_name = field_name.to_string();
+            let budget = budget(field);
+            let fair_intercom = fair_intercom(field);
+            let crash_loop = crash_loop(field);
+            let finalize_on_abort = finalize_on_abort(field);
+            let max_concurrent_tasks = max_concurrent_tasks(field);
 
             if field.shared(default_is_shared) {
+                let rt = match field.shared_pool(default_shared_pool) {
+                    Some(pool) => quote! { runtimes.pool_mut(#pool).unwrap() },
+                    None => quote! { runtimes.shared_mut().unwrap() },
+                };
+
                 quote! {
                     #field_name: {
-                        let rt = runtimes.shared_mut();
-                        ::organix::service::ServiceManager::with_runtime(rt)
+                        let rt = #rt;
+                        #[allow(unused_mut)]
+                        let mut sm = ::organix::service::ServiceManager::with_runtime(rt);
+                        #budget
+                        #fair_intercom
+                        #crash_loop
+                        #finalize_on_abort
+                        #max_concurrent_tasks
+                        sm
                     }
                 }
             } else {
                 let io_driver = field.io_driver();
                 let time_driver = field.time_driver();
+                let ty = field.ty;
 
                 quote! {
                     #field_name: {
                         let mut cfg = ::organix::runtime::RuntimeConfig::new(#thread_name);
                         cfg.io_driver = #io_driver;
                         cfg.time_driver = #time_driver;
-                        let mut rt = ::organix::runtime::Runtime::build(cfg).unwrap();
-                        let sm = ::organix::service::ServiceManager::with_runtime(&mut rt);
+                        runtimes.configure(
+                            <#ty as ::organix::service::ManageService>::SERVICE_IDENTIFIER,
+                            &mut cfg,
+                        );
+                        let mut rt = ::organix::runtime::Runtime::build(cfg).unwrap_or_else(|error| {
+                            panic!(
+                                "cannot build the runtime of service '{}': {}",
+                                <#ty as ::organix::service::ManageService>::SERVICE_IDENTIFIER,
+                                error,
+                            )
+                        });
+                        #[allow(unused_mut)]
+                        let mut sm = ::organix::service::ServiceManager::with_runtime(&mut rt);
+                        #budget
+                        #fair_intercom
+                        #crash_loop
+                        #finalize_on_abort
+                        #max_concurrent_tasks
                         runtimes.add(rt);
                         sm
                     }
@@ -87,21 +300,97 @@ impl<'a> Struct<'a> {
         }
     }
 
-    fn start(&self) -> TokenStream {
-        let possible_values = self.possible_values();
+    fn shutdown_group(&self) -> TokenStream {
+        let cases = self.fields().filter_map(|field| {
+            let group = field.shutdown_group()?;
+            let entry = field.original.ident.as_ref().unwrap().to_string();
+            Some(quote! {
+                #entry => Some(#group),
+            })
+        });
 
+        quote! {
+            fn shutdown_group(
+                service_identifier: ::organix::ServiceIdentifier,
+            ) -> Option<&'static str> {
+                match service_identifier {
+                    #( #cases )*
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    fn services_dyn(&self) -> TokenStream {
+        let fields: Vec<_> = self
+            .fields()
+            .map(|field| field.original.ident.as_ref().unwrap())
+            .collect();
+
+        quote! {
+            fn services_dyn(&self) -> Vec<&dyn ::organix::service::ManageServiceDyn> {
+                vec![ #( &self.#fields, )* ]
+            }
+
+            fn services_dyn_mut(&mut self) -> Vec<&mut dyn ::organix::service::ManageServiceDyn> {
+                vec![ #( &mut self.#fields, )* ]
+            }
+        }
+    }
+
+    fn placement(&self) -> TokenStream {
+        let default_is_shared = self.default_is_shared();
+        let default_shared_pool = self.default_shared_pool();
+        let entries = self.fields().map(|field| {
+            let field_name = field.original.ident.as_ref().unwrap();
+            let entry = field_name.to_string();
+
+            if field.shared(default_is_shared) {
+                let pool = match field.shared_pool(default_shared_pool) {
+                    Some(pool) => quote! { Some(#pool) },
+                    None => quote! { None },
+                };
+
+                quote! {
+                    (#entry, ::organix::runtime::RuntimePlacement::Shared {
+                        pool: #pool,
+                        io_driver: self.#field_name.has_io(),
+                        time_driver: self.#field_name.has_time(),
+                    })
+                }
+            } else {
+                quote! {
+                    (#entry, ::organix::runtime::RuntimePlacement::Individual {
+                        thread_name: #entry,
+                        io_driver: self.#field_name.has_io(),
+                        time_driver: self.#field_name.has_time(),
+                    })
+                }
+            }
+        });
+
+        quote! {
+            fn placement(
+                &self,
+            ) -> Vec<(::organix::ServiceIdentifier, ::organix::runtime::RuntimePlacement)> {
+                vec![#( #entries ),*]
+            }
+        }
+    }
+
+    fn start(&self) -> TokenStream {
         let cases = self.fields().map(|field| {
             let field_name = field.original.ident.as_ref().unwrap();
             let entry = field_name.to_string();
             quote! {
                 #entry => {
-                    match self.#field_name.runtime(watchdog_query) {
-                        Ok(rt) => Ok(rt.start()),
-                        Err(source) => Err(::organix::WatchdogError::CannotStartService {
+                    self.#field_name
+                        .runtime(watchdog_query)
+                        .and_then(::organix::service::ServiceRuntime::start)
+                        .map_err(|source| ::organix::WatchdogError::CannotStartService {
                             service_identifier,
                             source,
                         })
-                    }
                 }
             }
         });
@@ -116,7 +405,7 @@ impl<'a> Struct<'a> {
                     #( #cases ),*
                     _ => Err(::organix::WatchdogError::UnknownService {
                         service_identifier,
-                        possible_values: &[#( #possible_values ),*],
+                        possible_values: Self::SERVICE_IDENTIFIERS.to_vec(),
                     })
                 }
             }
@@ -124,26 +413,57 @@ impl<'a> Struct<'a> {
     }
 
     fn stop(&self) -> TokenStream {
-        let possible_values = self.possible_values();
-
         let cases = self.fields().map(|field| {
             let field_name = field.original.ident.as_ref().unwrap();
             let entry = field_name.to_string();
             quote! {
-                #entry => { Ok(self.#field_name.shutdown()) }
+                #entry => { Ok(self.#field_name.shutdown_with_reason(reason)) }
             }
         });
+        let kill_cases = self.fields().map(|field| {
+            let field_name = field.original.ident.as_ref().unwrap();
+            let entry = field_name.to_string();
+            quote! {
+                #entry => { Ok(self.#field_name.force_kill()) }
+            }
+        });
+        let fields = self
+            .fields()
+            .map(|field| field.original.ident.as_ref().unwrap())
+            .collect::<Vec<_>>();
 
         quote! {
+            fn stop_all(&mut self, reason: ::organix::service::ShutdownReason) {
+                #( self.#fields.shutdown_with_reason(reason); )*
+            }
+
+            fn kill_all(&mut self) {
+                #( self.#fields.force_kill(); )*
+            }
+
+            fn kill(
+                &mut self,
+                service_identifier: ::organix::ServiceIdentifier,
+            ) -> Result<(), ::organix::WatchdogError> {
+                match service_identifier {
+                    #( #kill_cases ),*
+                    _ => Err(::organix::WatchdogError::UnknownService {
+                        service_identifier,
+                        possible_values: Self::SERVICE_IDENTIFIERS.to_vec(),
+                    })
+                }
+            }
+
             fn stop(
                 &mut self,
                 service_identifier: ::organix::ServiceIdentifier,
+                reason: ::organix::service::ShutdownReason,
             ) -> Result<(), ::organix::WatchdogError> {
                 match service_identifier {
                     #( #cases ),*
                     _ => Err(::organix::WatchdogError::UnknownService {
                         service_identifier,
-                        possible_values: &[#( #possible_values ),*],
+                        possible_values: Self::SERVICE_IDENTIFIERS.to_vec(),
                     })
                 }
             }
@@ -151,8 +471,6 @@ impl<'a> Struct<'a> {
     }
 
     fn intercom(&self) -> TokenStream {
-        let possible_values = self.possible_values();
-
         let cases = self.fields().map(|field| {
             let field_name = field.original.ident.as_ref().unwrap();
             let entry = field_name.to_string();
@@ -170,16 +488,114 @@ impl<'a> Struct<'a> {
                     #( #cases ),*
                     _ => Err(::organix::WatchdogError::UnknownService {
                         service_identifier,
-                        possible_values: &[#( #possible_values ),*],
+                        possible_values: Self::SERVICE_IDENTIFIERS.to_vec(),
                     })
                 }
             }
         }
     }
 
-    fn status(&self) -> TokenStream {
-        let possible_values = self.possible_values();
+    fn admin(&self) -> TokenStream {
+        let cases = self.fields().map(|field| {
+            let field_name = field.original.ident.as_ref().unwrap();
+            let entry = field_name.to_string();
+            quote! {
+                #entry => { self.#field_name.admin() }
+            }
+        });
+
+        quote! {
+            fn admin(
+                &self,
+                service_identifier: ::organix::ServiceIdentifier,
+            ) -> Result<Box<dyn ::std::any::Any + Send>, ::organix::WatchdogError> {
+                match service_identifier {
+                    #( #cases ),*
+                    _ => Err(::organix::WatchdogError::UnknownService {
+                        service_identifier,
+                        possible_values: Self::SERVICE_IDENTIFIERS.to_vec(),
+                    })
+                }
+            }
+        }
+    }
+
+    fn has_ever_started(&self) -> TokenStream {
+        let cases = self.fields().map(|field| {
+            let field_name = field.original.ident.as_ref().unwrap();
+            let entry = field_name.to_string();
+            quote! {
+                #entry => { Ok(self.#field_name.has_ever_started()) }
+            }
+        });
+
+        quote! {
+            fn has_ever_started(
+                &self,
+                service_identifier: ::organix::ServiceIdentifier,
+            ) -> Result<bool, ::organix::WatchdogError> {
+                match service_identifier {
+                    #( #cases ),*
+                    _ => Err(::organix::WatchdogError::UnknownService {
+                        service_identifier,
+                        possible_values: Self::SERVICE_IDENTIFIERS.to_vec(),
+                    })
+                }
+            }
+        }
+    }
+
+    fn current_status(&self) -> TokenStream {
+        let cases = self.fields().map(|field| {
+            let field_name = field.original.ident.as_ref().unwrap();
+            let entry = field_name.to_string();
+            quote! {
+                #entry => { Ok(self.#field_name.current_status()) }
+            }
+        });
+
+        quote! {
+            fn current_status(
+                &self,
+                service_identifier: ::organix::ServiceIdentifier,
+            ) -> Result<::organix::service::Status, ::organix::WatchdogError> {
+                match service_identifier {
+                    #( #cases ),*
+                    _ => Err(::organix::WatchdogError::UnknownService {
+                        service_identifier,
+                        possible_values: Self::SERVICE_IDENTIFIERS.to_vec(),
+                    })
+                }
+            }
+        }
+    }
+
+    fn status_reader(&self) -> TokenStream {
+        let cases = self.fields().map(|field| {
+            let field_name = field.original.ident.as_ref().unwrap();
+            let entry = field_name.to_string();
+            quote! {
+                #entry => { Ok(self.#field_name.status_reader()) }
+            }
+        });
+
+        quote! {
+            fn status_reader(
+                &self,
+                service_identifier: ::organix::ServiceIdentifier,
+            ) -> Result<::organix::service::StatusReader, ::organix::WatchdogError> {
+                match service_identifier {
+                    #( #cases ),*
+                    _ => Err(::organix::WatchdogError::UnknownService {
+                        service_identifier,
+                        possible_values: Self::SERVICE_IDENTIFIERS.to_vec(),
+                    })
+                }
+            }
+        }
+    }
 
+    fn status(&self) -> TokenStream {
         let cases = self.fields().map(|field| {
             let field_name = field.original.ident.as_ref().unwrap();
             let entry = field_name.to_string();
@@ -197,10 +613,90 @@ impl<'a> Struct<'a> {
                     #( #cases ),*
                     _ => Err(::organix::WatchdogError::UnknownService {
                         service_identifier,
-                        possible_values: &[#( #possible_values ),*],
+                        possible_values: Self::SERVICE_IDENTIFIERS.to_vec(),
                     })
                 }
             }
         }
     }
 }
+
+fn budget(field: &Field<'_>) -> TokenStream {
+    let (max_tasks, max_intercom_queue) = match field.budget() {
+        Some(budget) => budget,
+        None => return quote! {},
+    };
+    let max_tasks = option(max_tasks);
+    let max_intercom_queue = option(max_intercom_queue);
+
+    quote! {
+        sm.set_budget(::organix::service::ResourceBudget {
+            max_tasks: #max_tasks,
+            max_intercom_queue: #max_intercom_queue,
+        });
+    }
+}
+
+fn fair_intercom(field: &Field<'_>) -> TokenStream {
+    let fair = if field.fair_intercom() {
+        quote! {
+            sm.set_fair_intercom(true);
+        }
+    } else {
+        quote! {}
+    };
+    let ordered = if field.ordered_intercom() {
+        quote! {
+            sm.set_ordered_intercom(true);
+        }
+    } else {
+        quote! {}
+    };
+
+    quote! {
+        #fair
+        #ordered
+    }
+}
+
+fn max_concurrent_tasks(field: &Field<'_>) -> TokenStream {
+    match field.max_concurrent_tasks() {
+        Some(max) => quote! {
+            sm.set_max_concurrent_tasks(#max);
+        },
+        None => quote! {},
+    }
+}
+
+fn finalize_on_abort(field: &Field<'_>) -> TokenStream {
+    let timeout = match field.finalize_on_abort() {
+        Some(Some(timeout)) => duration::to_tokens(timeout),
+        Some(None) => quote! { ::organix::service::FINALIZE_TIMEOUT },
+        None => return quote! {},
+    };
+    quote! {
+        sm.set_finalize_on_abort(Some(#timeout));
+    }
+}
+
+fn crash_loop(field: &Field<'_>) -> TokenStream {
+    match field.crash_loop() {
+        Some((max, window)) => {
+            let window = duration::to_tokens(window);
+            quote! {
+                sm.set_crash_loop(::organix::service::CrashLoop {
+                    max: #max,
+                    window: #window,
+                });
+            }
+        }
+        None => quote! {},
+    }
+}
+
+fn option(value: Option<usize>) -> TokenStream {
+    match value {
+        Some(value) => quote! { Some(#value) },
+        None => quote! { None },
+    }
+}
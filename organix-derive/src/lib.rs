@@ -4,10 +4,11 @@ use quote::quote;
 use syn::{parse_macro_input, DeriveInput};
 
 mod ast;
+mod duration;
 mod gen;
 mod symbol;
 
-#[proc_macro_derive(Organix, attributes(runtime))]
+#[proc_macro_derive(Organix, attributes(runtime, budget, shutdown, intercom, restart))]
 #[proc_macro_error]
 pub fn derive_organix(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
@@ -15,6 +15,20 @@ symbol!(SHARED, "shared");
 symbol!(SKIP, "skip");
 symbol!(IO_DRIVER, "io");
 symbol!(TIME_DRIVER, "time");
+symbol!(BUDGET, "budget");
+symbol!(MAX_TASKS, "max_tasks");
+symbol!(MAX_INTERCOM_QUEUE, "max_intercom_queue");
+symbol!(MAX_CONCURRENT_TASKS, "max_concurrent_tasks");
+symbol!(SHUTDOWN, "shutdown");
+symbol!(GROUP, "group");
+symbol!(FINALIZE_ON_ABORT, "finalize_on_abort");
+symbol!(INTERCOM, "intercom");
+symbol!(FAIR, "fair");
+symbol!(ORDERED, "ordered");
+symbol!(RESTART, "restart");
+symbol!(CRASH_LOOP, "crash_loop");
+symbol!(MAX, "max");
+symbol!(WINDOW, "window");
 
 impl PartialEq<Symbol> for Ident {
     fn eq(&self, other: &Symbol) -> bool {
@@ -22,7 +36,7 @@ impl PartialEq<Symbol> for Ident {
     }
 }
 
-impl<'a> PartialEq<Symbol> for &'a Ident {
+impl PartialEq<Symbol> for &Ident {
     fn eq(&self, other: &Symbol) -> bool {
         *self == other.0
     }
@@ -34,7 +48,7 @@ impl PartialEq<Symbol> for Path {
     }
 }
 
-impl<'a> PartialEq<Symbol> for &'a Path {
+impl PartialEq<Symbol> for &Path {
     fn eq(&self, word: &Symbol) -> bool {
         self.is_ident(word.0)
     }
@@ -89,23 +89,71 @@
 //! * `#[runtime(shared)]`: will make all the services to use a _shared_ runtime
 //!   by default. Otherwise the default is for every service to run an individual
 //!   runtime.
+//! * `#[runtime(shared = "io-pool")]`: same, on the named shared pool
+//!   `io-pool`.
 //!
 //! ## On the field of the `Organix` app type
 //!
 //! * `#[runtime(shared)]`: will make the associated service to use a shared runtime
 //!   with the other _shared_ labeled services. This shared runtime has `io` and
 //!   `time` drivers already enabled.
+//! * `#[runtime(shared = "cpu-pool")]`: will make the associated service to
+//!   use the named shared pool `cpu-pool`, a shared runtime of its own for
+//!   the services of the same pool. Configure it with `Runtimes::add_pool`
+//!   before `WatchdogBuilder::build_with_runtimes`, see
+//!   `RuntimeConfig::pool` for its default configuration.
 //! * `#[runtime(io)]`: enable the `io` driver;
 //! * `#[runtime(time)]`: enable the `time` driver;
 //! * `#[runtime(skip)]`: ignore the field.
+//! * `#[runtime(max_concurrent_tasks = 100)]`: bound the tasks spawned with
+//!   `ServiceState::spawn_limited` running at the same time.
+//! * `#[budget(max_tasks = 1000, max_intercom_queue = 100)]`: set a soft
+//!   resource budget to the service, see `service::ResourceBudget`.
+//! * `#[shutdown(group = "ingress")]`: put the service in a shutdown group,
+//!   see `WatchdogBuilder::shutdown_order`.
+//! * `#[shutdown(finalize_on_abort = "500ms")]`: run the finalizer of the
+//!   service when it is aborted, see `ServiceState::on_abort`. Without a
+//!   value the finalizer is given `service::FINALIZE_TIMEOUT`.
+//! * `#[intercom(fair)]`: rotate across the producers when receiving the
+//!   intercom messages, see `ServiceManager::set_fair_intercom`.
+//! * `#[intercom(ordered)]`: ignore the priority of the intercom messages
+//!   so they are received in send order, see
+//!   `ServiceManager::set_ordered_intercom`.
+//! * `#[restart(crash_loop(max = 5, window = "60s"))]`: refuse to restart
+//!   a service restarted too often, see `service::CrashLoop`.
+//!
+//! # Features
+//!
+//! * `tracing` (default): instrument the services and the watchdog with
+//!   `tracing` spans. Disabling it removes the `tracing-futures` dependency,
+//!   the `tracing` events are still emitted.
+//! * `intercom-spans`: capture the current span when sending an intercom
+//!   message, see `IntercomReceiver::recv_with_span`.
+//! * `signal`: shutdown the watchdog on `ctrl-c` in `WatchdogMonitor::run`
+//!   (and the [`run!`] macro).
+//! * `testing`: build a `ServiceState` without a watchdog, to unit test the
+//!   services (see `service::testing`).
+//! * `serde`: implement `Serialize` for the status reports and the
+//!   `WatchdogSnapshot`, to expose them on an admin endpoint.
+//! * `log-dir`: write the logs of every service to its own file, see
+//!   `WatchdogBuilder::per_service_log_dir` and the `tracing` module.
 //!
 //! [examples]: https://github.com/primetype/organix/tree/master/examples
 //! [`Watchdog`]: ./struct.WatchdogMonitor.html
 
+#[macro_use]
+mod trace;
+
 pub mod runtime;
 pub mod service;
+#[cfg(feature = "log-dir")]
+pub mod tracing;
 mod watchdog;
 
 pub use organix_derive::{IntercomMsg, Organix};
 pub use service::{Service, ServiceIdentifier, ServiceManager, ServiceState};
-pub use watchdog::{Organix, WatchdogBuilder, WatchdogError, WatchdogMonitor, WatchdogQuery};
+pub use watchdog::{
+    BatchCommand, BlockingWatchdogQuery, Organix, ReadOnlyWatchdogQuery, WatchdogBuilder,
+    WatchdogError, WatchdogEvent, WatchdogMonitor, WatchdogQuery, WatchdogSnapshot,
+    WeakWatchdogQuery,
+};
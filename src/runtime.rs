@@ -1,13 +1,30 @@
-use std::{collections::HashMap, future::Future};
+use crate::service::ServiceIdentifier;
+use std::{collections::HashMap, future::Future, io};
+use thiserror::Error;
 use tokio::runtime::Handle;
 
+/// default number of individual runtimes above which a warning is logged,
+/// see [`Runtimes::set_max_individuals`]
+///
+/// [`Runtimes::set_max_individuals`]: ./struct.Runtimes.html#method.set_max_individuals
+pub const DEFAULT_MAX_INDIVIDUAL_RUNTIMES: usize = 16;
+
+/// number of threads of a runtime whose `max_threads` is not set, as in
+/// tokio
+const DEFAULT_MAX_THREADS: usize = 512;
+
+pub(crate) type ConfigureRuntime = Box<dyn Fn(ServiceIdentifier, &mut RuntimeConfig) + Send + Sync>;
+
 pub struct Runtimes {
     watchdog: Runtime,
-    shared: Runtime,
+    shared: Option<Runtime>,
+    pools: HashMap<&'static str, Runtime>,
     individuals: HashMap<&'static str, Runtime>,
+    max_individuals: usize,
+    configure: Option<ConfigureRuntime>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct RuntimeConfig {
     pub thread_name: &'static str,
     pub io_driver: bool,
@@ -17,23 +34,107 @@ pub struct RuntimeConfig {
     pub thread_stack_size: Option<usize>,
 }
 
+/// invalid combinations of the [`RuntimeConfig`] settings
+///
+/// [`RuntimeConfig`]: ./struct.RuntimeConfig.html
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+pub enum RuntimeConfigError {
+    #[error("Runtime {thread_name} needs at least one core thread")]
+    NoCoreThreads { thread_name: &'static str },
+
+    #[error("Runtime {thread_name} needs at least one thread")]
+    NoThreads { thread_name: &'static str },
+
+    #[error("Runtime {thread_name} cannot have more core threads ({core_threads}) than threads ({max_threads})")]
+    TooManyCoreThreads {
+        thread_name: &'static str,
+        core_threads: usize,
+        max_threads: usize,
+    },
+
+    #[error("Runtime {thread_name} cannot have a thread stack size of 0")]
+    NoThreadStack { thread_name: &'static str },
+}
+
+/// where a service has been placed: on a shared runtime or on its own
+/// individual runtime, with the drivers enabled on that runtime
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum RuntimePlacement {
+    Shared {
+        /// the named shared pool, `None` for the default shared runtime
+        pool: Option<&'static str>,
+        io_driver: bool,
+        time_driver: bool,
+    },
+    Individual {
+        thread_name: &'static str,
+        io_driver: bool,
+        time_driver: bool,
+    },
+}
+
+/// the thread counts of a runtime, see [`Runtimes::metrics`]
+///
+/// [`Runtimes::metrics`]: ./struct.Runtimes.html#method.metrics
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct RuntimeMetrics {
+    pub thread_name: &'static str,
+    /// see [`Runtime::effective_core_threads`](./struct.Runtime.html#method.effective_core_threads)
+    pub core_threads: usize,
+    /// see [`Runtime::effective_max_threads`](./struct.Runtime.html#method.effective_max_threads)
+    pub max_threads: usize,
+}
+
 pub struct Runtime {
     rt: tokio::runtime::Runtime,
     config: RuntimeConfig,
+    core_threads: usize,
+    max_threads: usize,
 }
 
 impl Runtimes {
+    /// create the `Runtimes` with the default watchdog runtime
+    ///
+    /// the shared runtime is only built the first time it is needed
+    /// (see [`shared_mut`]).
+    ///
+    /// [`shared_mut`]: #method.shared_mut
     pub fn new() -> std::io::Result<Self> {
-        let watchdog = Runtime::build(RuntimeConfig::watchdog())?;
-        let shared = Runtime::build(RuntimeConfig::shared())?;
+        Self::with_watchdog(RuntimeConfig::watchdog())
+    }
+
+    /// create the `Runtimes` with a watchdog runtime built from the given
+    /// `config`, see [`new`]
+    ///
+    /// [`new`]: #method.new
+    pub fn with_watchdog(config: RuntimeConfig) -> std::io::Result<Self> {
+        let watchdog = Runtime::build(config)?;
 
         Ok(Self {
             watchdog,
-            shared,
+            shared: None,
+            pools: HashMap::new(),
             individuals: HashMap::new(),
+            max_individuals: DEFAULT_MAX_INDIVIDUAL_RUNTIMES,
+            configure: None,
         })
     }
 
+    /// create the `Runtimes` from already built `watchdog` and `shared`
+    /// runtimes.
+    pub fn from_runtimes(watchdog: Runtime, shared: Runtime) -> Self {
+        Self {
+            watchdog,
+            shared: Some(shared),
+            pools: HashMap::new(),
+            individuals: HashMap::new(),
+            max_individuals: DEFAULT_MAX_INDIVIDUAL_RUNTIMES,
+            configure: None,
+        }
+    }
+
     pub fn watchdog(&self) -> &Runtime {
         &self.watchdog
     }
@@ -42,16 +143,111 @@ impl Runtimes {
         &mut self.watchdog
     }
 
-    pub fn shared(&self) -> &Runtime {
-        &self.shared
+    /// access the shared runtime, if it has been built already
+    pub fn shared(&self) -> Option<&Runtime> {
+        self.shared.as_ref()
+    }
+
+    /// access the shared runtime, building it on first use
+    pub fn shared_mut(&mut self) -> std::io::Result<&mut Runtime> {
+        if self.shared.is_none() {
+            self.shared = Some(Runtime::build(RuntimeConfig::shared())?);
+        }
+
+        Ok(self.shared.as_mut().unwrap())
+    }
+
+    /// add the named shared pool `rt`, named after its `thread_name`
+    ///
+    /// use it to configure a pool before building the watchdog with
+    /// `WatchdogBuilder::build_with_runtimes`. It replaces the pool of the
+    /// same name, if any.
+    pub fn add_pool(&mut self, rt: Runtime) {
+        self.pools.insert(rt.config.thread_name, rt);
     }
 
-    pub fn shared_mut(&mut self) -> &mut Runtime {
-        &mut self.shared
+    /// access the named shared pool, if it has been built already
+    pub fn pool(&self, name: &'static str) -> Option<&Runtime> {
+        self.pools.get(name)
     }
 
+    /// access the named shared pool of the `#[runtime(shared = "...")]`
+    /// services, building it with [`RuntimeConfig::pool`] on first use
+    ///
+    /// [`RuntimeConfig::pool`]: ./struct.RuntimeConfig.html#method.pool
+    pub fn pool_mut(&mut self, name: &'static str) -> std::io::Result<&mut Runtime> {
+        if !self.pools.contains_key(name) {
+            self.add_pool(Runtime::build(RuntimeConfig::pool(name))?);
+        }
+
+        Ok(self.pools.get_mut(name).unwrap())
+    }
+
+    /// warn when more than `max` individual runtimes are added
+    ///
+    /// every individual runtime has its own thread pool: an app with many
+    /// services not marked `#[runtime(shared)]` quickly runs out of threads.
+    pub fn set_max_individuals(&mut self, max: usize) {
+        self.max_individuals = max;
+    }
+
+    /// customize the config of the individual runtimes of the services
+    /// before they are built, see `WatchdogBuilder::configure_runtime`
+    pub fn set_configure_runtime<F>(&mut self, configure: F)
+    where
+        F: Fn(ServiceIdentifier, &mut RuntimeConfig) + Send + Sync + 'static,
+    {
+        self.configure = Some(Box::new(configure));
+    }
+
+    /// apply the [`set_configure_runtime`] closure, if any, to the `config`
+    /// of the individual runtime of the service
+    ///
+    /// the changes are only kept if the resulting config is valid (see
+    /// [`RuntimeConfig::validate`]), otherwise the error is logged along
+    /// with the service identifier and `config` is left untouched.
+    ///
+    /// [`set_configure_runtime`]: #method.set_configure_runtime
+    /// [`RuntimeConfig::validate`]: ./struct.RuntimeConfig.html#method.validate
+    pub fn configure(&self, service_identifier: ServiceIdentifier, config: &mut RuntimeConfig) {
+        if let Some(configure) = &self.configure {
+            let mut configured = config.clone();
+            configure(service_identifier, &mut configured);
+
+            match configured.validate() {
+                Ok(()) => *config = configured,
+                Err(error) => tracing::error!(
+                    %service_identifier,
+                    %error,
+                    "invalid runtime config, keeping the one set by the attributes"
+                ),
+            }
+        }
+    }
+
+    /// the number of individual runtimes
+    pub fn individuals_count(&self) -> usize {
+        self.individuals.len()
+    }
+
+    /// add the individual runtime of a service
+    ///
+    /// a warning is logged if there are more individual runtimes than
+    /// allowed (see [`set_max_individuals`]).
+    ///
+    /// [`set_max_individuals`]: #method.set_max_individuals
     pub fn add(&mut self, rt: Runtime) {
-        self.individuals.insert(rt.config.thread_name, rt);
+        let thread_name = rt.config.thread_name;
+        self.individuals.insert(thread_name, rt);
+
+        if self.individuals.len() > self.max_individuals {
+            tracing::warn!(
+                thread_name,
+                individuals = self.individuals.len(),
+                max_individuals = self.max_individuals,
+                "too many individual runtimes, each has its own threads: consider #[runtime(shared)]"
+            );
+        }
     }
 
     pub fn individual(&self, k: &'static str) -> Option<&Runtime> {
@@ -61,10 +257,38 @@ impl Runtimes {
     pub fn individual_mut(&mut self, k: &'static str) -> Option<&mut Runtime> {
         self.individuals.get_mut(k)
     }
+
+    /// the individual runtimes with their thread names, in no particular
+    /// order
+    pub fn iter_individuals(&self) -> impl Iterator<Item = (&'static str, &Runtime)> {
+        self.individuals.iter().map(|(k, rt)| (*k, rt))
+    }
+
+    /// the thread counts of every runtime built so far: the watchdog's,
+    /// the shared one, the pools and the individual runtimes, in that order
+    pub fn metrics(&self) -> Vec<RuntimeMetrics> {
+        std::iter::once(&self.watchdog)
+            .chain(self.shared.as_ref())
+            .chain(self.pools.values())
+            .chain(self.individuals.values())
+            .map(Runtime::metrics)
+            .collect()
+    }
 }
 
 impl Runtime {
+    /// build the runtime from the given `config`
+    ///
+    /// an invalid `config` (see [`RuntimeConfig::validate`]) fails with an
+    /// `InvalidInput` error wrapping the [`RuntimeConfigError`].
+    ///
+    /// [`RuntimeConfig::validate`]: ./struct.RuntimeConfig.html#method.validate
+    /// [`RuntimeConfigError`]: ./enum.RuntimeConfigError.html
     pub fn build(config: RuntimeConfig) -> std::io::Result<Self> {
+        config
+            .validate()
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+
         let mut builder = tokio::runtime::Builder::new();
 
         builder.thread_name(config.thread_name);
@@ -77,22 +301,25 @@ impl Runtime {
             builder.enable_time();
         }
 
-        if let Some(core_threads) = config.core_threads {
-            builder.core_threads(core_threads);
-        }
-
-        if let Some(max_threads) = config.max_threads {
-            builder.max_threads(max_threads);
-        }
+        // tokio does not tell the number of threads it picked when they
+        // are not set, so they are set here
+        let max_threads = config.max_threads.unwrap_or(DEFAULT_MAX_THREADS);
+        let core_threads = config
+            .core_threads
+            .unwrap_or_else(|| num_cpus::get().max(1).min(max_threads));
+        builder.core_threads(core_threads);
+        builder.max_threads(max_threads);
 
         if let Some(thread_stack_size) = config.thread_stack_size {
             builder.thread_stack_size(thread_stack_size);
         }
 
-        builder
-            .threaded_scheduler()
-            .build()
-            .map(|rt| Self { rt, config })
+        builder.threaded_scheduler().build().map(|rt| Self {
+            rt,
+            config,
+            core_threads,
+            max_threads,
+        })
     }
 
     pub fn handle(&self) -> &Handle {
@@ -106,6 +333,26 @@ impl Runtime {
     pub fn config(&self) -> &RuntimeConfig {
         &self.config
     }
+
+    /// the number of worker threads of the runtime, `core_threads` or the
+    /// number of CPUs when it is not set
+    pub fn effective_core_threads(&self) -> usize {
+        self.core_threads
+    }
+
+    /// the maximum number of threads of the runtime, including the threads
+    /// for the blocking tasks: `max_threads` or 512 when it is not set
+    pub fn effective_max_threads(&self) -> usize {
+        self.max_threads
+    }
+
+    pub fn metrics(&self) -> RuntimeMetrics {
+        RuntimeMetrics {
+            thread_name: self.config.thread_name,
+            core_threads: self.core_threads,
+            max_threads: self.max_threads,
+        }
+    }
 }
 
 impl RuntimeConfig {
@@ -120,10 +367,43 @@ impl RuntimeConfig {
         }
     }
 
-    fn watchdog() -> Self {
+    /// check the settings can be used together
+    pub fn validate(&self) -> Result<(), RuntimeConfigError> {
+        let thread_name = self.thread_name;
+
+        if self.core_threads == Some(0) {
+            return Err(RuntimeConfigError::NoCoreThreads { thread_name });
+        }
+
+        if self.max_threads == Some(0) {
+            return Err(RuntimeConfigError::NoThreads { thread_name });
+        }
+
+        if let (Some(core_threads), Some(max_threads)) = (self.core_threads, self.max_threads) {
+            if core_threads > max_threads {
+                return Err(RuntimeConfigError::TooManyCoreThreads {
+                    thread_name,
+                    core_threads,
+                    max_threads,
+                });
+            }
+        }
+
+        if self.thread_stack_size == Some(0) {
+            return Err(RuntimeConfigError::NoThreadStack { thread_name });
+        }
+
+        Ok(())
+    }
+
+    /// default configuration of the watchdog's runtime
+    ///
+    /// the `io` driver is only enabled with the `signal` feature, to listen
+    /// for `ctrl-c`.
+    pub fn watchdog() -> Self {
         Self {
             thread_name: "watchdog",
-            io_driver: false,
+            io_driver: cfg!(feature = "signal"),
             time_driver: true,
             core_threads: None,
             max_threads: None,
@@ -131,7 +411,8 @@ impl RuntimeConfig {
         }
     }
 
-    fn shared() -> Self {
+    /// default configuration of the runtime shared between the services
+    pub fn shared() -> Self {
         Self {
             thread_name: "shared",
             io_driver: true,
@@ -141,4 +422,15 @@ impl RuntimeConfig {
             thread_stack_size: None,
         }
     }
+
+    /// default configuration of the named shared pool `name`, same as
+    /// [`shared`] with the threads named after the pool
+    ///
+    /// [`shared`]: #method.shared
+    pub fn pool(name: &'static str) -> Self {
+        Self {
+            thread_name: name,
+            ..Self::shared()
+        }
+    }
 }
@@ -0,0 +1,73 @@
+use crate::service::Service;
+use std::{
+    any::Any,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::{mpsc, oneshot};
+
+/// number of admin commands that can wait for the service to receive them
+pub(crate) const ADMIN_QUEUE_CAPACITY: usize = 8;
+
+/// a service accepting typed admin commands (e.g. "flush now", "dump
+/// state"), sent with `WatchdogQuery::admin`
+///
+/// the service receives the commands from `ServiceState::admin_rx` and
+/// answers every [`AdminRequest`] with an `AdminReply`.
+///
+/// [`AdminRequest`]: ./struct.AdminRequest.html
+pub trait ServiceAdmin: Service {
+    type AdminCmd: Send + 'static;
+    type AdminReply: Send + 'static;
+}
+
+/// an admin command received by the service, see `ServiceState::admin_rx`
+pub struct AdminRequest<T: ServiceAdmin> {
+    cmd: T::AdminCmd,
+    reply: oneshot::Sender<T::AdminReply>,
+}
+
+type Connect = Box<dyn Fn() -> Box<dyn Any + Send> + Send>;
+
+/// the sending end of the admin commands of the current run of a service,
+/// once it called `ServiceState::admin_rx`
+#[derive(Clone, Default)]
+pub(crate) struct AdminSlot(Arc<Mutex<Option<Connect>>>);
+
+impl<T: ServiceAdmin> AdminRequest<T> {
+    pub(crate) fn new(cmd: T::AdminCmd, reply: oneshot::Sender<T::AdminReply>) -> Self {
+        Self { cmd, reply }
+    }
+
+    pub fn cmd(&self) -> &T::AdminCmd {
+        &self.cmd
+    }
+
+    /// answer the command, the reply is dropped if the sender of the
+    /// command is gone
+    pub fn reply(self, reply: T::AdminReply) {
+        if self.reply.send(reply).is_err() {
+            // the sender stopped waiting for the reply
+        }
+    }
+}
+
+impl AdminSlot {
+    /// open the admin channel of the service, closing the previous one
+    pub(crate) fn open<T: ServiceAdmin>(&self) -> mpsc::Receiver<AdminRequest<T>> {
+        let (sender, receiver) = mpsc::channel::<AdminRequest<T>>(ADMIN_QUEUE_CAPACITY);
+        *self.0.lock().unwrap() = Some(Box::new(move || Box::new(sender.clone())));
+        receiver
+    }
+
+    /// close the admin channel, its receiver yields `None` once the
+    /// commands already sent are received
+    pub(crate) fn close(&self) {
+        *self.0.lock().unwrap() = None;
+    }
+
+    /// the `mpsc::Sender<AdminRequest<T>>` of the service, as expected by
+    /// `WatchdogQuery::admin`, if the service opened its admin channel
+    pub(crate) fn connect(&self) -> Option<Box<dyn Any + Send>> {
+        self.0.lock().unwrap().as_ref().map(|connect| connect())
+    }
+}
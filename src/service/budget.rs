@@ -0,0 +1,121 @@
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// soft limits on the resources a service may use
+///
+/// exceeding the budget does not stop the service, instead a warning is
+/// logged and a `WatchdogEvent::BudgetExceeded` is emitted.
+///
+/// Set it with `#[budget(max_tasks = 1000, max_intercom_queue = 100)]` on
+/// the service's field of the app.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceBudget {
+    /// maximum number of tasks spawned with `ServiceState::spawn` running
+    /// at the same time
+    pub max_tasks: Option<usize>,
+    /// maximum number of messages waiting in the service's intercom
+    pub max_intercom_queue: Option<usize>,
+}
+
+/// the resources tracked by the `ResourceBudget`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BudgetResource {
+    Tasks,
+    IntercomQueue,
+}
+
+/// keep track of whether the budget of a resource is currently exceeded
+/// so the event is only emitted once every time the limit is crossed
+#[derive(Debug, Default)]
+pub(crate) struct Usage {
+    exceeded: AtomicBool,
+}
+
+/// count the tasks spawned by a service that are still running
+#[derive(Debug, Default)]
+pub(crate) struct Tasks {
+    count: AtomicUsize,
+    pub(crate) usage: Usage,
+}
+
+/// decrement the number of running tasks when dropped
+pub(crate) struct TaskGuard(Arc<Tasks>);
+
+/// hard limit on the number of tasks spawned with
+/// `ServiceState::spawn_limited` running at the same time
+///
+/// set with `#[runtime(max_concurrent_tasks = 100)]`, kept across the
+/// restarts of the service.
+#[derive(Debug, Clone)]
+pub(crate) struct TaskLimit {
+    max: usize,
+    semaphore: Arc<Semaphore>,
+}
+
+impl Usage {
+    /// returns `true` if the `usage` just crossed the `limit`
+    pub(crate) fn exceeds(&self, usage: usize, limit: usize) -> bool {
+        if usage > limit {
+            !self.exceeded.swap(true, Ordering::SeqCst)
+        } else {
+            self.exceeded.store(false, Ordering::SeqCst);
+            false
+        }
+    }
+}
+
+impl Tasks {
+    pub(crate) fn count(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+
+    /// record a new running task, the task is running until the returned
+    /// guard is dropped
+    pub(crate) fn spawned(self: &Arc<Self>) -> TaskGuard {
+        self.count.fetch_add(1, Ordering::SeqCst);
+        TaskGuard(Arc::clone(self))
+    }
+}
+
+impl TaskLimit {
+    /// at least one task is allowed to run
+    pub(crate) fn new(max: usize) -> Self {
+        let max = std::cmp::max(1, max);
+        Self {
+            max,
+            semaphore: Arc::new(Semaphore::new(max)),
+        }
+    }
+
+    /// wait for a task to be allowed to run, it is running until the
+    /// returned permit is dropped
+    pub(crate) async fn acquire(&self) -> OwnedSemaphorePermit {
+        Arc::clone(&self.semaphore).acquire_owned().await
+    }
+
+    /// the number of tasks currently running
+    pub(crate) fn running(&self) -> usize {
+        self.max - self.semaphore.available_permits()
+    }
+}
+
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        self.0.count.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl fmt::Display for BudgetResource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Tasks => f.write_str("tasks"),
+            Self::IntercomQueue => f.write_str("intercom queue"),
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex, Weak,
+};
+use tokio::sync::watch;
+
+/// token to signal cancellation to a hierarchy of tasks
+///
+/// cancelling a token cancels all of its children tokens (see
+/// [`child_token`](#method.child_token)). Cancelling a child token does
+/// not cancel its parent.
+#[derive(Clone)]
+pub struct CancellationToken {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    cancelled: AtomicBool,
+    notifier: watch::Sender<()>,
+    notified: watch::Receiver<()>,
+    children: Mutex<Vec<Weak<Inner>>>,
+}
+
+impl CancellationToken {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        let (notifier, notified) = watch::channel(());
+        Self {
+            inner: Arc::new(Inner {
+                cancelled: AtomicBool::new(false),
+                notifier,
+                notified,
+                children: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    /// create a new token that will be cancelled when this token is
+    pub fn child_token(&self) -> Self {
+        let child = Self::new();
+
+        {
+            let mut children = self.inner.children.lock().unwrap();
+            children.retain(|child| child.strong_count() > 0);
+            children.push(Arc::downgrade(&child.inner));
+        }
+
+        // the parent may have been cancelled before the child was registered
+        if self.is_cancelled() {
+            child.cancel();
+        }
+
+        child
+    }
+
+    /// cancel the token and all of its children
+    pub fn cancel(&self) {
+        self.inner.cancel()
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// future that resolves once the token has been cancelled
+    pub async fn cancelled(&self) {
+        let mut notified = self.inner.notified.clone();
+
+        while !self.is_cancelled() {
+            // the notifier lives as long as the token so the channel
+            // cannot be closed
+            notified.recv().await;
+        }
+    }
+}
+
+impl Inner {
+    fn cancel(&self) {
+        if self.cancelled.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        if self.notifier.broadcast(()).is_err() {
+            // the token keeps a receiver alive, this cannot fail
+        }
+
+        let children = std::mem::take(&mut *self.children.lock().unwrap());
+        for child in children.iter().filter_map(Weak::upgrade) {
+            child.cancel();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::FutureExt as _;
+
+    #[test]
+    fn cancel_children() {
+        let parent = CancellationToken::new();
+        let child = parent.child_token();
+        let grand_child = child.child_token();
+
+        parent.cancel();
+
+        assert!(child.is_cancelled());
+        assert!(grand_child.is_cancelled());
+        assert!(grand_child.cancelled().now_or_never().is_some());
+    }
+
+    #[test]
+    fn do_not_cancel_parent() {
+        let parent = CancellationToken::new();
+        let child = parent.child_token();
+
+        child.cancel();
+
+        assert!(!parent.is_cancelled());
+        assert!(parent.cancelled().now_or_never().is_none());
+
+        // children created after the cancellation are cancelled too
+        parent.cancel();
+        assert!(parent.child_token().is_cancelled());
+    }
+}
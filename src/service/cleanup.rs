@@ -0,0 +1,54 @@
+use std::{
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::{Arc, Mutex},
+};
+
+type Cleanup = Box<dyn FnOnce() + Send + 'static>;
+
+/// the cleanups registered by a service with `ServiceState::on_cleanup`
+///
+/// they are kept outside of the service's task so they are run however the
+/// service stopped: finished, panicked or aborted.
+#[derive(Clone, Default)]
+pub(crate) struct Cleanups {
+    cleanups: Arc<Mutex<Vec<Cleanup>>>,
+}
+
+impl Cleanups {
+    pub(crate) fn push(&self, cleanup: Cleanup) {
+        self.cleanups.lock().unwrap().push(cleanup)
+    }
+
+    /// run the cleanups, the latest registered first
+    ///
+    /// a panicking cleanup does not prevent the other ones from running.
+    pub(crate) fn run(&self) {
+        let cleanups = std::mem::take(&mut *self.cleanups.lock().unwrap());
+        for cleanup in cleanups.into_iter().rev() {
+            if catch_unwind(AssertUnwindSafe(cleanup)).is_err() {
+                tracing::error!("cleanup panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_in_reverse_order_once() {
+        let cleanups = Cleanups::default();
+        let order = Arc::new(Mutex::new(Vec::new()));
+
+        for i in 0..3 {
+            let order = Arc::clone(&order);
+            cleanups.push(Box::new(move || order.lock().unwrap().push(i)));
+        }
+        cleanups.push(Box::new(|| panic!("cleanup")));
+
+        cleanups.run();
+        cleanups.run();
+        assert_eq!(*order.lock().unwrap(), vec![2, 1, 0]);
+    }
+}
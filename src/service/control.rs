@@ -1,4 +1,5 @@
 use std::{
+    fmt,
     pin::Pin,
     task::{Context, Poll},
 };
@@ -9,10 +10,25 @@ use tokio::{
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Control {
-    Shutdown,
+    Shutdown { reason: ShutdownReason },
     Kill,
 }
 
+/// why a service has been asked to shutdown
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ShutdownReason {
+    /// the service has been stopped on its own (see `WatchdogQuery::stop`)
+    UserRequested,
+    /// the whole watchdog is shutting down, the service is killed if it
+    /// does not stop within `WatchdogBuilder::total_shutdown_timeout`
+    WatchdogShutdown,
+    /// a service this service depends on failed
+    DependencyFailed,
+    /// the app is reloading, the service is started again once stopped
+    /// (see `WatchdogQuery::reload`)
+    Reload,
+}
+
 /// a controller can be used to send control command to a service.
 ///
 /// it is intended that any update of the command will erase the previous
@@ -65,6 +81,11 @@ impl Controller {
 }
 
 impl ControlReader {
+    /// the last control sent to the service
+    pub fn current(&self) -> Control {
+        *self.receiver.borrow()
+    }
+
     pub async fn updated(&mut self) -> Option<Control> {
         self.receiver.recv().await
     }
@@ -77,3 +98,14 @@ impl Stream for ControlReader {
         Pin::new(&mut self.get_mut().receiver).poll_next(cx)
     }
 }
+
+impl fmt::Display for ShutdownReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UserRequested => f.write_str("requested by the user"),
+            Self::WatchdogShutdown => f.write_str("watchdog shutting down"),
+            Self::DependencyFailed => f.write_str("dependency failed"),
+            Self::Reload => f.write_str("reloading"),
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::time::Instant;
+
+/// consider the service is crash looping if it is restarted more than
+/// `max` times within `window`
+///
+/// the window is evaluated when the service exits on its own: a crash
+/// looping service becomes `Failed` and a `WatchdogEvent::CrashLooping` is
+/// emitted. It is not restarted until its restarts leave the window.
+///
+/// Set it with `#[restart(crash_loop(max = 5, window = "60s"))]` on the
+/// service's field of the app.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CrashLoop {
+    pub max: usize,
+    pub window: Duration,
+}
+
+/// the time of the latest restarts of a service, within the window of
+/// its `CrashLoop`
+///
+/// shared between the `ServiceManager`, recording the restarts, and the
+/// control of the running service, evaluating the window when it exits.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Restarts {
+    times: Arc<Mutex<VecDeque<Instant>>>,
+}
+
+impl Restarts {
+    /// record a restart at `now`
+    pub(crate) fn record(&self, crash_loop: CrashLoop, now: Instant) {
+        let mut times = self.times.lock().unwrap();
+        Self::expire(&mut times, crash_loop, now);
+        times.push_back(now);
+    }
+
+    /// the number of restarts within the window at `now`, if it is more
+    /// than the `CrashLoop` allows
+    pub(crate) fn crash_looping(&self, crash_loop: CrashLoop, now: Instant) -> Option<usize> {
+        let mut times = self.times.lock().unwrap();
+        Self::expire(&mut times, crash_loop, now);
+
+        if times.len() > crash_loop.max {
+            Some(times.len())
+        } else {
+            None
+        }
+    }
+
+    /// forget the restarts that left the window
+    fn expire(times: &mut VecDeque<Instant>, crash_loop: CrashLoop, now: Instant) {
+        while let Some(time) = times.front() {
+            if now.duration_since(*time) < crash_loop.window {
+                break;
+            }
+            times.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rolling_window() {
+        let crash_loop = CrashLoop {
+            max: 2,
+            window: Duration::from_secs(60),
+        };
+        let restarts = Restarts::default();
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        restarts.record(crash_loop, start);
+        restarts.record(crash_loop, at(10));
+        assert_eq!(restarts.crash_looping(crash_loop, at(10)), None);
+        restarts.record(crash_loop, at(20));
+        assert_eq!(restarts.crash_looping(crash_loop, at(20)), Some(3));
+        // the first restart left the window
+        assert_eq!(restarts.crash_looping(crash_loop, at(65)), None);
+        restarts.record(crash_loop, at(65));
+        assert_eq!(restarts.crash_looping(crash_loop, at(65)), Some(3));
+        assert_eq!(restarts.crash_looping(crash_loop, at(200)), None);
+    }
+}
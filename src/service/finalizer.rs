@@ -0,0 +1,79 @@
+use futures_util::future::Aborted;
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// bound on the finalizer of a service, see `#[shutdown(finalize_on_abort)]`
+pub const FINALIZE_TIMEOUT: Duration = Duration::from_secs(1);
+
+type Finalize = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+/// the finalizer registered by a service with `ServiceState::on_abort`
+///
+/// unlike the service's own future it is kept outside of the task of the
+/// service, so it can still be run once that future has been aborted.
+#[derive(Clone, Default)]
+pub(crate) struct Finalizer {
+    finalize: Arc<Mutex<Option<Finalize>>>,
+}
+
+impl Finalizer {
+    /// replace the finalizer
+    pub(crate) fn set(&self, finalize: Finalize) {
+        *self.finalize.lock().unwrap() = Some(finalize);
+    }
+
+    /// run the `service`, and the finalizer if the service is aborted
+    pub(crate) async fn on_abort<F>(self, service: F)
+    where
+        F: Future<Output = Result<(), Aborted>>,
+    {
+        if service.await.is_ok() {
+            return;
+        }
+
+        let finalize = self.finalize.lock().unwrap().take();
+        if let Some(finalize) = finalize {
+            tracing::debug!("running the finalizer of the aborted service");
+            finalize.await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::future::{abortable, FutureExt as _};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    fn finalizer(finalized: &Arc<AtomicBool>) -> Finalizer {
+        let finalizer = Finalizer::default();
+        let finalized = Arc::clone(finalized);
+        finalizer.set(Box::pin(async move {
+            finalized.store(true, Ordering::SeqCst);
+        }));
+        finalizer
+    }
+
+    #[test]
+    fn only_run_on_abort() {
+        let finalized = Arc::new(AtomicBool::new(false));
+        let (service, _) = abortable(async {});
+        finalizer(&finalized)
+            .on_abort(service)
+            .now_or_never()
+            .unwrap();
+        assert!(!finalized.load(Ordering::SeqCst));
+
+        let (service, abort_handle) = abortable(futures_util::future::pending::<()>());
+        abort_handle.abort();
+        finalizer(&finalized)
+            .on_abort(service)
+            .now_or_never()
+            .unwrap();
+        assert!(finalized.load(Ordering::SeqCst));
+    }
+}
@@ -1,25 +1,137 @@
 use crate::{
-    service::{Service, Stats},
-    watchdog::{ControlCommand, Reply, WatchdogError, WatchdogQuery},
+    service::{
+        budget::Usage,
+        stats::{self, AtomicStats},
+        BudgetResource, Service, ServiceIdentifier, StatusReader,
+    },
+    trace::Instrument as _,
+    watchdog::{emit, ControlCommand, Reply, WatchdogError, WatchdogEvent, WatchdogQuery},
 };
+use futures_util::{future::poll_fn, sink::Sink};
 use std::sync::{
-    atomic::{AtomicU64, Ordering},
+    atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
     Arc, Mutex,
 };
-use std::time::Instant;
-use tokio::sync::{
-    mpsc::{
-        self,
-        error::{SendError, TrySendError},
+use std::{
+    collections::VecDeque,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+use tokio::{
+    stream::Stream,
+    sync::{
+        broadcast,
+        mpsc::{
+            self,
+            error::{SendError, TrySendError},
+        },
+        oneshot, watch,
     },
-    oneshot,
 };
-use tracing_futures::Instrument as _;
 
 #[derive(Debug)]
 pub struct NoIntercom;
 
-pub trait IntercomMsg: std::fmt::Debug + 'static {}
+/// capacity of every lanes of the intercom channel
+const INTERCOM_LANE_CAPACITY: usize = 10;
+/// number of messages pulled from the `Normal` priority lane in fair mode,
+/// on top of the capacity of the lanes
+const FAIR_BUFFER_CAPACITY: usize = INTERCOM_LANE_CAPACITY;
+
+pub trait IntercomMsg: std::fmt::Debug + 'static {
+    /// the priority of the message. Messages with a `High` priority
+    /// are received before the `Normal` priority messages already
+    /// queued in the intercom.
+    ///
+    /// # Ordering
+    ///
+    /// the messages sent by one `IntercomSender` of a given priority are
+    /// received in the order they were sent, in fair mode too. A `High`
+    /// priority message may overtake the `Normal` ones sent before it by
+    /// the same sender, unless the priorities are ignored with
+    /// `ServiceManager::set_ordered_intercom`. There is no ordering between
+    /// the messages of different senders, every clone being a sender of its
+    /// own.
+    fn priority(&self) -> Priority {
+        Priority::Normal
+    }
+
+    /// how long the message is worth processing once sent
+    ///
+    /// a message still queued in the intercom once its time to live has
+    /// elapsed is dropped by the receiver instead of being delivered, it is
+    /// counted in `IntercomStatus::number_expired`.
+    fn ttl(&self) -> Option<Duration> {
+        None
+    }
+
+    /// approximate number of bytes held by the message, accounted in
+    /// `IntercomStatus::queued_bytes` while it is queued
+    ///
+    /// the messages of size `0` (the default) are not accounted at all.
+    fn size_hint(&self) -> usize {
+        0
+    }
+}
+
+/// define an `IntercomMsg` enum wrapping different message types
+///
+/// every variant wraps one message type, the `From` conversions are
+/// implemented so the messages can be sent with `Intercom::send_as`.
+///
+/// ```
+/// #[derive(Debug)]
+/// pub struct PingMsg;
+/// #[derive(Debug)]
+/// pub struct ConfigMsg {
+///     pub verbose: bool,
+/// }
+///
+/// organix::intercom_enum! {
+///     #[derive(Debug)]
+///     pub enum PongMsg {
+///         Ping(PingMsg),
+///         Config(ConfigMsg),
+///     }
+/// }
+///
+/// let msg: PongMsg = ConfigMsg { verbose: true }.into();
+/// assert!(matches!(msg, PongMsg::Config(ConfigMsg { verbose: true })));
+/// ```
+#[macro_export]
+macro_rules! intercom_enum {
+    (
+        $(#[$meta:meta])*
+        $vis:vis enum $name:ident {
+            $( $variant:ident ( $ty:ty ) ),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        $vis enum $name {
+            $( $variant ( $ty ) ),*
+        }
+
+        impl $crate::service::IntercomMsg for $name {}
+
+        $(
+            impl ::std::convert::From<$ty> for $name {
+                fn from(msg: $ty) -> Self {
+                    $name::$variant(msg)
+                }
+            }
+        )*
+    };
+}
+
+/// priority levels of the intercom messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    Normal,
+    High,
+}
 
 pub struct Intercom<T: Service> {
     state: IntercomState<T::IntercomMsg>,
@@ -32,34 +144,181 @@ enum IntercomState<T> {
     Connected { connection: IntercomSender<T> },
 }
 
+/// the message could not be delivered by `Intercom::send`, it is given
+/// back so it can be retried or logged
+#[derive(Debug, Error)]
+#[error("Cannot deliver the intercom message")]
+pub struct IntercomError<M> {
+    msg: M,
+    #[source]
+    reason: WatchdogError,
+}
+
+impl<M> IntercomError<M> {
+    /// why the message could not be delivered
+    pub fn reason(&self) -> &WatchdogError {
+        &self.reason
+    }
+
+    /// the message that was not delivered
+    pub fn into_inner(self) -> M {
+        self.msg
+    }
+}
+
+impl<M> From<IntercomError<M>> for WatchdogError {
+    fn from(error: IntercomError<M>) -> Self {
+        error.reason
+    }
+}
+
 pub struct IntercomStats {
     sent_counter: Arc<AtomicU64>,
     received_counter: Arc<AtomicU64>,
-    stats: Arc<Mutex<Stats>>,
+    expired_counter: Arc<AtomicU64>,
+    queued_bytes: Arc<AtomicUsize>,
+    stats: Arc<AtomicStats>,
+    capacity: usize,
 }
 
 pub struct IntercomSender<T> {
-    sender: mpsc::Sender<(Instant, T)>,
+    high: mpsc::Sender<Envelope<T>>,
+    normal: mpsc::Sender<Envelope<T>>,
     sent_counter: Arc<AtomicU64>,
+    queued_bytes: Arc<AtomicUsize>,
+    connections: Arc<Connections>,
+    budget: Option<Arc<QueueBudget>>,
+    /// identify the messages of this sender, every clone is a new producer
+    producer: usize,
+    /// closed once the receiver is dropped, see `closed`
+    closed: watch::Receiver<()>,
+    /// ignore the priority of the messages, see `set_ordered`
+    ordered: bool,
+    /// the message given to `Sink::start_send`, sent on the next
+    /// `poll_ready` or `poll_flush`. Only accessed with `get_mut`, the
+    /// `Mutex` keeps the sender `Sync` whatever the message
+    pending: Mutex<Option<T>>,
+}
+
+/// soft limit on the number of messages waiting in the intercom
+struct QueueBudget {
+    service_identifier: ServiceIdentifier,
+    limit: usize,
+    received_counter: Arc<AtomicU64>,
+    expired_counter: Arc<AtomicU64>,
+    usage: Usage,
+    events: broadcast::Sender<WatchdogEvent>,
+}
+
+/// a message in transit in the intercom
+struct Envelope<T> {
+    sent_at: Instant,
+    /// the message is dropped if not received by then, see
+    /// `IntercomMsg::ttl`
+    expires_at: Option<Instant>,
+    /// the sender of the message, see `IntercomSender::producer`
+    producer: usize,
+    /// see `IntercomMsg::size_hint`
+    size: usize,
+    /// the span current when the message was sent
+    #[cfg(feature = "intercom-spans")]
+    span: tracing::Span,
+    msg: T,
 }
 
 pub struct IntercomReceiver<T> {
-    receiver: mpsc::Receiver<(Instant, T)>,
+    high: mpsc::Receiver<Envelope<T>>,
+    normal: mpsc::Receiver<Envelope<T>>,
     received_counter: Arc<AtomicU64>,
-    stats: Arc<Mutex<Stats>>,
+    expired_counter: Arc<AtomicU64>,
+    queued_bytes: Arc<AtomicUsize>,
+    stats: Arc<AtomicStats>,
+    connections: Arc<Connections>,
+    drain: Option<Drain>,
+    fair: Option<Fair<T>>,
+    /// never used to send, only dropped along with the receiver
+    _closed: watch::Sender<()>,
+}
+
+/// follow the status of the service while in drain mode
+///
+/// once the service is shutting down the receiver stops waiting for new
+/// messages and only yields the ones already queued.
+struct Drain {
+    status: StatusReader,
+    shutting_down: bool,
+}
+
+/// rotate across the producers of the `Normal` priority lane so a chatty
+/// producer cannot monopolize the receiver
+///
+/// the messages already queued in the lane are pulled (up to
+/// `FAIR_BUFFER_CAPACITY`) and served one producer after the other. They
+/// are still counted in the intercom's queue depth until received.
+struct Fair<T> {
+    /// the messages pulled from the lane by producer, in the order the
+    /// producers are served. A producer without messages is removed.
+    queues: VecDeque<(usize, VecDeque<Envelope<T>>)>,
+    buffered: usize,
+}
+
+/// keep track of the number of [`IntercomSender`] alive for a given
+/// intercom channel.
+///
+/// The first sender is the one owned by the `ServiceManager`, every
+/// other senders are clones handed out to the other services.
+struct Connections {
+    count: AtomicUsize,
+    ever_connected: AtomicBool,
+    next_producer: AtomicUsize,
+    updated: watch::Sender<()>,
+    update_reader: watch::Receiver<()>,
 }
 
 impl IntercomMsg for NoIntercom {}
 
+/// share a large immutable message (e.g. a loaded config) with many
+/// services: sending it to every recipient only bumps its reference count,
+/// see `IntercomSender::broadcast_shared`
+impl<M: IntercomMsg> IntercomMsg for Arc<M> {
+    fn priority(&self) -> Priority {
+        (**self).priority()
+    }
+
+    fn ttl(&self) -> Option<Duration> {
+        (**self).ttl()
+    }
+
+    fn size_hint(&self) -> usize {
+        (**self).size_hint()
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct IntercomStatus {
     /// number of messages that has been sent through the intercom
     pub number_sent: u64,
     /// the number of messages that has been actually read from
     /// the intercom
     pub number_received: u64,
+    /// the number of messages dropped because their time to live elapsed
+    /// before they could be received (see `IntercomMsg::ttl`)
+    pub number_expired: u64,
     /// number of opened connection to the service
     pub number_connections: usize,
+    /// number of messages the intercom can hold (`0` if unbounded)
+    pub capacity: usize,
+    /// number of messages sent but not yet received
+    pub queue_depth: usize,
+    /// approximate number of bytes of the messages sent but not yet
+    /// received, see `IntercomMsg::size_hint`
+    pub queued_bytes: usize,
+    /// `queue_depth / capacity`, always `0.0` for an unbounded intercom
+    ///
+    /// this can go above `1.0` when senders are waiting for room in the
+    /// intercom.
+    pub utilization: f64,
     /// mean to the time it gets between when a message is sent and
     /// when it is actually received by the Service.
     pub processing_speed_mean: f64,
@@ -68,26 +327,49 @@ pub struct IntercomStatus {
 }
 
 pub fn channel<T: IntercomMsg>() -> (IntercomSender<T>, IntercomReceiver<T>, IntercomStats) {
-    let (sender, receiver) = mpsc::channel(10);
+    let (high_sender, high_receiver) = mpsc::channel(INTERCOM_LANE_CAPACITY);
+    let (normal_sender, normal_receiver) = mpsc::channel(INTERCOM_LANE_CAPACITY);
 
     let sent_counter = Arc::new(AtomicU64::new(0));
     let received_counter = Arc::new(AtomicU64::new(0));
-    let stats = Arc::new(Mutex::new(Stats::new()));
+    let expired_counter = Arc::new(AtomicU64::new(0));
+    let queued_bytes = Arc::new(AtomicUsize::new(0));
+    let stats = Arc::new(AtomicStats::default());
+    let connections = Arc::new(Connections::new());
+    let (closed_sender, closed) = watch::channel(());
 
     (
         IntercomSender {
-            sender,
+            high: high_sender,
+            normal: normal_sender,
             sent_counter: Arc::clone(&sent_counter),
+            queued_bytes: Arc::clone(&queued_bytes),
+            connections: Arc::clone(&connections),
+            budget: None,
+            producer: 0,
+            closed,
+            ordered: false,
+            pending: Mutex::new(None),
         },
         IntercomReceiver {
-            receiver,
+            high: high_receiver,
+            normal: normal_receiver,
             received_counter: Arc::clone(&received_counter),
+            expired_counter: Arc::clone(&expired_counter),
+            queued_bytes: Arc::clone(&queued_bytes),
             stats: Arc::clone(&stats),
+            connections,
+            drain: None,
+            fair: None,
+            _closed: closed_sender,
         },
         IntercomStats {
             sent_counter,
             received_counter,
+            expired_counter,
+            queued_bytes,
             stats,
+            capacity: 2 * INTERCOM_LANE_CAPACITY,
         },
     )
 }
@@ -127,6 +409,13 @@ impl<T: Service> Intercom<T> {
                             tokio::time::delay_for(std::time::Duration::from_millis(100)).await;
                         }
                     }
+                    Status::Failed { .. } => {
+                        tracing::debug!(retry_attempted, "failed");
+                        return Err(WatchdogError::CannotConnectToService {
+                            service_identifier: T::SERVICE_IDENTIFIER,
+                            retry_attempted,
+                        });
+                    }
                     Status::Shutdown { .. } => {
                         tracing::debug!(retry_attempted, "shutdown");
                         if retry_attempted {
@@ -144,11 +433,81 @@ impl<T: Service> Intercom<T> {
         }
     }
 
+    /// check whether the service is currently started and can receive
+    /// messages.
+    ///
+    /// This queries the status of the service from the watchdog.
+    pub async fn is_connected(&mut self) -> bool {
+        use crate::service::Status;
+
+        match self.watchdog_query.status::<T>().await {
+            Ok(status_report) => matches!(status_report.status, Status::Started { .. }),
+            Err(_) => false,
+        }
+    }
+
+    /// wait until the service is started, allowing to hold back sending
+    /// messages until the service is ready to process them.
+    ///
+    /// Only a service that is not started yet is waited for: fails right
+    /// away if the service is unknown, has failed or if the watchdog is
+    /// gone. Once started, the intercom is connected to the service.
+    ///
+    /// This function requires the `time` driver to be enabled on the
+    /// runtime of the caller.
+    pub async fn wait_connected(&mut self, timeout: Duration) -> Result<(), WatchdogError> {
+        use crate::service::Status;
+
+        let wait = async {
+            loop {
+                match self.watchdog_query.status::<T>().await?.status {
+                    Status::Started { .. } => return self.connect().await,
+                    Status::Failed { .. } => {
+                        return Err(WatchdogError::CannotConnectToService {
+                            service_identifier: T::SERVICE_IDENTIFIER,
+                            retry_attempted: false,
+                        })
+                    }
+                    _ => tokio::time::delay_for(Duration::from_millis(50)).await,
+                }
+            }
+        };
+
+        tokio::time::timeout(timeout, wait)
+            .await
+            .unwrap_or(Err(WatchdogError::Timeout {
+                context: "service to be started",
+            }))
+    }
+
+    /// same as [`send`](#method.send) but accepts any message that can be
+    /// converted into the service's `IntercomMsg`
+    ///
+    /// see [`intercom_enum!`](../macro.intercom_enum.html).
+    pub async fn send_as<M>(&mut self, msg: M) -> Result<(), IntercomError<T::IntercomMsg>>
+    where
+        M: Into<T::IntercomMsg>,
+    {
+        self.send(msg.into()).await
+    }
+
+    /// same as [`try_send`](#method.try_send) but accepts any message that
+    /// can be converted into the service's `IntercomMsg`
+    pub fn try_send_as<M>(&mut self, msg: M) -> Result<(), TrySendError<T::IntercomMsg>>
+    where
+        M: Into<T::IntercomMsg>,
+    {
+        self.try_send(msg.into())
+    }
+
     /// try to send the message to the given service
     ///
     /// This function is non blocking version of `send` but without the `retry`
     /// attempts
-    #[tracing::instrument(skip(self), target = "intercom", level = "debug")]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip(self), target = "intercom", level = "debug")
+    )]
     pub fn try_send(&mut self, msg: T::IntercomMsg) -> Result<(), TrySendError<T::IntercomMsg>> {
         match &mut self.state {
             IntercomState::Connected { connection } => {
@@ -163,14 +522,18 @@ impl<T: Service> Intercom<T> {
     /// will attempt to reconnect if needed (if the intercom message has been closed).
     ///
     /// however, there is a 100ms delay before doing a retry. Only one retry
-    /// will be perform.
-    #[tracing::instrument(skip(self), target = "intercom", level = "debug")]
-    pub async fn send(&mut self, msg: T::IntercomMsg) -> Result<(), WatchdogError> {
+    /// will be perform. On failure the message is given back in the
+    /// [`IntercomError`](./struct.IntercomError.html).
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip(self), target = "intercom", level = "debug")
+    )]
+    pub async fn send(&mut self, msg: T::IntercomMsg) -> Result<(), IntercomError<T::IntercomMsg>> {
         let mut retry_attempted = false;
-        let mut retry = Err(msg);
+        let mut msg = msg;
 
-        while let Err(msg) = retry {
-            retry = match &mut self.state {
+        loop {
+            let sent = match &mut self.state {
                 IntercomState::Connected { connection } => {
                     tracing::trace!("sending message");
                     connection
@@ -187,36 +550,60 @@ impl<T: Service> Intercom<T> {
                     Err(msg)
                 }
             };
+            msg = match sent {
+                Ok(()) => return Ok(()),
+                Err(msg) => msg,
+            };
 
-            if retry.is_err() && retry_attempted {
+            if retry_attempted {
                 tracing::error!("cannot connect to service");
-                return Err(WatchdogError::CannotConnectToService {
-                    service_identifier: T::SERVICE_IDENTIFIER,
-                    retry_attempted,
+                return Err(IntercomError {
+                    msg,
+                    reason: WatchdogError::CannotConnectToService {
+                        service_identifier: T::SERVICE_IDENTIFIER,
+                        retry_attempted,
+                    },
                 });
             }
 
-            if retry.is_err() {
-                retry_attempted = true;
-                tracing::debug!("retrying to connect to service in 100ms");
-                tokio::time::delay_for(std::time::Duration::from_millis(100)).await;
-                self.connect().in_current_span().await?;
+            retry_attempted = true;
+            tracing::debug!("retrying to connect to service in 100ms");
+            tokio::time::delay_for(std::time::Duration::from_millis(100)).await;
+            if let Err(reason) = self.connect().in_current_span().await {
+                return Err(IntercomError { msg, reason });
             }
         }
+    }
+
+    /// connect to the service (if not already connected) and return the
+    /// underlying [`IntercomSender`]
+    ///
+    /// the sender is cheap to clone and does not hold a `WatchdogQuery`, it
+    /// suits the producers handing a sender to many tasks. However, unlike
+    /// the `Intercom`, it does not reconnect: once the service is restarted
+    /// the sending fails and a new sender needs to be obtained.
+    pub async fn into_sender(mut self) -> Result<IntercomSender<T::IntercomMsg>, WatchdogError> {
+        if !matches!(self.state, IntercomState::Connected { .. }) {
+            self.connect().await?;
+        }
 
-        Ok(())
+        match std::mem::replace(&mut self.state, IntercomState::Disconnected) {
+            IntercomState::Connected { connection } => Ok(connection),
+            _ => unreachable!("connected to the service"),
+        }
     }
 
     fn disconnect(&mut self) {
-        let span = tracing::span!(tracing::Level::DEBUG, "Intercom::disconnect");
-        let _enter = span.enter();
+        enter_span!(tracing::span!(
+            tracing::Level::DEBUG,
+            "Intercom::disconnect"
+        ));
         tracing::trace!("disconnect from the service");
         self.state = IntercomState::Disconnected;
     }
 
-    async fn connect(&mut self) -> Result<(), WatchdogError> {
-        let span = tracing::span!(tracing::Level::DEBUG, "Intercom::connect");
-        let _enter = span.enter();
+    pub(crate) async fn connect(&mut self) -> Result<(), WatchdogError> {
+        enter_span!(tracing::span!(tracing::Level::DEBUG, "Intercom::connect"));
 
         // make sure we are disconnected
         self.disconnect();
@@ -264,33 +651,259 @@ impl<T: Service> Intercom<T> {
 }
 
 impl<T> IntercomReceiver<T> {
+    /// future that resolves once every [`IntercomSender`] that has been
+    /// handed out to other services have been dropped.
+    ///
+    /// The future will not resolve before at least one sender has been
+    /// handed out, so a service waiting for its producers will not stop
+    /// while they are still starting up.
+    pub fn no_senders_signal(&self) -> impl Future<Output = ()> + Send + 'static {
+        let connections = Arc::clone(&self.connections);
+        async move { connections.no_external_senders().await }
+    }
+
+    /// number of senders connected to the service, not counting the one
+    /// owned by the `ServiceManager`
+    ///
+    /// zero while no other service connected yet, or once they are all
+    /// gone. See [`no_senders_signal`](#method.no_senders_signal) to wait
+    /// for the latter.
+    pub fn sender_count(&self) -> usize {
+        self.connections.external_senders()
+    }
+
+    /// no sender is left, not even the `ServiceManager`'s one: no new
+    /// message can be received
+    ///
+    /// the messages already queued can still be received.
+    pub fn is_closed(&self) -> bool {
+        self.connections.count.load(Ordering::SeqCst) == 0
+    }
+
+    /// poll the lanes of the intercom, the `High` priority lane first
+    fn poll_recv_entry(&mut self, cx: &mut Context) -> Poll<Option<Envelope<T>>> {
+        let high = self.high.poll_recv(cx);
+        if let Poll::Ready(Some(entry)) = high {
+            return Poll::Ready(Some(entry));
+        }
+
+        match self.poll_recv_normal(cx) {
+            Poll::Ready(Some(entry)) => Poll::Ready(Some(entry)),
+            // both lanes are closed only once all the senders are gone
+            Poll::Ready(None) if high.is_ready() => Poll::Ready(None),
+            // the queue is empty and the service is shutting down
+            _ if self.poll_drained(cx) => Poll::Ready(None),
+            _ => Poll::Pending,
+        }
+    }
+
+    /// poll the `Normal` priority lane, rotating across the producers in
+    /// fair mode
+    fn poll_recv_normal(&mut self, cx: &mut Context) -> Poll<Option<Envelope<T>>> {
+        let fair = match self.fair.as_mut() {
+            Some(fair) => fair,
+            None => return self.normal.poll_recv(cx),
+        };
+
+        let mut closed = false;
+        while fair.buffered < FAIR_BUFFER_CAPACITY {
+            match self.normal.poll_recv(cx) {
+                Poll::Ready(Some(entry)) => fair.push(entry),
+                Poll::Ready(None) => {
+                    closed = true;
+                    break;
+                }
+                Poll::Pending => break,
+            }
+        }
+
+        match fair.pop() {
+            Some(entry) => Poll::Ready(Some(entry)),
+            None if closed => Poll::Ready(None),
+            None => Poll::Pending,
+        }
+    }
+
+    /// enable the fair mode of the receiver, see
+    /// `ServiceManager::set_fair_intercom`
+    ///
+    /// the messages buffered to rotate across the producers are added to
+    /// the capacity reported by `stats`.
+    pub(crate) fn fair(&mut self, stats: &mut IntercomStats) {
+        if self.fair.is_none() {
+            stats.capacity += FAIR_BUFFER_CAPACITY;
+            self.fair = Some(Fair {
+                queues: VecDeque::new(),
+                buffered: 0,
+            });
+        }
+    }
+
+    /// in drain mode, check whether the service is shutting down. The
+    /// task is woken up on status updates.
+    fn poll_drained(&mut self, cx: &mut Context) -> bool {
+        if let Some(drain) = self.drain.as_mut() {
+            while let Poll::Ready(Some(status)) = Pin::new(&mut drain.status).poll_next(cx) {
+                drain.shutting_down = status.is_shutting_down() || status.is_stopped();
+            }
+            drain.shutting_down
+        } else {
+            false
+        }
+    }
+
+    /// enable or disable the drain mode of the receiver
+    ///
+    /// In drain mode, once the service's status is `ShuttingDown`, the
+    /// receiver yields the messages still queued and then returns `None`
+    /// instead of waiting for new messages.
+    pub(crate) fn drain_on_shutdown(&mut self, status: Option<StatusReader>) {
+        self.drain = status.map(|status| {
+            let current = status.status();
+            Drain {
+                shutting_down: current.is_shutting_down() || current.is_stopped(),
+                status,
+            }
+        });
+    }
+
     pub async fn recv(&mut self) -> Option<T> {
-        let r = self.receiver.recv().await;
+        self.recv_envelope().await.map(|envelope| envelope.msg)
+    }
+
+    /// receive the next message along with a span that _follows from_ the
+    /// span that was current when the message was sent
+    ///
+    /// Enter the span (or instrument a future with it) while processing the
+    /// message so the trace can be followed from one service to the other.
+    #[cfg(feature = "intercom-spans")]
+    pub async fn recv_with_span(&mut self) -> Option<(T, tracing::Span)> {
+        self.recv_envelope().await.map(|envelope| {
+            let span = tracing::debug_span!(target: "intercom", "intercom message");
+            span.follows_from(&envelope.span);
+            (envelope.msg, span)
+        })
+    }
+
+    async fn recv_envelope(&mut self) -> Option<Envelope<T>> {
+        poll_fn(|cx| self.poll_recv_envelope(cx)).await
+    }
+
+    /// poll the next message, skipping the expired ones
+    fn poll_recv_envelope(&mut self, cx: &mut Context) -> Poll<Option<Envelope<T>>> {
+        loop {
+            let envelope = match self.poll_recv_entry(cx) {
+                Poll::Ready(Some(envelope)) => envelope,
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            };
+            if envelope.size != 0 {
+                self.queued_bytes.fetch_sub(envelope.size, Ordering::SeqCst);
+            }
+
+            if envelope.is_expired() {
+                tracing::debug!("dropping expired message");
+                self.expired_counter.fetch_add(1, Ordering::SeqCst);
+                continue;
+            }
 
-        if let Some((instant, t)) = r {
             self.received_counter.fetch_add(1, Ordering::SeqCst);
-            let f = instant.elapsed().as_secs_f64();
+            self.stats.push(envelope.sent_at.elapsed().as_secs_f64());
+
+            return Poll::Ready(Some(envelope));
+        }
+    }
+}
+
+// the messages buffered in fair mode are never pinned
+impl<T> Unpin for IntercomReceiver<T> {}
+
+/// receive the messages with the `StreamExt` combinators, the stream ends
+/// when `recv` returns `None`
+impl<T> Stream for IntercomReceiver<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<T>> {
+        self.get_mut()
+            .poll_recv_envelope(cx)
+            .map(|envelope| envelope.map(|envelope| envelope.msg))
+    }
+}
+
+impl<T> Envelope<T> {
+    fn is_expired(&self) -> bool {
+        matches!(self.expires_at, Some(expires_at) if expires_at <= Instant::now())
+    }
 
-            {
-                let mut stats = self.stats.lock().unwrap();
-                stats.push(f);
+    /// the message that could not be sent, no longer accounted in the sent
+    /// messages nor in the queued bytes
+    fn unsent(self, sent_counter: &AtomicU64, queued_bytes: &AtomicUsize) -> T {
+        sent_counter.fetch_sub(1, Ordering::SeqCst);
+        if self.size != 0 {
+            queued_bytes.fetch_sub(self.size, Ordering::SeqCst);
+        }
+        self.msg
+    }
+}
+
+impl<T: IntercomMsg> Envelope<T> {
+    fn new(msg: T, producer: usize) -> Self {
+        let sent_at = Instant::now();
+        Self {
+            sent_at,
+            expires_at: msg.ttl().map(|ttl| sent_at + ttl),
+            producer,
+            size: msg.size_hint(),
+            #[cfg(feature = "intercom-spans")]
+            span: tracing::Span::current(),
+            msg,
+        }
+    }
+}
+
+impl<T> Fair<T> {
+    fn push(&mut self, envelope: Envelope<T>) {
+        self.buffered += 1;
+
+        let producer = envelope.producer;
+        match self.queues.iter_mut().find(|(p, _)| *p == producer) {
+            Some((_, queue)) => queue.push_back(envelope),
+            None => {
+                let mut queue = VecDeque::new();
+                queue.push_back(envelope);
+                self.queues.push_back((producer, queue));
             }
+        }
+    }
 
-            Some(t)
-        } else {
-            None
+    /// the next message of the next producer, the producer is then served
+    /// last
+    fn pop(&mut self) -> Option<Envelope<T>> {
+        let (producer, mut queue) = self.queues.pop_front()?;
+        let envelope = queue.pop_front();
+        if !queue.is_empty() {
+            self.queues.push_back((producer, queue));
         }
+
+        self.buffered -= 1;
+        envelope
     }
 }
 
 impl IntercomStats {
     pub async fn status(&self) -> IntercomStatus {
-        let stats = self.stats.lock().unwrap();
+        let stats = self.stats.snapshot();
+        let queue_depth = self.queue_depth();
 
         IntercomStatus {
             number_sent: self.sent(),
             number_received: self.received(),
+            number_expired: self.expired(),
             number_connections: self.number_connections(),
+            capacity: self.capacity,
+            queue_depth,
+            queued_bytes: self.queued_bytes(),
+            utilization: stats::utilization(queue_depth, self.capacity),
             processing_speed_mean: stats.mean(),
             processing_speed_variance: stats.variance(),
             processing_speed_standard_derivation: stats.standard_derivation(),
@@ -305,36 +918,665 @@ impl IntercomStats {
         self.sent_counter.load(Ordering::SeqCst)
     }
 
+    /// number of messages dropped because their time to live elapsed
+    pub fn expired(&self) -> u64 {
+        self.expired_counter.load(Ordering::SeqCst)
+    }
+
     pub fn number_connections(&self) -> usize {
         Arc::strong_count(&self.sent_counter)
     }
+
+    /// number of messages the intercom can hold (`0` if unbounded)
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// number of messages sent but neither received nor expired yet
+    pub fn queue_depth(&self) -> usize {
+        self.sent().saturating_sub(self.received() + self.expired()) as usize
+    }
+
+    /// approximate number of bytes of the messages queued, see
+    /// `IntercomMsg::size_hint`
+    pub fn queued_bytes(&self) -> usize {
+        self.queued_bytes.load(Ordering::SeqCst)
+    }
 }
 
 impl<T> IntercomSender<T> {
-    async fn send(&mut self, t: T) -> Result<(), SendError<T>> {
+    /// emit a `WatchdogEvent::BudgetExceeded` when more than `limit` messages
+    /// are waiting in the intercom
+    pub(crate) fn set_queue_budget(
+        &mut self,
+        service_identifier: ServiceIdentifier,
+        limit: usize,
+        stats: &IntercomStats,
+        events: broadcast::Sender<WatchdogEvent>,
+    ) {
+        self.budget = Some(Arc::new(QueueBudget {
+            service_identifier,
+            limit,
+            received_counter: Arc::clone(&stats.received_counter),
+            expired_counter: Arc::clone(&stats.expired_counter),
+            usage: Usage::default(),
+            events,
+        }));
+    }
+
+    /// send every message on the `Normal` lane whatever its priority, so
+    /// the messages of a sender are all received in send order, see
+    /// `ServiceManager::set_ordered_intercom`
+    ///
+    /// the clones made from then on are ordered too.
+    pub(crate) fn set_ordered(&mut self) {
+        self.ordered = true;
+    }
+
+    /// future that resolves once the receiving end of the intercom has
+    /// been dropped, i.e. the service stopped
+    ///
+    /// the messages sent from then on fail. Use it to stop producing as soon
+    /// as the service is gone rather than on the next failed `send`.
+    pub fn closed(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut closed = self.closed.clone();
+        async move { while closed.recv().await.is_some() {} }
+    }
+
+    /// check the queue depth against the budget, once a message has been
+    /// queued
+    fn check_budget(&self) {
+        if let Some(budget) = &self.budget {
+            let sent = self.sent_counter.load(Ordering::SeqCst);
+            let received = budget.received_counter.load(Ordering::SeqCst)
+                + budget.expired_counter.load(Ordering::SeqCst);
+            let usage = sent.saturating_sub(received) as usize;
+
+            if budget.usage.exceeds(usage, budget.limit) {
+                emit(
+                    &budget.events,
+                    WatchdogEvent::BudgetExceeded {
+                        service_identifier: budget.service_identifier,
+                        resource: BudgetResource::IntercomQueue,
+                        limit: budget.limit,
+                        usage,
+                    },
+                );
+            }
+        }
+    }
+}
+
+impl<T: IntercomMsg> IntercomSender<T> {
+    fn lane(&mut self, t: &T) -> &mut mpsc::Sender<Envelope<T>> {
+        match t.priority() {
+            Priority::High if !self.ordered => &mut self.high,
+            _ => &mut self.normal,
+        }
+    }
+
+    /// send the message, waiting for room in the intercom if needed
+    ///
+    /// fails if the service is gone: the sender does not reconnect.
+    pub async fn send(&mut self, t: T) -> Result<(), SendError<T>> {
         self.sent_counter.fetch_add(1, Ordering::SeqCst);
-        self.sender
-            .send((Instant::now(), t))
-            .await
-            .map_err(|SendError((_, t))| SendError(t))
+        let envelope = self.enqueue(t);
+        let sent = self.lane(&envelope.msg).send(envelope).await;
+        match sent {
+            Ok(()) => {
+                self.check_budget();
+                Ok(())
+            }
+            Err(SendError(envelope)) => Err(SendError(
+                envelope.unsent(&self.sent_counter, &self.queued_bytes),
+            )),
+        }
     }
 
-    fn try_send(&mut self, t: T) -> Result<(), TrySendError<T>> {
+    /// send the message if there is room in the intercom
+    pub fn try_send(&mut self, t: T) -> Result<(), TrySendError<T>> {
         self.sent_counter.fetch_add(1, Ordering::SeqCst);
-        self.sender
-            .try_send((Instant::now(), t))
-            .map_err(|err| match err {
-                TrySendError::Full((_, t)) => TrySendError::Full(t),
-                TrySendError::Closed((_, t)) => TrySendError::Closed(t),
-            })
+        let envelope = self.enqueue(t);
+        let sent = self.lane(&envelope.msg).try_send(envelope);
+        match sent {
+            Ok(()) => {
+                self.check_budget();
+                Ok(())
+            }
+            Err(TrySendError::Full(envelope)) => Err(TrySendError::Full(
+                envelope.unsent(&self.sent_counter, &self.queued_bytes),
+            )),
+            Err(TrySendError::Closed(envelope)) => Err(TrySendError::Closed(
+                envelope.unsent(&self.sent_counter, &self.queued_bytes),
+            )),
+        }
+    }
+
+    /// wrap the message, accounting its size in the queued bytes
+    fn enqueue(&self, t: T) -> Envelope<T> {
+        let envelope = Envelope::new(t, self.producer);
+        if envelope.size != 0 {
+            self.queued_bytes.fetch_add(envelope.size, Ordering::SeqCst);
+        }
+        envelope
+    }
+}
+
+impl<T: IntercomMsg> IntercomSender<T> {
+    fn pending(&mut self) -> &mut Option<T> {
+        // never locked, so never poisoned
+        self.pending.get_mut().unwrap()
+    }
+
+    /// send the message given to `Sink::start_send`, if any
+    fn poll_send_pending(&mut self, cx: &mut Context) -> Poll<Result<(), SendError<T>>> {
+        let t = match self.pending().take() {
+            Some(t) => t,
+            None => return Poll::Ready(Ok(())),
+        };
+
+        match self.lane(&t).poll_ready(cx) {
+            Poll::Pending => {
+                *self.pending() = Some(t);
+                Poll::Pending
+            }
+            Poll::Ready(Err(_)) => Poll::Ready(Err(SendError(t))),
+            Poll::Ready(Ok(())) => match self.try_send(t) {
+                Ok(()) => Poll::Ready(Ok(())),
+                Err(TrySendError::Closed(t)) => Poll::Ready(Err(SendError(t))),
+                Err(TrySendError::Full(t)) => {
+                    // not expected once the lane is ready, try again later
+                    *self.pending() = Some(t);
+                    cx.waker().wake_by_ref();
+                    Poll::Pending
+                }
+            },
+        }
+    }
+}
+
+// the pending message is never pinned
+impl<T> Unpin for IntercomSender<T> {}
+
+/// send the messages of a `Stream` with `StreamExt::forward` or
+/// `SinkExt::send_all`
+///
+/// a message is only sent once the sink is flushed or polled ready again:
+/// a message given to `start_send` is lost if the sender is dropped before
+/// that. On failure the message is given back in the `SendError`.
+impl<T: IntercomMsg> Sink<T> for IntercomSender<T> {
+    type Error = SendError<T>;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_send_pending(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, t: T) -> Result<(), Self::Error> {
+        let pending = self.get_mut().pending();
+        assert!(
+            pending.is_none(),
+            "IntercomSender::start_send called before poll_ready"
+        );
+        *pending = Some(t);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_send_pending(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_send_pending(cx)
+    }
+}
+
+impl<M: IntercomMsg> IntercomSender<Arc<M>> {
+    /// send the shared message, without cloning its content
+    pub async fn send_shared(&mut self, msg: &Arc<M>) -> Result<(), SendError<Arc<M>>> {
+        self.send(Arc::clone(msg)).await
+    }
+
+    /// send the same shared message to every one of `senders`, waiting for
+    /// room in their intercoms one after the other
+    ///
+    /// the results are in the order of the `senders`: a service gone does
+    /// not prevent the others from receiving the message.
+    pub async fn broadcast_shared(
+        senders: &mut [Self],
+        msg: &Arc<M>,
+    ) -> Vec<Result<(), SendError<Arc<M>>>> {
+        let mut results = Vec::with_capacity(senders.len());
+        for sender in senders {
+            results.push(sender.send_shared(msg).await);
+        }
+        results
     }
 }
 
 impl<T> Clone for IntercomSender<T> {
     fn clone(&self) -> Self {
+        self.connections.connect();
         Self {
-            sender: self.sender.clone(),
+            high: self.high.clone(),
+            normal: self.normal.clone(),
             sent_counter: Arc::clone(&self.sent_counter),
+            queued_bytes: Arc::clone(&self.queued_bytes),
+            connections: Arc::clone(&self.connections),
+            budget: self.budget.clone(),
+            producer: self
+                .connections
+                .next_producer
+                .fetch_add(1, Ordering::SeqCst),
+            closed: self.closed.clone(),
+            ordered: self.ordered,
+            pending: Mutex::new(None),
+        }
+    }
+}
+
+impl<T> Drop for IntercomSender<T> {
+    fn drop(&mut self) {
+        self.connections.disconnect();
+    }
+}
+
+impl Connections {
+    fn new() -> Self {
+        let (updated, update_reader) = watch::channel(());
+        Self {
+            count: AtomicUsize::new(1),
+            ever_connected: AtomicBool::new(false),
+            // the first producer is the `ServiceManager`'s sender
+            next_producer: AtomicUsize::new(1),
+            updated,
+            update_reader,
         }
     }
+
+    fn connect(&self) {
+        self.count.fetch_add(1, Ordering::SeqCst);
+        self.ever_connected.store(true, Ordering::SeqCst);
+    }
+
+    fn disconnect(&self) {
+        self.count.fetch_sub(1, Ordering::SeqCst);
+        if self.updated.broadcast(()).is_err() {
+            // `Connections` owns a receiver so the broadcast cannot fail
+        }
+    }
+
+    /// number of senders alive, not counting the one owned by the
+    /// `ServiceManager`
+    fn external_senders(&self) -> usize {
+        self.count.load(Ordering::SeqCst).saturating_sub(1)
+    }
+
+    async fn no_external_senders(&self) {
+        let mut update_reader = self.update_reader.clone();
+        loop {
+            if self.ever_connected.load(Ordering::SeqCst) && self.external_senders() == 0 {
+                return;
+            }
+
+            if update_reader.recv().await.is_none() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::future::FutureExt as _;
+
+    #[test]
+    fn no_senders_signal_waits_for_a_first_connection() {
+        let (_sender, receiver, _) = channel::<NoIntercom>();
+
+        assert!(receiver.no_senders_signal().now_or_never().is_none());
+    }
+
+    #[test]
+    fn no_senders_signal_after_last_connection_dropped() {
+        let (sender, receiver, _) = channel::<NoIntercom>();
+
+        let first = sender.clone();
+        let second = first.clone();
+        assert!(receiver.no_senders_signal().now_or_never().is_none());
+
+        std::mem::drop(first);
+        assert!(receiver.no_senders_signal().now_or_never().is_none());
+
+        std::mem::drop(second);
+        assert!(receiver.no_senders_signal().now_or_never().is_some());
+    }
+
+    #[test]
+    fn sender_count() {
+        let (sender, receiver, _) = channel::<NoIntercom>();
+        assert_eq!(receiver.sender_count(), 0);
+
+        let connection = sender.clone();
+        assert_eq!(receiver.sender_count(), 1);
+        std::mem::drop(connection);
+        assert_eq!(receiver.sender_count(), 0);
+        assert!(!receiver.is_closed());
+
+        std::mem::drop(sender);
+        assert!(receiver.is_closed());
+    }
+
+    #[test]
+    fn closed_once_receiver_dropped() {
+        let (sender, receiver, _) = channel::<NoIntercom>();
+        let connection = sender.clone();
+        assert!(sender.closed().now_or_never().is_none());
+
+        std::mem::drop(receiver);
+        assert!(sender.closed().now_or_never().is_some());
+        assert!(connection.closed().now_or_never().is_some());
+    }
+
+    #[derive(Debug, PartialEq, Eq)]
+    enum Job {
+        Work(usize),
+        Drain,
+    }
+
+    impl IntercomMsg for Job {
+        fn priority(&self) -> Priority {
+            match self {
+                Self::Work(_) => Priority::Normal,
+                Self::Drain => Priority::High,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn high_priority_received_first() {
+        let (mut sender, mut receiver, _) = channel::<Job>();
+
+        for i in 0..5 {
+            sender.send(Job::Work(i)).await.unwrap();
+        }
+        sender.send(Job::Drain).await.unwrap();
+
+        assert_eq!(receiver.recv().await, Some(Job::Drain));
+        for i in 0..5 {
+            assert_eq!(receiver.recv().await, Some(Job::Work(i)));
+        }
+    }
+
+    #[tokio::test]
+    async fn fair_across_producers() {
+        let (sender, mut receiver, mut stats) = channel::<Job>();
+        receiver.fair(&mut stats);
+        let mut chatty = sender.clone();
+        let mut slow = sender.clone();
+
+        for i in 0..3 {
+            chatty.send(Job::Work(i)).await.unwrap();
+        }
+        slow.send(Job::Work(10)).await.unwrap();
+
+        assert_eq!(receiver.recv().await, Some(Job::Work(0)));
+        assert_eq!(receiver.recv().await, Some(Job::Work(10)));
+        assert_eq!(receiver.recv().await, Some(Job::Work(1)));
+        assert_eq!(receiver.recv().await, Some(Job::Work(2)));
+    }
+
+    #[tokio::test]
+    async fn fair_buffer_counted_in_stats() {
+        let (mut sender, mut receiver, mut stats) = channel::<Job>();
+        receiver.fair(&mut stats);
+
+        for i in 0..4 {
+            sender.send(Job::Work(i)).await.unwrap();
+        }
+        assert_eq!(receiver.recv().await, Some(Job::Work(0)));
+
+        // the 3 others are buffered, out of the lane
+        assert_eq!(receiver.fair.as_ref().unwrap().buffered, 3);
+        assert_eq!(stats.queue_depth(), 3);
+        assert_eq!(
+            stats.capacity(),
+            2 * INTERCOM_LANE_CAPACITY + FAIR_BUFFER_CAPACITY
+        );
+    }
+
+    #[tokio::test]
+    async fn send_order_preserved_per_producer() {
+        let (sender, mut receiver, mut stats) = channel::<Job>();
+        receiver.fair(&mut stats);
+        let mut first = sender.clone();
+        let mut second = sender.clone();
+
+        for i in 0..4 {
+            first.send(Job::Work(i)).await.unwrap();
+            second.send(Job::Work(10 + i)).await.unwrap();
+        }
+
+        let mut received = Vec::new();
+        for _ in 0..8 {
+            received.push(receiver.recv().await.unwrap());
+        }
+        let from = |producer: std::ops::Range<usize>| {
+            received
+                .iter()
+                .filter_map(|job| match job {
+                    Job::Work(i) if producer.contains(i) => Some(*i),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(from(0..10), vec![0, 1, 2, 3]);
+        assert_eq!(from(10..20), vec![10, 11, 12, 13]);
+    }
+
+    #[tokio::test]
+    async fn ordered_ignores_priority() {
+        let (mut sender, mut receiver, _) = channel::<Job>();
+        sender.set_ordered();
+        let mut clone = sender.clone();
+
+        sender.send(Job::Work(0)).await.unwrap();
+        sender.send(Job::Drain).await.unwrap();
+        clone.send(Job::Work(1)).await.unwrap();
+        clone.try_send(Job::Drain).unwrap();
+
+        assert_eq!(receiver.recv().await, Some(Job::Work(0)));
+        assert_eq!(receiver.recv().await, Some(Job::Drain));
+        assert_eq!(receiver.recv().await, Some(Job::Work(1)));
+        assert_eq!(receiver.recv().await, Some(Job::Drain));
+    }
+
+    #[tokio::test]
+    async fn closed_once_all_senders_dropped() {
+        let (mut sender, mut receiver, _) = channel::<Job>();
+
+        sender.send(Job::Work(0)).await.unwrap();
+        sender.send(Job::Drain).await.unwrap();
+        std::mem::drop(sender);
+
+        assert_eq!(receiver.recv().await, Some(Job::Drain));
+        assert_eq!(receiver.recv().await, Some(Job::Work(0)));
+        assert_eq!(receiver.recv().await, None);
+    }
+
+    #[derive(Debug)]
+    struct Blob(Vec<u8>);
+
+    impl IntercomMsg for Blob {
+        fn size_hint(&self) -> usize {
+            self.0.len()
+        }
+    }
+
+    #[tokio::test]
+    async fn queued_bytes() {
+        let (mut sender, mut receiver, stats) = channel::<Blob>();
+
+        sender.send(Blob(vec![0; 100])).await.unwrap();
+        sender.try_send(Blob(vec![0; 20])).unwrap();
+        assert_eq!(stats.queued_bytes(), 120);
+
+        receiver.recv().await.unwrap();
+        assert_eq!(stats.queued_bytes(), 20);
+        assert_eq!(stats.status().await.queued_bytes, 20);
+
+        std::mem::drop(receiver);
+        assert!(sender.send(Blob(vec![0; 50])).await.is_err());
+        assert_eq!(stats.queued_bytes(), 20);
+    }
+
+    #[tokio::test]
+    async fn sink() {
+        use futures_util::{sink::SinkExt, stream::StreamExt as _};
+
+        let (mut sender, mut receiver, stats) = channel::<Job>();
+
+        let jobs = futures_util::stream::iter(vec![Job::Work(0), Job::Work(1)]);
+        let mut forward = jobs.map(Ok).forward(&mut sender);
+        assert!((&mut forward).now_or_never().is_some());
+        assert_eq!(stats.sent(), 2);
+
+        assert_eq!(receiver.recv().await, Some(Job::Work(0)));
+        assert_eq!(receiver.recv().await, Some(Job::Work(1)));
+
+        std::mem::drop(receiver);
+        let SendError(job) = SinkExt::send(&mut sender, Job::Drain).await.unwrap_err();
+        assert_eq!(job, Job::Drain);
+    }
+
+    /// the messages waiting for room in the lane are counted once sent
+    #[tokio::test]
+    async fn sink_counts_once() {
+        use futures_util::stream::StreamExt as _;
+
+        let (mut sender, mut receiver, stats) = channel::<Job>();
+        let count = 3 * INTERCOM_LANE_CAPACITY;
+
+        let received = tokio::spawn(async move {
+            for i in 0..count {
+                assert_eq!(receiver.recv().await, Some(Job::Work(i)));
+            }
+            receiver
+        });
+        let jobs = futures_util::stream::iter((0..count).map(Job::Work));
+        jobs.map(Ok).forward(&mut sender).await.unwrap();
+        let _receiver = received.await.unwrap();
+
+        assert_eq!(stats.sent(), count as u64);
+        assert_eq!(stats.received(), count as u64);
+        assert_eq!(stats.status().await.number_sent, count as u64);
+    }
+
+    #[tokio::test]
+    async fn stream() {
+        use futures_util::stream::StreamExt as _;
+
+        let (mut sender, receiver, _) = channel::<Job>();
+
+        for i in 0..3 {
+            sender.send(Job::Work(i)).await.unwrap();
+        }
+        sender.send(Job::Drain).await.unwrap();
+        std::mem::drop(sender);
+
+        let received: Vec<_> = receiver.chunks(3).collect().await;
+        assert_eq!(
+            received,
+            vec![
+                vec![Job::Drain, Job::Work(0), Job::Work(1)],
+                vec![Job::Work(2)]
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn broadcast_shared() {
+        let (first, mut first_receiver, _) = channel::<Arc<Job>>();
+        let (second, second_receiver, _) = channel::<Arc<Job>>();
+        let (third, mut third_receiver, _) = channel::<Arc<Job>>();
+        std::mem::drop(second_receiver);
+        let mut senders = vec![first, second, third];
+
+        let job = Arc::new(Job::Drain);
+        let results = IntercomSender::broadcast_shared(&mut senders, &job).await;
+        assert_eq!(
+            results.iter().map(Result::is_ok).collect::<Vec<_>>(),
+            vec![true, false, true]
+        );
+
+        let received = first_receiver.recv().await.unwrap();
+        assert!(Arc::ptr_eq(&received, &job));
+        assert!(Arc::ptr_eq(&third_receiver.recv().await.unwrap(), &job));
+    }
+
+    #[test]
+    fn no_senders_signal_not_triggered_by_restart() {
+        let (previous_sender, _previous_receiver, _) = channel::<NoIntercom>();
+        let previous_connection = previous_sender.clone();
+
+        // the `ServiceManager` replaces its sender when the service restarts
+        let (sender, receiver, _) = channel::<NoIntercom>();
+        std::mem::drop(previous_sender);
+        std::mem::drop(previous_connection);
+        assert!(receiver.no_senders_signal().now_or_never().is_none());
+
+        let connection = sender.clone();
+        assert!(receiver.no_senders_signal().now_or_never().is_none());
+        std::mem::drop(connection);
+        assert!(receiver.no_senders_signal().now_or_never().is_some());
+    }
+
+    /// records the names of the spans linked with `follows_from`
+    #[cfg(feature = "intercom-spans")]
+    #[derive(Clone, Default)]
+    struct FollowsFrom(Arc<std::sync::Mutex<Vec<(&'static str, &'static str)>>>);
+
+    #[cfg(feature = "intercom-spans")]
+    impl<S> tracing_subscriber::Layer<S> for FollowsFrom
+    where
+        S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+    {
+        fn on_follows_from(
+            &self,
+            span: &tracing::span::Id,
+            follows: &tracing::span::Id,
+            ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            if let (Some(span), Some(follows)) = (ctx.span(span), ctx.span(follows)) {
+                self.0.lock().unwrap().push((span.name(), follows.name()));
+            }
+        }
+    }
+
+    #[cfg(feature = "intercom-spans")]
+    #[tokio::test]
+    async fn recv_with_span() {
+        use tracing_subscriber::layer::SubscriberExt as _;
+
+        let follows_from = FollowsFrom::default();
+        let subscriber = tracing_subscriber::Registry::default().with(follows_from.clone());
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let (mut sender, mut receiver, _) = channel::<Job>();
+
+        let sender_span = tracing::info_span!("sender");
+        sender
+            .send(Job::Work(0))
+            .instrument(sender_span)
+            .await
+            .unwrap();
+
+        let (job, span) = receiver.recv_with_span().await.unwrap();
+        assert_eq!(job, Job::Work(0));
+        assert_eq!(span.metadata().unwrap().name(), "intercom message");
+        assert_eq!(
+            *follows_from.0.lock().unwrap(),
+            vec![("intercom message", "sender")]
+        );
+    }
 }
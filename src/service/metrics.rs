@@ -0,0 +1,106 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// the user defined metrics of a service, see `ServiceState::metrics`
+///
+/// the metrics are kept by the `ServiceManager` across the restarts of the
+/// service and reported in `StatusReport::custom`.
+#[derive(Debug, Clone, Default)]
+pub struct ServiceMetrics {
+    metrics: Arc<Mutex<HashMap<&'static str, Arc<AtomicI64>>>>,
+}
+
+/// a metric that only goes up, e.g. the number of messages processed
+#[derive(Debug, Clone)]
+pub struct Counter(Arc<AtomicI64>);
+
+/// a metric that can go up and down, e.g. the size of a cache
+#[derive(Debug, Clone)]
+pub struct Gauge(Arc<AtomicI64>);
+
+impl ServiceMetrics {
+    fn metric(&self, name: &'static str) -> Arc<AtomicI64> {
+        let mut metrics = self.metrics.lock().unwrap();
+        Arc::clone(metrics.entry(name).or_default())
+    }
+
+    /// get the counter `name`, registering it if needed
+    ///
+    /// keep the returned handle rather than calling this on the hot path.
+    pub fn counter(&self, name: &'static str) -> Counter {
+        Counter(self.metric(name))
+    }
+
+    /// get the gauge `name`, registering it if needed
+    ///
+    /// keep the returned handle rather than calling this on the hot path.
+    pub fn gauge(&self, name: &'static str) -> Gauge {
+        Gauge(self.metric(name))
+    }
+
+    /// the current value of every metrics
+    pub fn snapshot(&self) -> HashMap<&'static str, i64> {
+        self.metrics
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, value)| (*name, value.load(Ordering::Relaxed)))
+            .collect()
+    }
+}
+
+impl Counter {
+    pub fn increment(&self) {
+        self.add(1)
+    }
+
+    pub fn add(&self, value: u32) {
+        self.0.fetch_add(i64::from(value), Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl Gauge {
+    pub fn set(&self, value: i64) {
+        self.0.store(value, Ordering::Relaxed)
+    }
+
+    pub fn add(&self, value: i64) {
+        self.0.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_by_name() {
+        let metrics = ServiceMetrics::default();
+        let indexed = metrics.counter("records_indexed");
+        indexed.increment();
+        metrics.counter("records_indexed").add(2);
+
+        let cache = metrics.gauge("cache_size");
+        cache.set(10);
+        cache.add(-4);
+
+        assert_eq!(indexed.get(), 3);
+        let snapshot = metrics.clone().snapshot();
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(snapshot["records_indexed"], 3);
+        assert_eq!(snapshot["cache_size"], 6);
+    }
+}
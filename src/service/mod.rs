@@ -1,38 +1,119 @@
+mod admin;
+mod budget;
+mod cancellation;
+mod cleanup;
 mod control;
+mod crash_loop;
+mod finalizer;
 mod intercom;
+mod metrics;
+mod state_bag;
 mod stats;
 mod status;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 
+use self::{
+    admin::AdminSlot,
+    budget::{TaskLimit, Tasks},
+    cleanup::Cleanups,
+    crash_loop::Restarts,
+    finalizer::Finalizer,
+    state_bag::StateBag,
+    status::Uptime,
+};
 pub use self::{
-    control::{Control, ControlReader, Controller},
+    admin::{AdminRequest, ServiceAdmin},
+    budget::{BudgetResource, ResourceBudget},
+    cancellation::CancellationToken,
+    control::{Control, ControlReader, Controller, ShutdownReason},
+    crash_loop::CrashLoop,
+    finalizer::FINALIZE_TIMEOUT,
     intercom::{
-        Intercom, IntercomMsg, IntercomReceiver, IntercomSender, IntercomStats, IntercomStatus,
-        NoIntercom,
+        Intercom, IntercomError, IntercomMsg, IntercomReceiver, IntercomSender, IntercomStats,
+        IntercomStatus, NoIntercom, Priority,
     },
+    metrics::{Counter, Gauge, ServiceMetrics},
     stats::Stats,
-    status::{Status, StatusReader, StatusUpdater},
+    status::{Status, StatusReader, StatusUpdater, STATUS_HISTORY_CAPACITY},
+};
+use crate::{
+    runtime::Runtime,
+    trace::Instrument as _,
+    watchdog::{emit, WatchdogError, WatchdogEvent, WatchdogQuery},
 };
-use crate::{runtime::Runtime, watchdog::WatchdogQuery};
 use async_trait::async_trait;
 use futures_util::future::abortable;
-use std::future::Future;
+use std::{
+    any::Any,
+    collections::HashMap,
+    fmt,
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
 use thiserror::Error;
-use tokio::{runtime::Handle, task::JoinHandle};
-use tracing_futures::Instrument as _;
+use tokio::{
+    runtime::Handle,
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+    time::Instant,
+};
 
 pub type ServiceIdentifier = &'static str;
 
+/// error returned by `Service::try_prepare`
+pub type PrepareError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+/// default time given to a service to drain its intercom queue
+/// once it has been asked to shutdown
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// number of calls to `ServiceState::maybe_yield` between two actual
+/// yields to the scheduler
+pub const MAYBE_YIELD_INTERVAL: u32 = 64;
+
 #[async_trait]
 pub trait Service: Send + Sized + 'static {
     const SERVICE_IDENTIFIER: ServiceIdentifier;
 
     type IntercomMsg: IntercomMsg;
 
+    /// prepare the service from its `ServiceState`
+    ///
+    /// it is called from the watchdog (through `try_prepare`), within the
+    /// context of the service's runtime: the timers and the IO resources
+    /// (e.g. a `TcpListener`) can be created here if the runtime has the
+    /// matching driver.
     fn prepare(service_state: ServiceState<Self>) -> Self;
 
+    /// fallible version of `prepare`, the one called by the watchdog. The
+    /// default implementation calls `prepare` and never fails.
+    ///
+    /// On error the service is not started, its status becomes `Failed`
+    /// and starting the service returns `ServiceError::CannotPrepare`. A
+    /// service implementing it never has its `prepare` called by the
+    /// watchdog.
+    fn try_prepare(service_state: ServiceState<Self>) -> Result<Self, PrepareError> {
+        Ok(Self::prepare(service_state))
+    }
+
+    /// asynchronous initialization of the service, called before `start`
+    ///
+    /// The service is reported `Started` only once `init` returned. If the
+    /// service panics while initializing its status will be `Failed`.
+    async fn init(&mut self) {}
+
     async fn start(self);
 }
 
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` does not manage a service",
+    label = "field must be `ServiceManager<T>` or marked `#[runtime(skip)]`"
+)]
 pub trait ManageService {
     const SERVICE_IDENTIFIER: ServiceIdentifier;
 
@@ -45,18 +126,183 @@ impl<T: Service> ManageService for ServiceManager<T> {
     type IntercomMsg = T::IntercomMsg;
 }
 
-#[derive(Clone, Debug, Error, PartialEq, Eq)]
+/// object safe counterpart of [`ManageService`], to manage a service
+/// registered in a running watchdog (see [`WatchdogQuery::register`])
+///
+/// `Organix::services_dyn` lists the services of the app as
+/// `ManageServiceDyn`, to write generic tooling once for all the services.
+/// Once the app runs in a watchdog, `WatchdogQuery::status_all` and
+/// `WatchdogQuery::stop_by_identifier` give the same view.
+///
+/// [`ManageService`]: ./trait.ManageService.html
+/// [`WatchdogQuery::register`]: ../struct.WatchdogQuery.html#method.register
+#[async_trait]
+pub trait ManageServiceDyn: Send + Sync {
+    fn service_identifier(&self) -> ServiceIdentifier;
+
+    fn start(&mut self, watchdog_query: WatchdogQuery) -> Result<(), ServiceError>;
+
+    fn stop(&mut self, reason: ShutdownReason);
+
+    fn kill(&mut self);
+
+    async fn status(&self) -> StatusReport;
+
+    fn current_status(&self) -> Status;
+
+    fn has_ever_started(&self) -> bool;
+
+    fn status_reader(&self) -> StatusReader;
+
+    /// the `IntercomSender` of the service, as expected by
+    /// `WatchdogQuery::intercom`
+    fn intercom(&self) -> Box<dyn Any + Send + 'static>;
+
+    /// see `ServiceManager::admin`
+    fn admin(&self) -> Result<Box<dyn Any + Send + 'static>, WatchdogError>;
+}
+
+#[async_trait]
+impl<T> ManageServiceDyn for ServiceManager<T>
+where
+    T: Service,
+    T::IntercomMsg: Send,
+{
+    fn service_identifier(&self) -> ServiceIdentifier {
+        self.identifier
+    }
+
+    fn start(&mut self, watchdog_query: WatchdogQuery) -> Result<(), ServiceError> {
+        self.runtime(watchdog_query).and_then(ServiceRuntime::start)
+    }
+
+    fn stop(&mut self, reason: ShutdownReason) {
+        self.shutdown_with_reason(reason)
+    }
+
+    fn kill(&mut self) {
+        ServiceManager::force_kill(self)
+    }
+
+    async fn status(&self) -> StatusReport {
+        ServiceManager::status(self).await
+    }
+
+    fn current_status(&self) -> Status {
+        ServiceManager::current_status(self)
+    }
+
+    fn has_ever_started(&self) -> bool {
+        ServiceManager::has_ever_started(self)
+    }
+
+    fn status_reader(&self) -> StatusReader {
+        ServiceManager::status_reader(self)
+    }
+
+    fn intercom(&self) -> Box<dyn Any + Send + 'static> {
+        Box::new(ServiceManager::intercom(self))
+    }
+
+    fn admin(&self) -> Result<Box<dyn Any + Send + 'static>, WatchdogError> {
+        ServiceManager::admin(self)
+    }
+}
+
+#[derive(Clone, Debug, Error)]
 pub enum ServiceError {
     #[error("Service cannot be started because status is: {status}")]
     CannotStart { status: Status },
+
+    #[error("Service cannot be prepared: {source}")]
+    CannotPrepare {
+        #[source]
+        source: Arc<dyn std::error::Error + Send + Sync + 'static>,
+    },
+
+    #[error("Service restarted {restarts} times within {window:?}, it is crash looping")]
+    CrashLooping { restarts: usize, window: Duration },
+}
+
+/// the errors given back by `Service::try_prepare` are compared by their
+/// message
+impl PartialEq for ServiceError {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::CannotStart { status }, Self::CannotStart { status: other }) => status == other,
+            (Self::CannotPrepare { source }, Self::CannotPrepare { source: other }) => {
+                source.to_string() == other.to_string()
+            }
+            (
+                Self::CrashLooping { restarts, window },
+                Self::CrashLooping {
+                    restarts: other_restarts,
+                    window: other_window,
+                },
+            ) => restarts == other_restarts && window == other_window,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for ServiceError {}
+
+/// how the task of a service failed, see `WatchdogBuilder::on_service_error`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ServiceErrorInfo {
+    /// the service panicked with the given message
+    Panicked { message: String },
+    /// the task of the service could not be joined
+    JoinFailed { reason: String },
+}
+
+/// the callback given to `WatchdogBuilder::on_service_error`
+#[derive(Clone)]
+pub(crate) struct OnServiceError(Arc<dyn Fn(ServiceIdentifier, ServiceErrorInfo) + Send + Sync>);
+
+impl OnServiceError {
+    pub(crate) fn new<F>(callback: F) -> Self
+    where
+        F: Fn(ServiceIdentifier, ServiceErrorInfo) + Send + Sync + 'static,
+    {
+        Self(Arc::new(callback))
+    }
+
+    pub(crate) fn call(&self, service_identifier: ServiceIdentifier, info: ServiceErrorInfo) {
+        (self.0)(service_identifier, info)
+    }
+}
+
+impl fmt::Debug for OnServiceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("OnServiceError")
+    }
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct StatusReport {
     pub identifier: ServiceIdentifier,
     pub status: Status,
     pub intercom: IntercomStatus,
+    /// number of times the service has been asked to start, whether it
+    /// managed to reach `Started` or not
     pub started: u64,
+    /// the service reached `Started` at least once since the app started
+    pub has_ever_started: bool,
+    /// time since the service last reached `Started`, zero if the service
+    /// is not running
+    pub uptime: Duration,
+    /// the message of the latest panic of the service, kept across restarts
+    pub last_panic: Option<String>,
+    /// the metrics defined by the service, see `ServiceState::metrics`
+    pub custom: HashMap<&'static str, i64>,
+    /// number of tasks spawned with `ServiceState::spawn_limited` that are
+    /// still running
+    pub concurrent_tasks: usize,
+    /// the latest status transitions, oldest first, see
+    /// `StatusReader::history`
+    pub history: Vec<Status>,
 }
 
 pub struct ServiceManager<T: Service> {
@@ -69,6 +315,21 @@ pub struct ServiceManager<T: Service> {
     status: StatusReader,
     controller: Controller,
     runtime: Handle,
+    io_driver: bool,
+    time_driver: bool,
+    budget: ResourceBudget,
+    task_limit: Option<TaskLimit>,
+    fair_intercom: bool,
+    finalize_on_abort: Option<Duration>,
+    ordered_intercom: bool,
+    crash_loop: Option<CrashLoop>,
+    restarts: Restarts,
+    uptime: Uptime,
+    ever_started: Arc<AtomicBool>,
+    persistent: StateBag,
+    metrics: ServiceMetrics,
+    last_panic: Arc<Mutex<Option<String>>>,
+    admin: AdminSlot,
 }
 
 /// not to mistake for `tokio`'s runtime. This is the object that
@@ -80,6 +341,17 @@ pub struct ServiceRuntime<T: Service> {
 
     status: StatusUpdater,
     control: ControlReader,
+    drain_timeout: Arc<Mutex<Option<Duration>>>,
+    cancellation: CancellationToken,
+    uptime: Uptime,
+    ever_started: Arc<AtomicBool>,
+    persistent: StateBag,
+    last_panic: Arc<Mutex<Option<String>>>,
+    cleanups: Cleanups,
+    restart: Arc<AtomicBool>,
+    crash_loop: Option<(CrashLoop, Restarts)>,
+    finalizer: Finalizer,
+    finalize_on_abort: Option<Duration>,
 }
 
 /// this is the object that every services has access to
@@ -93,6 +365,23 @@ pub struct ServiceState<T: Service> {
     intercom_receiver: IntercomReceiver<T::IntercomMsg>,
     watchdog_query: WatchdogQuery,
     status: StatusReader,
+    io_driver: bool,
+    time_driver: bool,
+    drain_timeout: Arc<Mutex<Option<Duration>>>,
+    budget: ResourceBudget,
+    tasks: Arc<Tasks>,
+    task_limit: Option<TaskLimit>,
+    cancellation: CancellationToken,
+    control: ControlReader,
+    uptime: Uptime,
+    persistent: StateBag,
+    metrics: ServiceMetrics,
+    yield_budget: u32,
+    cleanups: Cleanups,
+    restart: Arc<AtomicBool>,
+    admin: AdminSlot,
+    finalizer: Finalizer,
+    finalize_on_abort: Option<Duration>,
 }
 
 impl<T: Service> ServiceState<T> {
@@ -110,6 +399,20 @@ impl<T: Service> ServiceState<T> {
         self.watchdog_query.intercom::<O>()
     }
 
+    /// run `f` with a connected [`Intercom`] to the service `O`, see
+    /// [`WatchdogQuery::with_service`]
+    ///
+    /// [`Intercom`]: ./struct.Intercom.html
+    /// [`WatchdogQuery::with_service`]: ../struct.WatchdogQuery.html#method.with_service
+    pub async fn with_service<O, F, Fut, R>(&self, f: F) -> Result<R, WatchdogError>
+    where
+        O: Service,
+        F: FnOnce(Intercom<O>) -> Fut,
+        Fut: Future<Output = Result<R, WatchdogError>>,
+    {
+        self.watchdog_query.with_service::<O, F, Fut, R>(f).await
+    }
+
     /// access the `WatchdogQuery` allowing raw command access to all watchdog
     /// commands.
     pub fn watchdog_controller(&self) -> &WatchdogQuery {
@@ -123,6 +426,169 @@ impl<T: Service> ServiceState<T> {
         &mut self.intercom_receiver
     }
 
+    /// process the intercom messages with `handler` until the service
+    /// is asked to shutdown
+    ///
+    /// the loop stops as soon as the service is `ShuttingDown`, or, in
+    /// drain mode (see [`drain_on_shutdown`]), once the messages already
+    /// queued have been handled. It also stops if the intercom is closed.
+    /// A message being handled is always handled to completion.
+    ///
+    /// [`drain_on_shutdown`]: #method.drain_on_shutdown
+    pub async fn run_loop<F, Fut>(&mut self, mut handler: F)
+    where
+        F: FnMut(T::IntercomMsg) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let draining = self.drain_timeout.lock().unwrap().is_some();
+        let mut status = self.status.clone();
+
+        loop {
+            if !draining && status.status().is_shutting_down() {
+                break;
+            }
+            self.maybe_yield().await;
+
+            tokio::select! {
+                msg = self.intercom_receiver.recv() => match msg {
+                    Some(msg) => handler(msg).await,
+                    None => break,
+                },
+                _ = status.updated(), if !draining => {}
+            }
+        }
+    }
+
+    /// yield to the scheduler once every [`MAYBE_YIELD_INTERVAL`] calls
+    ///
+    /// a service looping without awaiting anything never gives the other
+    /// services of its runtime a chance to run. Call this in the hot loops
+    /// of CPU bound services, especially on a `#[runtime(shared)]` runtime.
+    /// [`run_loop`] already calls it for every messages.
+    ///
+    /// [`MAYBE_YIELD_INTERVAL`]: ./constant.MAYBE_YIELD_INTERVAL.html
+    /// [`run_loop`]: #method.run_loop
+    pub async fn maybe_yield(&mut self) {
+        self.yield_budget += 1;
+        if self.yield_budget < MAYBE_YIELD_INTERVAL {
+            return;
+        }
+        self.yield_budget = 0;
+        tokio::task::yield_now().await
+    }
+
+    /// future that resolves once every other services have dropped
+    /// their connection to this service.
+    ///
+    /// This allows a service at the end of a pipeline to finish
+    /// cleanly once its upstream services are gone instead of waiting
+    /// for messages that will never come.
+    ///
+    /// The future only resolves after at least one connection has been
+    /// opened to this service since it (re)started.
+    pub fn no_senders_signal(&self) -> impl Future<Output = ()> + Send + 'static {
+        self.intercom_receiver.no_senders_signal()
+    }
+
+    /// future that resolves once the whole watchdog is shutting down
+    ///
+    /// The watchdog signals it before asking the services to shutdown (or
+    /// before killing them), allowing a service to stop accepting new
+    /// external work ahead of its own shutdown.
+    pub fn watchdog_shutdown_signal(&self) -> impl Future<Output = ()> + Send + 'static {
+        self.watchdog_query.shutdown_signal()
+    }
+
+    /// drain the intercom queue when the service is asked to shutdown
+    ///
+    /// Once the service is `ShuttingDown`, [`IntercomReceiver::recv`] keeps
+    /// yielding the messages already queued and returns `None` when the
+    /// queue is empty. The service is aborted if it did not finish within
+    /// [`DEFAULT_DRAIN_TIMEOUT`]. When the whole watchdog is shutdown, the
+    /// service is only given this time with
+    /// `WatchdogBuilder::total_shutdown_timeout`.
+    ///
+    /// [`IntercomReceiver::recv`]: ./struct.IntercomReceiver.html#method.recv
+    /// [`DEFAULT_DRAIN_TIMEOUT`]: ./constant.DEFAULT_DRAIN_TIMEOUT.html
+    pub fn drain_on_shutdown(&mut self, drain: bool) {
+        if drain {
+            self.drain_on_shutdown_with_timeout(DEFAULT_DRAIN_TIMEOUT)
+        } else {
+            *self.drain_timeout.lock().unwrap() = None;
+            self.intercom_receiver.drain_on_shutdown(None);
+        }
+    }
+
+    /// same as [`drain_on_shutdown`] but with a custom timeout
+    ///
+    /// [`drain_on_shutdown`]: #method.drain_on_shutdown
+    pub fn drain_on_shutdown_with_timeout(&mut self, timeout: Duration) {
+        *self.drain_timeout.lock().unwrap() = Some(timeout);
+        self.intercom_receiver
+            .drain_on_shutdown(Some(self.status.clone()));
+    }
+
+    /// why the service has been asked to shutdown
+    ///
+    /// returns `None` if the service has not been asked to shutdown.
+    pub fn shutdown_reason(&self) -> Option<ShutdownReason> {
+        let status = self.status.status();
+        if !(status.is_shutting_down() || status.is_stopped()) {
+            return None;
+        }
+
+        match self.control.current() {
+            Control::Shutdown { reason } => Some(reason),
+            Control::Kill => None,
+        }
+    }
+
+    /// the service has been asked to shutdown, or to be killed
+    ///
+    /// stopping or killing a service aborts its task, which only takes
+    /// effect at its next `.await`: a service blocking in `block_in_place`,
+    /// or in a nested `block_on`, is not stopped until it returns from it.
+    /// Check this between the chunks of the blocking work to stop early.
+    pub fn is_shutting_down(&self) -> bool {
+        let status = self.status.status();
+        status.is_shutting_down() || status.is_stopped()
+    }
+
+    /// time since the service reached `Started`
+    ///
+    /// zero until the service finished its `init`. On restart the uptime
+    /// starts again from the latest start.
+    pub fn uptime(&self) -> Duration {
+        self.uptime.get()
+    }
+
+    /// get the value of type `V` kept across the restarts of the service
+    ///
+    /// the values are kept by the `ServiceManager` until the service is
+    /// killed: a graceful shutdown or a failure keep them for the next start.
+    pub fn persistent<V: Any + Send + Clone>(&self) -> Option<V> {
+        self.persistent.get()
+    }
+
+    /// set the value of type `V` kept across the restarts of the service,
+    /// see [`persistent`](#method.persistent)
+    pub fn set_persistent<V: Any + Send>(&self, value: V) {
+        self.persistent.set(value)
+    }
+
+    /// the metrics of the service, reported in `StatusReport::custom`
+    ///
+    /// like the [`persistent`](#method.persistent) values, the metrics are
+    /// kept across the restarts of the service.
+    pub fn metrics(&self) -> &ServiceMetrics {
+        &self.metrics
+    }
+
+    /// the current time, on the runtime's clock
+    pub fn now(&self) -> Instant {
+        Instant::now()
+    }
+
     /// access the status reader of the service. If the status is updated
     /// to be shutdown then the reader will receive the notification event
     /// and will be able to prepare for shutdown gracefully
@@ -138,17 +604,239 @@ impl<T: Service> ServiceState<T> {
         &self.handle
     }
 
+    /// access the watchdog's Runtime handle
+    ///
+    /// the tasks spawned there are not tied to the service: they are not
+    /// aborted when the service shuts down or is killed and they outlive
+    /// its restarts (e.g. a cache warmer shared by the successive runs of
+    /// the service). They only stop with the watchdog itself. Prefer
+    /// [`spawn_child`](#method.spawn_child) for the tasks of the current
+    /// run.
+    pub fn watchdog_handle(&self) -> Handle {
+        self.watchdog_query.handle().clone()
+    }
+
+    /// check whether the `io` driver is enabled on the service's runtime
+    pub fn has_io(&self) -> bool {
+        self.io_driver
+    }
+
+    /// check whether the `time` driver is enabled on the service's runtime
+    pub fn has_time(&self) -> bool {
+        self.time_driver
+    }
+
+    /// create a new `Interval` that yields every `period` on the service's
+    /// runtime.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the `time` driver is not enabled for the
+    /// runtime of the service (see `#[runtime(time)]`).
+    pub fn interval(&self, period: Duration) -> tokio::time::Interval {
+        if !self.has_time() {
+            panic!(
+                "service '{}' cannot create an interval: the `time` driver is not enabled \
+                 on its runtime, use `#[runtime(time)]` on the service's field",
+                self.identifier
+            );
+        }
+
+        self.handle.enter(|| tokio::time::interval(period))
+    }
+
+    /// send `msg` to the service itself once `after` elapsed, e.g. to retry
+    /// an operation with a backoff
+    ///
+    /// the message is dropped if the service is no longer `Started` when
+    /// the delay elapsed: it is never delivered to a service shutting down
+    /// nor to the next run of the service.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the `time` driver is not enabled for the
+    /// runtime of the service (see `#[runtime(time)]`).
+    pub fn defer(&self, msg: T::IntercomMsg, after: Duration)
+    where
+        T::IntercomMsg: Send,
+    {
+        if !self.has_time() {
+            panic!(
+                "service '{}' cannot defer a message: the `time` driver is not enabled \
+                 on its runtime, use `#[runtime(time)]` on the service's field",
+                self.identifier
+            );
+        }
+
+        let mut intercom = self.intercom_with::<T>();
+        let status = self.status.clone();
+        self.spawn_child(async move {
+            tokio::time::delay_for(after).await;
+            if !status.status().is_started() {
+                tracing::debug!("service stopping, deferred message dropped");
+                return;
+            }
+            if let Err(error) = intercom.send(msg).await {
+                tracing::warn!(reason = %error.reason(), "deferred message not delivered");
+            }
+        });
+    }
+
+    /// receive the admin commands sent with `WatchdogQuery::admin`
+    ///
+    /// the admin channel is opened by this call: until then, and once the
+    /// service has been asked to shutdown, the admin commands fail with
+    /// `WatchdogError::CannotConnectToService`. The receiver then yields
+    /// `None` once the commands already sent are received. Calling it again
+    /// closes the previous receiver.
+    pub fn admin_rx(&self) -> mpsc::Receiver<AdminRequest<T>>
+    where
+        T: ServiceAdmin,
+    {
+        self.admin.open::<T>()
+    }
+
+    /// ask to be started again once the service returns from `start`
+    ///
+    /// use it to restart cleanly from a bad state (e.g. a corrupted cache)
+    /// rather than panicking: the service returns from `start` and the
+    /// watchdog starts it again from a new `ServiceState`, keeping its
+    /// persistent values. The request is ignored if the service is shut
+    /// down, killed or panics in the meantime.
+    ///
+    /// it counts as any other restart against the
+    /// `#[restart(crash_loop(...))]` of the service.
+    pub fn request_restart(&self) {
+        self.restart.store(true, Ordering::SeqCst);
+    }
+
+    /// register a `cleanup` to run once the service stopped, however it
+    /// stopped: finished, panicked or aborted
+    ///
+    /// the cleanups are run by the watchdog once the service's task is over,
+    /// the latest registered first. Only the cleanups registered during the
+    /// current run are called.
+    pub fn on_cleanup<F>(&self, cleanup: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.cleanups.push(Box::new(cleanup))
+    }
+
+    /// register a `finalizer` to run if the service is aborted (killed, or
+    /// not stopped within its drain timeout), replacing the previous one
+    ///
+    /// aborting the service drops its future at its next `.await`: the
+    /// finalizer is then run to completion on the service's runtime, for at
+    /// most the timeout of `#[shutdown(finalize_on_abort)]`, before the
+    /// cleanups. It is ignored without the attribute, and it is not run on
+    /// `WatchdogMonitor::abort_all` nor if the service finished on its own.
+    pub fn on_abort<F>(&self, finalizer: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        if self.finalize_on_abort.is_none() {
+            tracing::warn!(
+                "finalizer ignored, use #[shutdown(finalize_on_abort)] on the service's field"
+            );
+            return;
+        }
+        self.finalizer.set(Box::pin(finalizer))
+    }
+
+    /// create a token that is cancelled once the service finished or
+    /// has been aborted
+    pub fn child_token(&self) -> CancellationToken {
+        self.cancellation.child_token()
+    }
+
+    /// spawn the given future in the context of the Service's Runtime, tied
+    /// to the lifecycle of the service.
+    ///
+    /// Unlike [`spawn`], the task is cancelled once the service finished or
+    /// has been aborted. The returned handle yields `None` if the task has
+    /// been cancelled.
+    ///
+    /// [`spawn`]: #method.spawn
+    pub fn spawn_child<F>(&self, future: F) -> JoinHandle<Option<F::Output>>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let token = self.child_token();
+        self.spawn(async move {
+            tokio::select! {
+                output = future => Some(output),
+                _ = token.cancelled() => None,
+            }
+        })
+    }
+
+    /// spawn the given future like [`spawn`], waiting first for the number
+    /// of tasks spawned with `spawn_limited` still running to be below the
+    /// `max_concurrent_tasks` of the service
+    ///
+    /// the task is counted as running until it finished, panicked or has
+    /// been aborted. Without `#[runtime(max_concurrent_tasks = ...)]` the task
+    /// is spawned right away.
+    ///
+    /// [`spawn`]: #method.spawn
+    pub async fn spawn_limited<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let permit = match &self.task_limit {
+            Some(task_limit) => Some(task_limit.acquire().await),
+            None => None,
+        };
+
+        self.spawn(async move {
+            let _permit = permit;
+            future.await
+        })
+    }
+
+    /// the number of tasks spawned with [`spawn`] that are still running
+    ///
+    /// [`spawn`]: #method.spawn
+    pub fn task_count(&self) -> usize {
+        self.tasks.count()
+    }
+
     /// spawn the given future in the context of the Service's Runtime.
     ///
     /// While there is no way to enforce the users to actually spawn tasks
     /// within the Runtime we can at least urge the users to do so and avoid
     /// using the global runtime context as it may be used for other purposes.
+    ///
+    /// The running tasks are checked against the `max_tasks` of the service's
+    /// [`ResourceBudget`].
+    ///
+    /// [`ResourceBudget`]: ./struct.ResourceBudget.html
     pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
     where
         F: Future + Send + 'static,
         F::Output: Send + 'static,
     {
-        self.runtime_handle().spawn(future)
+        let task = self.tasks.spawned();
+
+        if let Some(limit) = self.budget.max_tasks {
+            let usage = self.tasks.count();
+            if self.tasks.usage.exceeds(usage, limit) {
+                self.watchdog_query.emit(WatchdogEvent::BudgetExceeded {
+                    service_identifier: self.identifier,
+                    resource: BudgetResource::Tasks,
+                    limit,
+                    usage,
+                });
+            }
+        }
+
+        self.runtime_handle().spawn(async move {
+            let _task = task;
+            future.await
+        })
     }
 }
 
@@ -167,51 +855,257 @@ impl<T: Service> ServiceManager<T> {
             status,
             controller,
             runtime: runtime.handle().clone(),
+            io_driver: runtime.config().io_driver,
+            time_driver: runtime.config().time_driver,
+            budget: ResourceBudget::default(),
+            task_limit: None,
+            fair_intercom: false,
+            finalize_on_abort: None,
+            ordered_intercom: false,
+            crash_loop: None,
+            restarts: Restarts::default(),
             started: 0,
+            uptime: Uptime::default(),
+            ever_started: Arc::new(AtomicBool::new(false)),
+            persistent: StateBag::default(),
+            metrics: ServiceMetrics::default(),
+            last_panic: Arc::new(Mutex::new(None)),
+            admin: AdminSlot::default(),
         }
     }
 
+    /// set the soft [`ResourceBudget`] of the service, applied the next
+    /// time the service is started
+    ///
+    /// [`ResourceBudget`]: ./struct.ResourceBudget.html
+    pub fn set_budget(&mut self, budget: ResourceBudget) {
+        self.budget = budget;
+    }
+
+    pub fn budget(&self) -> ResourceBudget {
+        self.budget
+    }
+
+    /// bound the number of tasks spawned with `ServiceState::spawn_limited`
+    /// running at the same time, at least one task is allowed to run
+    ///
+    /// applied the next time the service is started, see
+    /// `#[runtime(max_concurrent_tasks = 100)]`.
+    pub fn set_max_concurrent_tasks(&mut self, max: usize) {
+        self.task_limit = Some(TaskLimit::new(max));
+    }
+
+    /// rotate across the producers when receiving the `Normal` priority
+    /// intercom messages, so a chatty producer cannot starve the others
+    ///
+    /// applied the next time the service is started, see
+    /// `#[intercom(fair)]`.
+    pub fn set_fair_intercom(&mut self, fair: bool) {
+        self.fair_intercom = fair;
+    }
+
+    /// ignore the priority of the intercom messages, so the messages of a
+    /// sender are all received in the order they were sent
+    ///
+    /// applied the next time the service is started, see
+    /// `#[intercom(ordered)]` and `IntercomMsg::priority`.
+    pub fn set_ordered_intercom(&mut self, ordered: bool) {
+        self.ordered_intercom = ordered;
+    }
+
+    /// run the finalizer registered with `ServiceState::on_abort` when the
+    /// service is aborted, for at most `timeout`
+    ///
+    /// applied the next time the service is started, see
+    /// `#[shutdown(finalize_on_abort)]`.
+    pub fn set_finalize_on_abort(&mut self, timeout: Option<Duration>) {
+        self.finalize_on_abort = timeout;
+    }
+
+    /// refuse to restart the service if it restarts too often, see
+    /// [`CrashLoop`]
+    ///
+    /// [`CrashLoop`]: ./struct.CrashLoop.html
+    pub fn set_crash_loop(&mut self, crash_loop: CrashLoop) {
+        self.crash_loop = Some(crash_loop);
+    }
+
     pub fn intercom(&self) -> IntercomSender<T::IntercomMsg> {
         self.intercom_sender.clone()
     }
 
+    /// the admin channel of the service, as expected by
+    /// `WatchdogQuery::admin`
+    ///
+    /// fails with `WatchdogError::CannotConnectToService` if the service
+    /// is not running, is shutting down or did not open its channel with
+    /// `ServiceState::admin_rx`.
+    pub fn admin(&self) -> Result<Box<dyn Any + Send + 'static>, WatchdogError> {
+        match self.admin.connect() {
+            Some(admin) if !self.status.status().is_stopped() => Ok(admin),
+            _ => Err(WatchdogError::CannotConnectToService {
+                service_identifier: self.identifier,
+                retry_attempted: false,
+            }),
+        }
+    }
+
+    /// check whether the `io` driver is enabled on the service's runtime
+    pub fn has_io(&self) -> bool {
+        self.io_driver
+    }
+
+    /// check whether the `time` driver is enabled on the service's runtime
+    pub fn has_time(&self) -> bool {
+        self.time_driver
+    }
+
+    /// the current status of the service, without gathering the intercom
+    /// stats of the full [`status`](#method.status) report
+    pub fn current_status(&self) -> Status {
+        self.status.status()
+    }
+
+    /// a reader of the status of the service, notified of its updates
+    ///
+    /// the same status is updated across the restarts of the service.
+    pub fn status_reader(&self) -> StatusReader {
+        self.status.clone()
+    }
+
+    /// the service is `Started`
+    pub fn is_running(&self) -> bool {
+        self.current_status().is_started()
+    }
+
+    pub fn is_starting(&self) -> bool {
+        self.current_status().is_starting()
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.current_status().is_shutting_down()
+    }
+
+    /// check whether the service reached `Started` at least once
+    ///
+    /// unlike the status this remains `true` once the service is stopped.
+    pub fn has_ever_started(&self) -> bool {
+        self.ever_started.load(Ordering::SeqCst)
+    }
+
+    /// report the status of the service
+    ///
+    /// the intercom stats are read from atomic counters: gathering them
+    /// never waits on the service, even if it is stuck.
     pub async fn status(&self) -> StatusReport {
+        let intercom = self.intercom_stats.status().await;
+
+        let status = self.status.status();
+        let uptime = match status {
+            Status::Started { .. } | Status::ShuttingDown { .. } => self.uptime.get(),
+            _ => Duration::default(),
+        };
+
         StatusReport {
             identifier: self.identifier,
-            status: self.status.status(),
-            intercom: self.intercom_stats.status().await,
+            status,
+            intercom,
             started: self.started,
+            has_ever_started: self.has_ever_started(),
+            uptime,
+            last_panic: self.last_panic.lock().unwrap().clone(),
+            custom: self.metrics.snapshot(),
+            concurrent_tasks: self.task_limit.as_ref().map_or(0, TaskLimit::running),
+            history: self.status.history(),
         }
     }
 
     pub fn shutdown(&mut self) {
+        self.shutdown_with_reason(ShutdownReason::UserRequested)
+    }
+
+    /// ask the service to shutdown, the `reason` is available to the service
+    /// with `ServiceState::shutdown_reason`
+    pub fn shutdown_with_reason(&mut self, reason: ShutdownReason) {
         match self.status.status() {
-            Status::Shutdown { .. } | Status::ShuttingDown { .. } => {
-                // Ignore as the node is either shutdown or already shutting
-                // down
+            Status::Shutdown { .. } | Status::ShuttingDown { .. } | Status::Failed { .. } => {
+                // Ignore as the node is either shutdown, failed or already
+                // shutting down
             }
             Status::Starting { .. } | Status::Started { .. } => {
                 // send only if the node will have a chance to actually read
                 // the command
-                self.controller.send(Control::Shutdown)
+                self.admin.close();
+                self.controller.send(Control::Shutdown { reason })
             }
         }
     }
 
+    /// kill the service if it is still running, even if it is already
+    /// shutting down
+    ///
+    /// unlike [`shutdown`](#method.shutdown) the service is aborted right
+    /// away and its persistent values are cleared.
+    pub fn force_kill(&mut self) {
+        if !self.status.status().is_stopped() {
+            self.admin.close();
+            self.controller.send(Control::Kill)
+        }
+    }
+
+    /// the restarts within the window of the `CrashLoop`, if there are
+    /// too many to restart the service
+    fn crash_looping(&self) -> Option<usize> {
+        let crash_loop = self.crash_loop?;
+        self.restarts.crash_looping(crash_loop, Instant::now())
+    }
+
     pub fn runtime(
         &mut self,
         watchdog_query: WatchdogQuery,
     ) -> Result<ServiceRuntime<T>, ServiceError> {
         let status = self.status.status();
-        if !status.is_shutdown() {
+        if !status.is_stopped() {
             Err(ServiceError::CannotStart { status })
+        } else if let Some(restarts) = self.crash_looping() {
+            // already reported when the service exited
+            let window = self.crash_loop.unwrap().window;
+            Err(ServiceError::CrashLooping { restarts, window })
         } else {
-            let (intercom_sender, intercom_receiver, intercom_stats) =
+            let (mut intercom_sender, mut intercom_receiver, mut intercom_stats) =
                 intercom::channel::<T::IntercomMsg>();
 
+            if self.fair_intercom {
+                intercom_receiver.fair(&mut intercom_stats);
+            }
+
+            if self.ordered_intercom {
+                intercom_sender.set_ordered();
+            }
+
+            if let Some(limit) = self.budget.max_intercom_queue {
+                intercom_sender.set_queue_budget(
+                    self.identifier,
+                    limit,
+                    &intercom_stats,
+                    watchdog_query.events().clone(),
+                );
+            }
+
             self.intercom_sender = intercom_sender;
             self.intercom_stats = intercom_stats;
+            if let Some(crash_loop) = self.crash_loop.filter(|_| self.started > 0) {
+                self.restarts.record(crash_loop, Instant::now());
+            }
             self.started += 1;
+            self.uptime = Uptime::default();
+
+            let drain_timeout = Arc::new(Mutex::new(None));
+            let cancellation = CancellationToken::new();
+            let cleanups = Cleanups::default();
+            let restart = Arc::new(AtomicBool::new(false));
+            let finalizer = Finalizer::default();
+            self.admin = AdminSlot::default();
 
             Ok(ServiceRuntime {
                 service_state: ServiceState {
@@ -220,20 +1114,61 @@ impl<T: Service> ServiceManager<T> {
                     status: self.status.clone(),
                     intercom_receiver,
                     watchdog_query,
+                    io_driver: self.io_driver,
+                    time_driver: self.time_driver,
+                    drain_timeout: Arc::clone(&drain_timeout),
+                    budget: self.budget,
+                    tasks: Arc::new(Tasks::default()),
+                    task_limit: self.task_limit.clone(),
+                    cancellation: cancellation.clone(),
+                    control: self.controller.reader(),
+                    uptime: self.uptime.clone(),
+                    persistent: self.persistent.clone(),
+                    metrics: self.metrics.clone(),
+                    yield_budget: 0,
+                    cleanups: cleanups.clone(),
+                    restart: Arc::clone(&restart),
+                    admin: self.admin.clone(),
+                    finalizer: finalizer.clone(),
+                    finalize_on_abort: self.finalize_on_abort,
                 },
                 status: self.status.updater(),
                 control: self.controller.reader(),
+                drain_timeout,
+                cancellation,
+                uptime: self.uptime.clone(),
+                ever_started: Arc::clone(&self.ever_started),
+                persistent: self.persistent.clone(),
+                last_panic: Arc::clone(&self.last_panic),
+                cleanups,
+                restart,
+                crash_loop: self
+                    .crash_loop
+                    .map(|crash_loop| (crash_loop, self.restarts.clone())),
+                finalizer,
+                finalize_on_abort: self.finalize_on_abort,
             })
         }
     }
 }
 
 impl<T: Service> ServiceRuntime<T> {
-    pub fn start(self) {
+    pub fn start(self) -> Result<(), ServiceError> {
         let ServiceRuntime {
             service_state,
             status,
             mut control,
+            drain_timeout,
+            cancellation,
+            uptime,
+            ever_started,
+            persistent,
+            last_panic,
+            cleanups,
+            restart,
+            crash_loop,
+            finalizer,
+            finalize_on_abort,
         } = self;
 
         let service_identifier: &'static str = service_state.identifier;
@@ -241,16 +1176,46 @@ impl<T: Service> ServiceRuntime<T> {
         status.update(Status::starting());
 
         let watchdog_query = service_state.watchdog_query.clone();
+        #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+        let tracing_prefix = watchdog_query.tracing_prefix();
+        let events = watchdog_query.events().clone();
+        let on_service_error = watchdog_query.on_service_error().cloned();
         let handle = service_state.handle.clone();
-        let runner = T::prepare(service_state);
+        // enter the service's runtime so `try_prepare` can create its timers and
+        // IO resources
+        let mut runner = match handle.enter(|| T::try_prepare(service_state)) {
+            Ok(runner) => runner,
+            Err(error) => {
+                tracing::error!(%error, "cannot prepare the service");
+                cleanups.run();
+                status.update(Status::failed());
+                return Err(ServiceError::CannotPrepare {
+                    source: Arc::from(error),
+                });
+            }
+        };
+        let (initialized_sender, mut initialized) = oneshot::channel();
 
         let (runner, abort_handle) = abortable(async move {
-            let span = tracing::info_span!("service", service_identifier);
-            let _enter = span.enter();
+            enter_span!(crate::trace::with_prefix(
+                tracing::info_span!("service", app = tracing::field::Empty, service_identifier),
+                tracing_prefix,
+            ));
+
+            runner.init().in_current_span().await;
+            if initialized_sender.send(()).is_err() {
+                // the control of the service is already gone
+            }
 
             runner.start().in_current_span().await
         });
+        // the finalizer is run once the service is aborted, it is only
+        // aborted itself if it does not finish in time
+        let (runner, finalizer_abort_handle) = abortable(finalizer.on_abort(runner));
 
+        watchdog_query
+            .aborts()
+            .register(service_identifier, finalizer_abort_handle.clone());
         let mut service_join_handle = handle.spawn(runner);
 
         // the runner (the service) has been started into its current runtime. They must use
@@ -258,61 +1223,199 @@ impl<T: Service> ServiceRuntime<T> {
         //
         // however the control of the service is still spawned in the watchdog current context
         // so we can perform the management tasks without disrupting the service's runtime
+        let mut restarter = watchdog_query.clone();
         watchdog_query.spawn(async move {
-            status.update(Status::started());
+            enter_span!(crate::trace::with_prefix(
+                tracing::debug_span!(
+                    "service control",
+                    app = tracing::field::Empty,
+                    service_identifier
+                ),
+                tracing_prefix,
+            ));
 
-            let span = tracing::debug_span!("service control", service_identifier);
-            let _enter = span.enter();
+            let mut drain_deadline = None;
+            let mut waiting_initialization = true;
+            let mut is_initialized = false;
+            let mut is_shutting_down = false;
+            let mut aborted = false;
+            let mut restarting = false;
 
             loop {
                 tokio::select! {
+                    init_result = &mut initialized, if waiting_initialization => {
+                        waiting_initialization = false;
+                        is_initialized = init_result.is_ok();
+                        if is_initialized && !is_shutting_down {
+                            uptime.start();
+                            ever_started.store(true, Ordering::SeqCst);
+                            status.update(Status::started());
+                        }
+                    }
                     join_result = &mut service_join_handle => {
+                        let is_initialized = is_initialized
+                            || (waiting_initialization && initialized.try_recv().is_ok());
+
                         if let Err(join_error) = join_result {
                             // TODO: the task could not join, either cancelled
                             //       or panicked. Ideally we need to document
                             //       this panic and see what kind of strategy
                             //       can be applied (can we restart the service?)
                             //       or is it a fatal panic and we cannot recover?
-
-                            tracing::error!(
-                                "main process failed with following error: {:#?}",
-                                join_error
-                            );
+                            match join_error.try_into_panic() {
+                                Ok(payload) => {
+                                    let message = panic_message(payload.as_ref());
+                                    *last_panic.lock().unwrap() = Some(message.clone());
+                                    if let Some(on_service_error) = &on_service_error {
+                                        on_service_error.call(
+                                            service_identifier,
+                                            ServiceErrorInfo::Panicked {
+                                                message: message.clone(),
+                                            },
+                                        );
+                                    }
+                                    emit(
+                                        &events,
+                                        WatchdogEvent::ServicePanicked {
+                                            service_identifier,
+                                            message,
+                                        },
+                                    );
+                                }
+                                Err(join_error) => match &on_service_error {
+                                    Some(on_service_error) => on_service_error.call(
+                                        service_identifier,
+                                        ServiceErrorInfo::JoinFailed {
+                                            reason: join_error.to_string(),
+                                        },
+                                    ),
+                                    None => tracing::error!(
+                                        "main process failed with following error: {:#?}",
+                                        join_error
+                                    ),
+                                },
+                            }
                         } else {
                             // nothing to do her, the service already finished and
-                            // returned successfully
+                            // returned successfully, unless it asked to be restarted
+                            restarting = restart.load(Ordering::SeqCst) && !is_shutting_down;
+                        }
+
+                        if is_initialized {
+                            status.update(Status::shutdown());
+                        } else {
+                            tracing::error!("service stopped before being initialized");
+                            status.update(Status::failed());
+                        }
+
+                        // a service stopped on request is not crashing
+                        let crash_looping = crash_loop
+                            .as_ref()
+                            .filter(|_| !is_shutting_down)
+                            .and_then(|(crash_loop, restarts)| {
+                                let restarts = restarts.crash_looping(*crash_loop, Instant::now())?;
+                                Some((restarts, crash_loop.window))
+                            });
+                        if let Some((restarts, window)) = crash_looping {
+                            tracing::error!(restarts, ?window, "the service is crash looping");
+                            status.update(Status::failed());
+                            emit(
+                                &events,
+                                WatchdogEvent::CrashLooping {
+                                    service_identifier,
+                                    restarts,
+                                    window,
+                                },
+                            );
                         }
-                        status.update(Status::shutdown());
                         break;
                     }
                     control = control.updated() => {
                         match control {
-                            Some(Control::Shutdown) => {
-                                tracing::info!("shutting down...");
+                            Some(Control::Shutdown { reason }) => {
+                                tracing::info!(%reason, "shutting down...");
 
                                 // updating the status will notify the `StatusReader` in the `ServiceState`
                                 // if watched, the future will yield and the service will be able to prepare
                                 // for the service shutdown and exit gracefully.
+                                is_shutting_down = true;
                                 status.update(Status::shutting_down());
+
+                                // in drain mode the service is given some time to process
+                                // the messages still queued in its intercom
+                                if let Some(timeout) = *drain_timeout.lock().unwrap() {
+                                    drain_deadline = Some(tokio::time::delay_for(timeout));
+                                }
                             }
                             None | Some(Control::Kill) => {
                                 tracing::info!("Terminating...");
+                                persistent.clear();
                                 status.update(Status::shutdown());
                                 abort_handle.abort();
+                                aborted = true;
                                 break;
                             }
                         }
                     }
+                    _ = async { drain_deadline.as_mut().unwrap().await }, if drain_deadline.is_some() => {
+                        tracing::warn!("intercom not drained in time, terminating...");
+                        status.update(Status::shutdown());
+                        abort_handle.abort();
+                        aborted = true;
+                        break;
+                    }
                 };
             }
+
+            // the service is gone, cancel the children tasks it spawned
+            cancellation.cancel();
+
+            if aborted {
+                // the task only stops at its next await point, and then runs
+                // the finalizer of the service
+                let finalized = match finalize_on_abort {
+                    Some(timeout) => tokio::time::timeout(timeout, &mut service_join_handle)
+                        .await
+                        .is_ok(),
+                    None => false,
+                };
+                if !finalized {
+                    if let Some(timeout) = finalize_on_abort {
+                        tracing::warn!(?timeout, "finalizer not finished in time, aborting it");
+                        finalizer_abort_handle.abort();
+                    }
+                    if service_join_handle.await.is_err() {
+                        // aborted, or panicked while being aborted
+                    }
+                }
+            }
+            cleanups.run();
+
+            if restarting {
+                tracing::info!("restarting as requested by the service");
+                if let Err(error) = restarter.start::<T>().await {
+                    tracing::warn!(%error, "cannot restart the service");
+                }
+            }
         });
+
+        Ok(())
+    }
+}
+
+/// the message of a panic, as printed by the default panic hook
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        (*message).to_owned()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Box<dyn Any>".to_owned()
     }
 }
 
 impl<T: Service> Drop for ServiceManager<T> {
     fn drop(&mut self) {
-        if !self.status.status().is_shutdown() {
-            self.controller.send(Control::Kill)
-        }
+        self.force_kill()
     }
 }
@@ -0,0 +1,56 @@
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+/// values kept by the `ServiceManager` across the restarts of its service
+///
+/// there is at most one value per type. The bag is cleared when the
+/// service is killed, a graceful shutdown (or a failure) keeps it.
+#[derive(Clone, Default)]
+pub(crate) struct StateBag {
+    values: Arc<Mutex<HashMap<TypeId, Box<dyn Any + Send>>>>,
+}
+
+impl StateBag {
+    pub(crate) fn get<V: Any + Send + Clone>(&self) -> Option<V> {
+        self.values
+            .lock()
+            .unwrap()
+            .get(&TypeId::of::<V>())
+            .and_then(|value| value.downcast_ref::<V>())
+            .cloned()
+    }
+
+    pub(crate) fn set<V: Any + Send>(&self, value: V) {
+        self.values
+            .lock()
+            .unwrap()
+            .insert(TypeId::of::<V>(), Box::new(value));
+    }
+
+    pub(crate) fn clear(&self) {
+        self.values.lock().unwrap().clear()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_value_per_type() {
+        let bag = StateBag::default();
+        assert_eq!(bag.get::<u64>(), None);
+
+        bag.set(1u64);
+        bag.set(2u64);
+        bag.set("offset");
+        assert_eq!(bag.get::<u64>(), Some(2));
+        assert_eq!(bag.get::<&str>(), Some("offset"));
+
+        bag.clone().clear();
+        assert_eq!(bag.get::<u64>(), None);
+    }
+}
@@ -1,6 +1,9 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
 /// keep some stats based on [Welford's online algorithm]
 ///
 /// [Welford's online algorithm]: https://en.wikipedia.org/wiki/Algorithms_for_calculating_variance#Welford's_online_algorithm
+#[derive(Debug, Clone, Copy)]
 pub struct Stats {
     count: u64,
     mean: f64,
@@ -51,6 +54,57 @@ impl Stats {
     }
 }
 
+/// [`Stats`] shared between a single writer and any number of readers,
+/// without locking
+///
+/// the writer is the only one to update the stats (e.g. the intercom
+/// receiver, on every message), the readers take a [`snapshot`]. A
+/// snapshot taken while the stats are updated may mix the previous and
+/// the new entry.
+///
+/// [`Stats`]: ./struct.Stats.html
+/// [`snapshot`]: #method.snapshot
+#[derive(Debug, Default)]
+pub(crate) struct AtomicStats {
+    count: AtomicU64,
+    /// `f64::to_bits` of the mean
+    mean: AtomicU64,
+    /// `f64::to_bits` of the m2
+    m2: AtomicU64,
+}
+
+impl AtomicStats {
+    /// see `Stats::push`, must only be called by one writer at a time
+    pub(crate) fn push(&self, entry: f64) {
+        let mut stats = self.snapshot();
+        stats.push(entry);
+
+        self.mean.store(stats.mean.to_bits(), Ordering::Relaxed);
+        self.m2.store(stats.m2.to_bits(), Ordering::Relaxed);
+        self.count.store(stats.count, Ordering::Release);
+    }
+
+    pub(crate) fn snapshot(&self) -> Stats {
+        let count = self.count.load(Ordering::Acquire);
+        Stats {
+            count,
+            mean: f64::from_bits(self.mean.load(Ordering::Relaxed)),
+            m2: f64::from_bits(self.m2.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// ratio of the `capacity` used by the `depth` of a queue
+///
+/// an unbounded queue (`capacity` of `0`) is never considered used.
+pub fn utilization(depth: usize, capacity: usize) -> f64 {
+    if capacity == 0 {
+        0.0
+    } else {
+        depth as f64 / capacity as f64
+    }
+}
+
 impl Default for Stats {
     fn default() -> Self {
         Self::new()
@@ -96,6 +150,28 @@ mod tests {
         approx::assert_relative_eq!(stats.standard_derivation(), 7.07, max_relative = 0.001,);
     }
 
+    #[test]
+    fn atomic_snapshot() {
+        let stats = AtomicStats::default();
+        approx::assert_relative_eq!(stats.snapshot().mean(), 0.0);
+
+        for entry in &[10.0, 11.0, 15.0, 19.0, 20.0] {
+            stats.push(*entry);
+        }
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.count(), 5);
+        approx::assert_relative_eq!(snapshot.mean(), 15.0, max_relative = 0.001);
+        approx::assert_relative_eq!(snapshot.variance(), 20.5, max_relative = 0.001);
+    }
+
+    #[test]
+    fn utilization_of_unbounded() {
+        approx::assert_relative_eq!(utilization(0, 0), 0.0);
+        approx::assert_relative_eq!(utilization(10, 0), 0.0);
+        approx::assert_relative_eq!(utilization(5, 20), 0.25);
+    }
+
     fn unit(entries: &[f64], mean: f64, variance: f64, standard_derivation: f64) {
         let count = entries.len();
 
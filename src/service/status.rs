@@ -1,33 +1,70 @@
 use std::{
+    collections::VecDeque,
     fmt,
     pin::Pin,
-    sync::Arc,
+    sync::{Arc, Mutex},
     task::{Context, Poll},
-    time::SystemTime,
+    time::{Duration, SystemTime},
 };
 use tokio::{
     stream::Stream,
     sync::watch::{self, Receiver, Sender},
+    time::Instant,
 };
 
+/// number of status transitions kept in the history of a service, see
+/// `StatusReader::history`
+pub const STATUS_HISTORY_CAPACITY: usize = 16;
+
 #[derive(Debug, Clone)]
 pub struct StatusReader {
     status: Receiver<Status>,
     updater: Arc<Sender<Status>>,
+    history: History,
 }
 
 #[derive(Debug)]
 pub struct StatusUpdater {
+    status: Receiver<Status>,
     updater: Arc<Sender<Status>>,
+    history: History,
+}
+
+/// the latest status transitions, bounded to `STATUS_HISTORY_CAPACITY`
+/// so a restart storm does not grow it
+#[derive(Debug, Clone, Default)]
+struct History(Arc<Mutex<VecDeque<Status>>>);
+
+/// monotonic time since the service reached `Started`
+///
+/// shared between the `ServiceManager` and the running service, a new one
+/// is created every time the service is (re)started.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Uptime {
+    started: Arc<Mutex<Option<Instant>>>,
 }
 
 /// these are the different status of the service
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum Status {
-    Starting { since: SystemTime },
-    Started { since: SystemTime },
-    ShuttingDown { since: SystemTime },
-    Shutdown { since: SystemTime },
+    Starting {
+        since: SystemTime,
+    },
+    Started {
+        since: SystemTime,
+    },
+    ShuttingDown {
+        since: SystemTime,
+    },
+    Shutdown {
+        since: SystemTime,
+    },
+    /// the service could not be prepared or it stopped before it
+    /// finished its initialization
+    Failed {
+        since: SystemTime,
+    },
 }
 
 impl Status {
@@ -55,13 +92,36 @@ impl Status {
         }
     }
 
-    pub fn is_shutdown(&self) -> bool {
-        if let Status::Shutdown { .. } = self {
-            true
-        } else {
-            false
+    pub fn failed() -> Self {
+        Status::Failed {
+            since: SystemTime::now(),
         }
     }
+
+    pub fn is_starting(&self) -> bool {
+        matches!(self, Status::Starting { .. })
+    }
+
+    pub fn is_started(&self) -> bool {
+        matches!(self, Status::Started { .. })
+    }
+
+    pub fn is_failed(&self) -> bool {
+        matches!(self, Status::Failed { .. })
+    }
+
+    /// the service is not running: it is either shutdown or it failed
+    pub fn is_stopped(&self) -> bool {
+        self.is_shutdown() || self.is_failed()
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        matches!(self, Status::Shutdown { .. })
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        matches!(self, Status::ShuttingDown { .. })
+    }
 }
 
 impl StatusReader {
@@ -71,13 +131,19 @@ impl StatusReader {
         let (updater, status) = watch::channel(initial);
         let updater = Arc::new(updater);
 
-        StatusReader { status, updater }
+        StatusReader {
+            status,
+            updater,
+            history: History::default(),
+        }
     }
 
     /// create a `StatusUpdater` from the given reader
     pub fn updater(&self) -> StatusUpdater {
         StatusUpdater {
+            status: self.status.clone(),
             updater: Arc::clone(&self.updater),
+            history: self.history.clone(),
         }
     }
 
@@ -90,10 +156,47 @@ impl StatusReader {
     pub async fn updated(&mut self) -> Option<Status> {
         self.status.recv().await
     }
+
+    /// the latest status transitions, oldest first, kept across the
+    /// restarts of the service
+    ///
+    /// at most [`STATUS_HISTORY_CAPACITY`] transitions are kept, every
+    /// status tells since when it applied.
+    ///
+    /// [`STATUS_HISTORY_CAPACITY`]: ./constant.STATUS_HISTORY_CAPACITY.html
+    pub fn history(&self) -> Vec<Status> {
+        self.history.0.lock().unwrap().iter().copied().collect()
+    }
+}
+
+impl History {
+    fn push(&self, status: Status) {
+        let mut history = self.0.lock().unwrap();
+        if history.len() == STATUS_HISTORY_CAPACITY {
+            history.pop_front();
+        }
+        history.push_back(status);
+    }
+}
+
+impl Uptime {
+    pub(crate) fn start(&self) {
+        *self.started.lock().unwrap() = Some(Instant::now());
+    }
+
+    /// `Duration::default()` if the service has not started yet
+    pub(crate) fn get(&self) -> Duration {
+        self.started
+            .lock()
+            .unwrap()
+            .map(|started| started.elapsed())
+            .unwrap_or_default()
+    }
 }
 
 impl StatusUpdater {
     pub fn update(&self, status: Status) {
+        self.history.push(status);
         if self.updater.broadcast(status).is_err() {
             // if the receiver is gone, it means the watchdog dropped the
             // associated StatusReader and that it is not important to monitor
@@ -112,6 +215,12 @@ impl Stream for StatusReader {
 
 impl Drop for StatusUpdater {
     fn drop(&mut self) {
+        // do not override the final status if the service already
+        // reported it is stopped
+        if self.status.borrow().is_stopped() {
+            return;
+        }
+
         self.update(Status::ShuttingDown {
             since: SystemTime::now(),
         })
@@ -125,9 +234,31 @@ impl fmt::Display for Status {
             Status::Started { since } => ("started", since),
             Status::ShuttingDown { since } => ("shutting down", since),
             Status::Shutdown { since } => ("shutdown", since),
+            Status::Failed { since } => ("failed", since),
         };
 
         // TODO: display time in better format
         write!(f, "{} (since {:?})", v, since)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn history_is_bounded() {
+        let reader = StatusReader::new(Status::shutdown());
+        let updater = reader.updater();
+        updater.update(Status::failed());
+        for _ in 0..STATUS_HISTORY_CAPACITY {
+            updater.update(Status::starting());
+        }
+        updater.update(Status::started());
+
+        let history = reader.history();
+        assert_eq!(history.len(), STATUS_HISTORY_CAPACITY);
+        assert!(!history.iter().any(Status::is_failed));
+        assert!(history.last().unwrap().is_started());
+    }
+}
@@ -0,0 +1,247 @@
+//! build a [`ServiceState`] without a watchdog, to unit test a service
+//!
+//! available with the `testing` feature.
+//!
+//! [`ServiceState`]: ../struct.ServiceState.html
+
+use crate::{
+    service::{
+        admin::AdminSlot, budget::Tasks, cleanup::Cleanups, finalizer::Finalizer, intercom,
+        status::Uptime, CancellationToken, Control, Controller, IntercomReceiver, IntercomSender,
+        ResourceBudget, Service, ServiceIdentifier, ServiceMetrics, ServiceState, ShutdownReason,
+        StateBag, Status, StatusReader, StatusUpdater,
+    },
+    watchdog::{command_channel, ControlCommand, WatchdogQuery},
+};
+use std::{
+    any::Any,
+    collections::HashMap,
+    sync::{atomic::AtomicBool, Arc, Mutex},
+};
+use tokio::{
+    runtime::Handle,
+    sync::{broadcast, watch},
+};
+
+type Connect = Box<dyn Fn() -> Box<dyn Any + Send> + Send + Sync>;
+
+/// build a [`ServiceState`] for unit tests, see `ServiceState::test_builder`
+///
+/// [`ServiceState`]: ../struct.ServiceState.html
+pub struct TestBuilder<T: Service> {
+    outbound: HashMap<ServiceIdentifier, Connect>,
+    _marker: std::marker::PhantomData<T>,
+}
+
+/// drive the [`ServiceState`] built with a [`TestBuilder`]
+///
+/// [`ServiceState`]: ../struct.ServiceState.html
+/// [`TestBuilder`]: ./struct.TestBuilder.html
+pub struct TestHarness<T: Service> {
+    intercom: IntercomSender<T::IntercomMsg>,
+    status: StatusUpdater,
+    controller: Controller,
+}
+
+impl<T: Service> ServiceState<T> {
+    /// build a `ServiceState` without a watchdog, to unit test a service
+    ///
+    /// available with the `testing` feature.
+    pub fn test_builder() -> TestBuilder<T> {
+        TestBuilder {
+            outbound: HashMap::new(),
+            _marker: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<T: Service> TestBuilder<T> {
+    /// capture the messages the service sends to the service `O`
+    ///
+    /// connecting to a service that has not been captured fails as if the
+    /// service was not running.
+    pub fn outbound<O>(&mut self) -> IntercomReceiver<O::IntercomMsg>
+    where
+        O: Service,
+        O::IntercomMsg: Send,
+    {
+        let (sender, receiver, _) = intercom::channel::<O::IntercomMsg>();
+        let sender = Mutex::new(sender);
+        self.outbound.insert(
+            O::SERVICE_IDENTIFIER,
+            Box::new(move || Box::new(sender.lock().unwrap().clone())),
+        );
+        receiver
+    }
+
+    /// build the `ServiceState`, in `Started` state
+    ///
+    /// this needs to be called from within a `tokio` runtime, the handle of
+    /// the service is the current runtime's.
+    pub async fn build(self) -> (ServiceState<T>, TestHarness<T>) {
+        let handle = Handle::current();
+        let (sender, mut receiver) = command_channel(1);
+        let (events, _) = broadcast::channel(1);
+        let (_, shutting_down) = watch::channel(false);
+        let watchdog_query = WatchdogQuery::new(
+            handle.clone(),
+            sender,
+            &[],
+            Default::default(),
+            events,
+            shutting_down,
+        );
+
+        // only answer the connections to the captured services, the other
+        // queries are left without reply
+        let outbound = self.outbound;
+        handle.spawn(async move {
+            while let Some(command) = receiver.recv().await {
+                match command {
+                    ControlCommand::Intercom {
+                        service_identifier,
+                        reply,
+                    } => reply.reply(match outbound.get(service_identifier) {
+                        Some(connect) => Ok(connect()),
+                        None => Err(crate::WatchdogError::UnknownService {
+                            service_identifier,
+                            possible_values: Vec::new(),
+                        }),
+                    }),
+                    ControlCommand::Shutdown | ControlCommand::Kill => break,
+                    _ => {}
+                }
+            }
+        });
+
+        let (intercom, intercom_receiver, _) = intercom::channel::<T::IntercomMsg>();
+        let status = StatusReader::new(Status::started());
+        let controller = Controller::new().await;
+
+        let state = ServiceState {
+            identifier: T::SERVICE_IDENTIFIER,
+            handle,
+            intercom_receiver,
+            watchdog_query,
+            status: status.clone(),
+            io_driver: true,
+            time_driver: true,
+            drain_timeout: Arc::new(Mutex::new(None)),
+            budget: ResourceBudget::default(),
+            tasks: Arc::new(Tasks::default()),
+            task_limit: None,
+            cancellation: CancellationToken::new(),
+            control: controller.reader(),
+            uptime: Uptime::default(),
+            persistent: StateBag::default(),
+            metrics: ServiceMetrics::default(),
+            yield_budget: 0,
+            cleanups: Cleanups::default(),
+            restart: Arc::new(AtomicBool::new(false)),
+            admin: AdminSlot::default(),
+            finalizer: Finalizer::default(),
+            finalize_on_abort: None,
+        };
+        let harness = TestHarness {
+            intercom,
+            status: status.updater(),
+            controller,
+        };
+
+        (state, harness)
+    }
+}
+
+impl<T: Service> TestHarness<T> {
+    /// a sender to push messages to the service
+    pub fn intercom(&self) -> IntercomSender<T::IntercomMsg> {
+        self.intercom.clone()
+    }
+
+    /// ask the service to shutdown, as the watchdog would
+    pub fn shutdown(&mut self, reason: ShutdownReason) {
+        self.status.update(Status::shutting_down());
+        self.controller.send(Control::Shutdown { reason });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::IntercomMsg;
+    use async_trait::async_trait;
+
+    #[derive(Debug, PartialEq, Eq)]
+    struct Line(&'static str);
+
+    impl IntercomMsg for Line {}
+
+    /// forward the lines to the `Printer`
+    struct Forwarder {
+        state: ServiceState<Self>,
+    }
+
+    struct Printer;
+
+    #[async_trait]
+    impl Service for Forwarder {
+        const SERVICE_IDENTIFIER: ServiceIdentifier = "forwarder";
+
+        type IntercomMsg = Line;
+
+        fn prepare(state: ServiceState<Self>) -> Self {
+            Self { state }
+        }
+
+        async fn start(mut self) {
+            let mut printer = self
+                .state
+                .intercom_with::<Printer>()
+                .into_sender()
+                .await
+                .unwrap();
+            self.state
+                .run_loop(|line| {
+                    let _ = printer.try_send(line);
+                    async {}
+                })
+                .await
+        }
+    }
+
+    #[async_trait]
+    impl Service for Printer {
+        const SERVICE_IDENTIFIER: ServiceIdentifier = "printer";
+
+        type IntercomMsg = Line;
+
+        fn prepare(_: ServiceState<Self>) -> Self {
+            Self
+        }
+
+        async fn start(self) {}
+    }
+
+    #[tokio::test]
+    async fn forward_messages() {
+        let mut builder = ServiceState::<Forwarder>::test_builder();
+        let mut printed = builder.outbound::<Printer>();
+        let (state, mut harness) = builder.build().await;
+
+        let forwarder = tokio::spawn(Forwarder::prepare(state).start());
+
+        harness.intercom().send(Line("hello")).await.unwrap();
+        assert_eq!(printed.recv().await, Some(Line("hello")));
+
+        harness.shutdown(ShutdownReason::UserRequested);
+        forwarder.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn unknown_outbound() {
+        let (state, _harness) = ServiceState::<Forwarder>::test_builder().build().await;
+
+        let mut printer = state.intercom_with::<Printer>();
+        assert!(printer.send(Line("hello")).await.is_err());
+    }
+}
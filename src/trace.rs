@@ -0,0 +1,37 @@
+//! instrumentation helpers
+//!
+//! with the `tracing` feature disabled the spans are not created and the
+//! futures are not instrumented, only the `tracing` events remain.
+
+#[cfg(feature = "tracing")]
+pub(crate) use tracing_futures::Instrument;
+
+/// no-op replacement of `tracing_futures::Instrument`
+#[cfg(not(feature = "tracing"))]
+pub(crate) trait Instrument: Sized {
+    fn in_current_span(self) -> Self {
+        self
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+impl<T> Instrument for T {}
+
+/// create the given span and enter it until the end of the current scope
+macro_rules! enter_span {
+    ($span:expr) => {
+        #[cfg(feature = "tracing")]
+        let span = $span;
+        #[cfg(feature = "tracing")]
+        let _enter = span.enter();
+    };
+}
+
+/// record the `WatchdogBuilder::tracing_prefix` as the `app` field of the
+/// `span`, declared with `app = tracing::field::Empty`
+pub(crate) fn with_prefix(span: tracing::Span, prefix: Option<&'static str>) -> tracing::Span {
+    if let Some(prefix) = prefix {
+        span.record("app", prefix);
+    }
+    span
+}
@@ -0,0 +1,170 @@
+//! write the logs of every service to its own file
+//!
+//! the services run within a `service` span recording their
+//! `service_identifier` (see the `tracing` feature). The [`PerServiceLogs`]
+//! layer writes the events emitted within such a span to
+//! `<dir>/<service_identifier>.log`, and the other events (e.g. the ones
+//! of the watchdog) to `<dir>/organix.log`.
+//!
+//! see `WatchdogBuilder::per_service_log_dir` to install it as the global
+//! subscriber, or add it to your own subscriber:
+//!
+//! ```no_run
+//! use organix::tracing::PerServiceLogs;
+//! use tracing_subscriber::{layer::SubscriberExt as _, Registry};
+//!
+//! let subscriber = Registry::default().with(PerServiceLogs::new("logs").unwrap());
+//! tracing::subscriber::set_global_default(subscriber).unwrap();
+//! ```
+//!
+//! [`PerServiceLogs`]: ./struct.PerServiceLogs.html
+
+use std::{
+    collections::HashMap,
+    fmt::{self, Write as _},
+    io::{self, Write as _},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use tracing::{
+    field::{Field, Visit},
+    span, Event, Subscriber,
+};
+use tracing_appender::rolling::{self, RollingFileAppender};
+use tracing_subscriber::{
+    fmt::{
+        format::{DefaultFields, FormatFields as _},
+        time::{FormatTime as _, SystemTime},
+    },
+    layer::{Context, Layer},
+    registry::LookupSpan,
+    Registry,
+};
+
+/// name of the file receiving the events emitted outside of the services,
+/// without its `.log` extension
+pub const DEFAULT_LOG_FILE: &str = "organix";
+
+/// a `tracing` layer writing the events of every service to its own file
+/// in the log directory, see the [module documentation](./index.html)
+pub struct PerServiceLogs {
+    dir: PathBuf,
+    fields: DefaultFields,
+    /// the files opened so far, by service identifier
+    files: Mutex<HashMap<String, RollingFileAppender>>,
+}
+
+/// the `service_identifier` recorded on a span, kept in its extensions
+struct ServiceLog(String);
+
+/// find the `service_identifier` among the fields of a span
+#[derive(Default)]
+struct ServiceIdentifierVisitor(Option<String>);
+
+impl PerServiceLogs {
+    /// write the logs in `dir`, created if needed
+    ///
+    /// the files are opened on the first event of their service and are
+    /// appended to, never rotated.
+    pub fn new(dir: impl AsRef<Path>) -> io::Result<Self> {
+        std::fs::create_dir_all(dir.as_ref())?;
+
+        Ok(Self {
+            dir: dir.as_ref().to_path_buf(),
+            fields: DefaultFields::new(),
+            files: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// `<timestamp> <level> <target>: <fields>`, as the `fmt` subscriber
+    fn format(&self, event: &Event<'_>) -> Result<String, fmt::Error> {
+        let metadata = event.metadata();
+        let mut line = String::new();
+
+        SystemTime.format_time(&mut line)?;
+        write!(line, " {:>5} {}: ", metadata.level(), metadata.target())?;
+        self.fields.format_fields(&mut line, event)?;
+        line.push('\n');
+
+        Ok(line)
+    }
+
+    fn write(&self, file: &str, line: &str) {
+        let mut files = self.files.lock().unwrap();
+        let appender = files
+            .entry(file.to_owned())
+            .or_insert_with(|| rolling::never(&self.dir, format!("{}.log", file)));
+
+        // the appender is buffered: flush every line so none is lost when
+        // the process exits, the global subscriber is never dropped
+        let written = appender
+            .write_all(line.as_bytes())
+            .and_then(|()| appender.flush());
+        if written.is_err() {
+            // nowhere to report it: logging the error would come back here
+        }
+    }
+}
+
+impl<S> Layer<S> for PerServiceLogs
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let mut visitor = ServiceIdentifierVisitor::default();
+        attrs.record(&mut visitor);
+
+        if let (Some(service_identifier), Some(span)) = (visitor.0, ctx.span(id)) {
+            span.extensions_mut().insert(ServiceLog(service_identifier));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let service_identifier = ctx.event_scope(event).and_then(|mut scope| {
+            scope.find_map(|span| {
+                span.extensions()
+                    .get::<ServiceLog>()
+                    .map(|ServiceLog(service_identifier)| service_identifier.clone())
+            })
+        });
+
+        if let Ok(line) = self.format(event) {
+            self.write(
+                service_identifier.as_deref().unwrap_or(DEFAULT_LOG_FILE),
+                &line,
+            );
+        }
+    }
+}
+
+impl Visit for ServiceIdentifierVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "service_identifier" {
+            self.0 = Some(value.to_owned());
+        }
+    }
+
+    fn record_debug(&mut self, _: &Field, _: &dyn fmt::Debug) {}
+}
+
+/// install the [`PerServiceLogs`] layer writing in `dir` as the global
+/// subscriber, see `WatchdogBuilder::per_service_log_dir`
+///
+/// [`PerServiceLogs`]: ./struct.PerServiceLogs.html
+pub(crate) fn install(dir: &Path) {
+    use tracing_subscriber::layer::SubscriberExt as _;
+
+    let layer = match PerServiceLogs::new(dir) {
+        Ok(layer) => layer,
+        Err(error) => {
+            tracing::error!(%error, dir = %dir.display(), "cannot create the log directory");
+            return;
+        }
+    };
+
+    if tracing::subscriber::set_global_default(Registry::default().with(layer)).is_err() {
+        tracing::warn!(
+            "a global subscriber is already set, add `organix::tracing::PerServiceLogs` to it instead"
+        );
+    }
+}
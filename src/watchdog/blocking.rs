@@ -0,0 +1,117 @@
+use crate::{
+    service::{Service, ShutdownReason, StatusReport},
+    watchdog::{WatchdogError, WatchdogQuery},
+};
+use std::{future::Future, sync::mpsc, time::Duration};
+use tokio::runtime::Handle;
+
+/// a [`WatchdogQuery`] for the synchronous code, every query blocks the
+/// current thread until the watchdog replied
+///
+/// the queries are run on the watchdog's runtime. Calling them from within
+/// a runtime fails with [`WatchdogError::BlockingInRuntime`] rather than
+/// blocking the runtime's thread, and with [`WatchdogError::WatchdogGone`]
+/// once the watchdog's runtime is shutdown.
+///
+/// see [`WatchdogMonitor::control_blocking`].
+///
+/// [`WatchdogQuery`]: ./struct.WatchdogQuery.html
+/// [`WatchdogError::BlockingInRuntime`]: ./enum.WatchdogError.html#variant.BlockingInRuntime
+/// [`WatchdogError::WatchdogGone`]: ./enum.WatchdogError.html#variant.WatchdogGone
+/// [`WatchdogMonitor::control_blocking`]: ./struct.WatchdogMonitor.html#method.control_blocking
+#[derive(Clone, Debug)]
+pub struct BlockingWatchdogQuery {
+    query: WatchdogQuery,
+}
+
+impl BlockingWatchdogQuery {
+    pub(crate) fn new(query: WatchdogQuery) -> Self {
+        Self { query }
+    }
+
+    /// run the `query` on the watchdog's runtime and wait for its result
+    fn block_on<F, Fut, R>(&self, context: &'static str, query: F) -> Result<R, WatchdogError>
+    where
+        F: FnOnce(WatchdogQuery) -> Fut,
+        Fut: Future<Output = Result<R, WatchdogError>> + Send + 'static,
+        R: Send + 'static,
+    {
+        if Handle::try_current().is_ok() {
+            return Err(WatchdogError::BlockingInRuntime);
+        }
+
+        let (reply, result) = mpsc::sync_channel(1);
+        let query = query(self.query.clone());
+        self.query.spawn(async move {
+            if reply.send(query.await).is_err() {
+                // the caller is gone
+            }
+        });
+
+        // the query is dropped without replying if the watchdog's runtime
+        // is shutting down
+        result
+            .recv()
+            .unwrap_or(Err(WatchdogError::WatchdogGone { context }))
+    }
+
+    /// see [`WatchdogQuery::start`](./struct.WatchdogQuery.html#method.start)
+    pub fn start<T: Service>(&self) -> Result<(), WatchdogError> {
+        self.block_on("start query", |mut query| async move {
+            query.start::<T>().await
+        })
+    }
+
+    /// see [`WatchdogQuery::stop`](./struct.WatchdogQuery.html#method.stop)
+    pub fn stop<T: Service>(&self) -> Result<(), WatchdogError> {
+        self.stop_with_reason::<T>(ShutdownReason::UserRequested)
+    }
+
+    /// see [`WatchdogQuery::stop_with_reason`](./struct.WatchdogQuery.html#method.stop_with_reason)
+    pub fn stop_with_reason<T: Service>(
+        &self,
+        reason: ShutdownReason,
+    ) -> Result<(), WatchdogError> {
+        self.block_on("stop query", move |mut query| async move {
+            query.stop_with_reason::<T>(reason).await
+        })
+    }
+
+    /// see [`WatchdogQuery::status`](./struct.WatchdogQuery.html#method.status)
+    pub fn status<T: Service>(&self) -> Result<StatusReport, WatchdogError> {
+        self.block_on("status query", |mut query| async move {
+            query.status::<T>().await
+        })
+    }
+
+    /// see [`WatchdogQuery::status_all`](./struct.WatchdogQuery.html#method.status_all)
+    pub fn status_all(&self) -> Result<Vec<StatusReport>, WatchdogError> {
+        self.block_on("status query", |mut query| async move {
+            query.status_all().await
+        })
+    }
+
+    /// see [`WatchdogQuery::ping`](./struct.WatchdogQuery.html#method.ping)
+    pub fn ping(&self, timeout: Duration) -> Result<Duration, WatchdogError> {
+        self.block_on(
+            "ping",
+            move |mut query| async move { query.ping(timeout).await },
+        )
+    }
+
+    /// see [`WatchdogQuery::shutdown`](./struct.WatchdogQuery.html#method.shutdown)
+    pub fn shutdown(&self) -> Result<(), WatchdogError> {
+        self.block_on("shutdown query", |mut query| async move {
+            query.shutdown().await;
+            Ok(())
+        })
+    }
+
+    /// see [`WatchdogQuery::kill`](./struct.WatchdogQuery.html#method.kill)
+    pub fn kill(&self) -> Result<(), WatchdogError> {
+        self.block_on("kill query", |mut query| async move {
+            query.kill().await;
+            Ok(())
+        })
+    }
+}
@@ -1,12 +1,29 @@
 use crate::{
-    service::{Intercom, StatusReport},
-    watchdog::WatchdogError,
-    Service, ServiceIdentifier,
+    service::{
+        AdminRequest, Intercom, IntercomMsg, IntercomSender, OnServiceError, ServiceAdmin,
+        ShutdownReason, Status, StatusReader, StatusReport,
+    },
+    watchdog::{
+        dynamic::{DynamicService, Registered},
+        ReadOnlyWatchdogQuery, WatchdogError, WatchdogEvent,
+    },
+    Service, ServiceIdentifier, ServiceManager,
+};
+use futures_util::future::{join_all, AbortHandle};
+use std::{
+    any::Any,
+    collections::HashMap,
+    fmt,
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, Weak,
+    },
+    time::{Duration, Instant},
 };
-use std::{any::Any, fmt, future::Future};
 use tokio::{
     runtime::Handle,
-    sync::{mpsc, oneshot},
+    sync::{broadcast, mpsc, oneshot, watch},
     task::JoinHandle,
 };
 
@@ -19,6 +36,11 @@ pub(crate) enum ControlCommand {
         reply: Reply<Result<(), WatchdogError>>,
     },
     Stop {
+        service_identifier: ServiceIdentifier,
+        reason: ShutdownReason,
+        reply: Reply<Result<(), WatchdogError>>,
+    },
+    KillService {
         service_identifier: ServiceIdentifier,
         reply: Reply<Result<(), WatchdogError>>,
     },
@@ -30,17 +52,222 @@ pub(crate) enum ControlCommand {
         service_identifier: ServiceIdentifier,
         reply: Reply<Result<StatusReport, WatchdogError>>,
     },
+    HasEverStarted {
+        service_identifier: ServiceIdentifier,
+        reply: Reply<Result<bool, WatchdogError>>,
+    },
+    CurrentStatus {
+        service_identifier: ServiceIdentifier,
+        reply: Reply<Result<Status, WatchdogError>>,
+    },
+    StatusAll {
+        reply: Reply<Result<Vec<StatusReport>, WatchdogError>>,
+    },
+    SubscribeStatus {
+        service_identifier: ServiceIdentifier,
+        reply: Reply<Result<StatusReader, WatchdogError>>,
+    },
+    Reload {
+        timeout: Duration,
+        reply: Reply<Result<(), WatchdogError>>,
+    },
+    Batch {
+        commands: Vec<BatchCommand>,
+        reply: Reply<Result<Vec<Result<(), WatchdogError>>, WatchdogError>>,
+    },
+    Register {
+        service: DynamicService,
+        reply: Reply<Result<(), WatchdogError>>,
+    },
+    Ping {
+        reply: Reply<Result<(), WatchdogError>>,
+    },
+    Admin {
+        service_identifier: ServiceIdentifier,
+        reply: Reply<Result<Box<dyn Any + 'static + Send>, WatchdogError>>,
+    },
+    Freeze {
+        reply: Reply<Result<(), WatchdogError>>,
+    },
+    Unfreeze {
+        reply: Reply<Result<(), WatchdogError>>,
+    },
+}
+
+/// command that can be grouped with other commands and sent to the
+/// watchdog in one go with [`WatchdogQuery::batch`].
+///
+/// [`WatchdogQuery::batch`]: ./struct.WatchdogQuery.html#method.batch
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BatchCommand {
+    Start {
+        service_identifier: ServiceIdentifier,
+    },
+    Stop {
+        service_identifier: ServiceIdentifier,
+    },
 }
 
 #[derive(Debug)]
 pub(crate) struct Reply<T>(pub(crate) oneshot::Sender<T>);
 
+/// sending end of the watchdog's commands
+///
+/// the introspection commands (the status queries) go through their own
+/// lane so they cannot delay the control commands (start, stop...).
+#[derive(Clone, Debug)]
+pub(crate) struct CommandSender {
+    control: mpsc::Sender<ControlCommand>,
+    introspection: mpsc::Sender<ControlCommand>,
+    pub(crate) aborts: Aborts,
+}
+
+/// the abort handles of the tasks of the services, to abort them without
+/// going through the watchdog, see `WatchdogMonitor::abort_all`
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Aborts(Arc<Mutex<HashMap<ServiceIdentifier, AbortHandle>>>);
+
+/// receiving end of the watchdog's commands, see [`CommandSender`]
+pub(crate) struct CommandReceiver {
+    control: mpsc::Receiver<ControlCommand>,
+    introspection: mpsc::Receiver<ControlCommand>,
+    introspection_closed: bool,
+}
+
+/// create the command channel of the watchdog, every lane can hold
+/// `capacity` commands
+pub(crate) fn command_channel(capacity: usize) -> (CommandSender, CommandReceiver) {
+    let (control, control_receiver) = mpsc::channel(capacity);
+    let (introspection, introspection_receiver) = mpsc::channel(capacity);
+
+    (
+        CommandSender {
+            control,
+            introspection,
+            aborts: Aborts::default(),
+        },
+        CommandReceiver {
+            control: control_receiver,
+            introspection: introspection_receiver,
+            introspection_closed: false,
+        },
+    )
+}
+
+impl ControlCommand {
+    /// the command only reads the state of the services
+    fn is_introspection(&self) -> bool {
+        matches!(
+            self,
+            Self::Status { .. }
+                | Self::HasEverStarted { .. }
+                | Self::CurrentStatus { .. }
+                | Self::StatusAll { .. }
+                | Self::SubscribeStatus { .. }
+        )
+    }
+
+    /// the command changes the set or the state of the services, it is
+    /// queued while the watchdog is frozen, see `WatchdogQuery::freeze`
+    pub(crate) fn is_transition(&self) -> bool {
+        matches!(
+            self,
+            Self::Start { .. }
+                | Self::Stop { .. }
+                | Self::KillService { .. }
+                | Self::Reload { .. }
+                | Self::Batch { .. }
+                | Self::Register { .. }
+        )
+    }
+}
+
+impl CommandSender {
+    pub(crate) async fn send(&mut self, cc: ControlCommand) -> Result<(), ControlCommand> {
+        let lane = if cc.is_introspection() {
+            &mut self.introspection
+        } else {
+            &mut self.control
+        };
+
+        lane.send(cc).await.map_err(|mpsc::error::SendError(cc)| cc)
+    }
+}
+
+impl Aborts {
+    /// keep the `abort_handle` of the service's latest task, replacing the
+    /// one of its previous run
+    pub(crate) fn register(
+        &self,
+        service_identifier: ServiceIdentifier,
+        abort_handle: AbortHandle,
+    ) {
+        self.0
+            .lock()
+            .unwrap()
+            .insert(service_identifier, abort_handle);
+    }
+
+    /// abort the tasks of every services, aborting a task that already
+    /// finished does nothing
+    pub(crate) fn abort_all(&self) {
+        for abort_handle in self.0.lock().unwrap().values() {
+            abort_handle.abort();
+        }
+    }
+}
+
+impl CommandReceiver {
+    /// receive the next command, the control commands first
+    ///
+    /// returns `None` once the control lane is closed.
+    pub(crate) async fn recv(&mut self) -> Option<ControlCommand> {
+        if let Ok(command) = self.control.try_recv() {
+            return Some(command);
+        }
+
+        loop {
+            let introspection_closed = self.introspection_closed;
+            tokio::select! {
+                command = self.control.recv() => return command,
+                command = self.introspection.recv(), if !introspection_closed => match command {
+                    Some(command) => return Some(command),
+                    None => self.introspection_closed = true,
+                },
+            }
+        }
+    }
+}
+
 /// special access to the watchdog direct handle
 ///
 #[derive(Clone, Debug)]
 pub struct WatchdogQuery {
-    sender: mpsc::Sender<ControlCommand>,
+    sender: CommandSender,
+    handle: Handle,
+    services: &'static [ServiceIdentifier],
+    registered: Registered,
+    events: broadcast::Sender<WatchdogEvent>,
+    shutting_down: watch::Receiver<bool>,
+    drained: Arc<AtomicBool>,
+    tracing_prefix: Option<&'static str>,
+    on_service_error: Option<OnServiceError>,
+}
+
+/// a handle to the watchdog that does not keep its command channel open
+///
+/// see [`WatchdogMonitor::control_weak`].
+///
+/// [`WatchdogMonitor::control_weak`]: ./struct.WatchdogMonitor.html#method.control_weak
+#[derive(Clone, Debug)]
+pub struct WeakWatchdogQuery {
+    sender: Weak<CommandSender>,
+    running: Arc<AtomicBool>,
     handle: Handle,
+    services: &'static [ServiceIdentifier],
+    registered: Registered,
+    events: broadcast::Sender<WatchdogEvent>,
+    shutting_down: watch::Receiver<bool>,
 }
 
 impl<T> Reply<Result<T, WatchdogError>> {
@@ -58,12 +285,180 @@ impl<T> Reply<Result<T, WatchdogError>> {
     }
 }
 
+impl WeakWatchdogQuery {
+    pub(crate) fn new(
+        handle: Handle,
+        sender: &Arc<CommandSender>,
+        running: Arc<AtomicBool>,
+        services: &'static [ServiceIdentifier],
+        registered: Registered,
+        events: broadcast::Sender<WatchdogEvent>,
+        shutting_down: watch::Receiver<bool>,
+    ) -> Self {
+        Self {
+            sender: Arc::downgrade(sender),
+            running,
+            handle,
+            services,
+            registered,
+            events,
+            shutting_down,
+        }
+    }
+
+    /// get a `WatchdogQuery` if the watchdog is still running
+    pub fn upgrade(&self) -> Option<WatchdogQuery> {
+        if !self.running.load(Ordering::SeqCst) {
+            return None;
+        }
+
+        let sender = self.sender.upgrade()?;
+        Some(WatchdogQuery::new(
+            self.handle.clone(),
+            CommandSender::clone(&sender),
+            self.services,
+            self.registered.clone(),
+            self.events.clone(),
+            self.shutting_down.clone(),
+        ))
+    }
+}
+
 impl WatchdogQuery {
     /// This function creates a control handler from a given [`Watchdog`].
     ///
     /// [`Watchdog`]: ./struct.Watchdog.html
-    pub(crate) fn new(handle: Handle, sender: mpsc::Sender<ControlCommand>) -> Self {
-        Self { sender, handle }
+    pub(crate) fn new(
+        handle: Handle,
+        sender: CommandSender,
+        services: &'static [ServiceIdentifier],
+        registered: Registered,
+        events: broadcast::Sender<WatchdogEvent>,
+        shutting_down: watch::Receiver<bool>,
+    ) -> Self {
+        Self {
+            sender,
+            handle,
+            services,
+            registered,
+            events,
+            shutting_down,
+            drained: Arc::new(AtomicBool::new(false)),
+            tracing_prefix: None,
+            on_service_error: None,
+        }
+    }
+
+    /// see `WatchdogBuilder::tracing_prefix`, only set on the query the
+    /// watchdog gives to its services
+    pub(crate) fn set_tracing_prefix(&mut self, prefix: Option<&'static str>) {
+        self.tracing_prefix = prefix;
+    }
+
+    pub(crate) fn tracing_prefix(&self) -> Option<&'static str> {
+        self.tracing_prefix
+    }
+
+    /// see `WatchdogBuilder::on_service_error`, only set on the query the
+    /// watchdog gives to its services
+    pub(crate) fn set_on_service_error(&mut self, on_service_error: Option<OnServiceError>) {
+        self.on_service_error = on_service_error;
+    }
+
+    pub(crate) fn on_service_error(&self) -> Option<&OnServiceError> {
+        self.on_service_error.as_ref()
+    }
+
+    /// stop accepting new commands
+    ///
+    /// once drained, this `WatchdogQuery` and all its clones fail the
+    /// `start`, `stop`, `status` and `batch` queries with
+    /// [`WatchdogError::Draining`] without sending them to the watchdog.
+    /// The queries already sent are still processed.
+    ///
+    /// [`WatchdogError::Draining`]: ./enum.WatchdogError.html#variant.Draining
+    pub fn drain(&self) {
+        self.drained.store(true, Ordering::SeqCst);
+    }
+
+    /// check whether [`drain`](#method.drain) has been called on this
+    /// `WatchdogQuery` or one of its clones
+    pub fn is_drained(&self) -> bool {
+        self.drained.load(Ordering::SeqCst)
+    }
+
+    fn check_drained(&self) -> Result<(), WatchdogError> {
+        if self.is_drained() {
+            Err(WatchdogError::Draining)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// subscribe to the [`WatchdogEvent`]s of the app
+    ///
+    /// only the events emitted after subscribing are received.
+    ///
+    /// [`WatchdogEvent`]: ./enum.WatchdogEvent.html
+    pub fn subscribe(&self) -> broadcast::Receiver<WatchdogEvent> {
+        self.events.subscribe()
+    }
+
+    pub(crate) fn events(&self) -> &broadcast::Sender<WatchdogEvent> {
+        &self.events
+    }
+
+    pub(crate) fn aborts(&self) -> &Aborts {
+        &self.sender.aborts
+    }
+
+    pub(crate) fn emit(&self, event: WatchdogEvent) {
+        crate::watchdog::emit(&self.events, event)
+    }
+
+    /// future that resolves once the watchdog has been asked to shutdown
+    /// (or to be killed) or is gone
+    pub fn shutdown_signal(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut shutting_down = self.shutting_down.clone();
+
+        async move {
+            while !*shutting_down.borrow() {
+                if shutting_down.recv().await.is_none() {
+                    // the watchdog is gone
+                    break;
+                }
+            }
+        }
+    }
+
+    /// the identifiers of the services of the app, the services added with
+    /// [`register`](#method.register) are not listed
+    pub fn services(&self) -> &'static [ServiceIdentifier] {
+        self.services
+    }
+
+    /// a handle that can only query the state of the services, see
+    /// [`ReadOnlyWatchdogQuery`]
+    ///
+    /// [`ReadOnlyWatchdogQuery`]: ./struct.ReadOnlyWatchdogQuery.html
+    pub fn read_only(&self) -> ReadOnlyWatchdogQuery {
+        ReadOnlyWatchdogQuery::new(self.clone())
+    }
+
+    /// check whether a service with the given identifier is part of the app
+    /// or has been added with [`register`](#method.register)
+    ///
+    /// This does not query the watchdog so it is cheap to use before
+    /// issuing other commands.
+    pub fn is_registered(&self, service_identifier: ServiceIdentifier) -> bool {
+        self.services.contains(&service_identifier) || self.registered.contains(service_identifier)
+    }
+
+    /// check whether the service `T` is part of the app
+    ///
+    /// see [`is_registered`](#method.is_registered)
+    pub fn is_registered_service<T: Service>(&self) -> bool {
+        self.is_registered(T::SERVICE_IDENTIFIER)
     }
 
     /// retrieve an intercom object, allows to connect and send messages to
@@ -72,6 +467,38 @@ impl WatchdogQuery {
         Intercom::new(self.clone())
     }
 
+    /// connect to the service `T` and run `f` with the connected
+    /// [`Intercom`], for the one-off messages
+    ///
+    /// fails with the connection error without calling `f` if the service
+    /// cannot be connected to, otherwise returns the result of `f`.
+    ///
+    /// ```no_run
+    /// # use organix::{WatchdogError, WatchdogQuery, Service};
+    /// # async fn notify<T: Service>(query: &WatchdogQuery, msg: T::IntercomMsg) -> Result<(), WatchdogError> {
+    /// query
+    ///     .with_service::<T, _, _, _>(|mut intercom| async move { Ok(intercom.send(msg).await?) })
+    ///     .await
+    /// # }
+    /// ```
+    ///
+    /// [`Intercom`]: ./service/struct.Intercom.html
+    pub async fn with_service<T, F, Fut, R>(&self, f: F) -> Result<R, WatchdogError>
+    where
+        T: Service,
+        F: FnOnce(Intercom<T>) -> Fut,
+        Fut: Future<Output = Result<R, WatchdogError>>,
+    {
+        let mut intercom = self.intercom::<T>();
+        intercom.connect().await?;
+        f(intercom).await
+    }
+
+    /// the handle of the watchdog's runtime
+    pub(crate) fn handle(&self) -> &Handle {
+        &self.handle
+    }
+
     /// spawn a future in the associated runtime.
     pub(crate) fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
     where
@@ -82,7 +509,13 @@ impl WatchdogQuery {
     }
 
     /// query the status report of a given service
+    ///
+    /// gathering the intercom stats of the report has a cost: to poll the
+    /// liveness of a service use [`current_status`](#method.current_status)
+    /// instead.
     pub async fn status<T: Service>(&mut self) -> Result<StatusReport, WatchdogError> {
+        self.check_drained()?;
+
         let (reply, receiver) = oneshot::channel();
         self.send(ControlCommand::Status {
             service_identifier: T::SERVICE_IDENTIFIER,
@@ -102,6 +535,353 @@ impl WatchdogQuery {
         }
     }
 
+    /// check whether the service reached `Started` at least once
+    ///
+    /// cheaper than querying the full [`status`](#method.status) report, to
+    /// gate the readiness of the app on all the services having started.
+    pub async fn has_ever_started<T: Service>(&mut self) -> Result<bool, WatchdogError> {
+        self.check_drained()?;
+
+        let (reply, receiver) = oneshot::channel();
+        self.send(ControlCommand::HasEverStarted {
+            service_identifier: T::SERVICE_IDENTIFIER,
+            reply: Reply(reply),
+        })
+        .await;
+
+        match receiver.await {
+            Ok(v) => v,
+            Err(reason) => {
+                tracing::error!(%reason, context = "has ever started query", "The watchdog didn't reply");
+                Err(WatchdogError::NoReply {
+                    reason,
+                    context: "has ever started query",
+                })
+            }
+        }
+    }
+
+    /// query the status reports of all the services, in one round-trip
+    ///
+    /// this is the `Organix::services_dyn` view of the running app: the
+    /// services of the app are reported in the order of the fields, then
+    /// the services registered in the running watchdog.
+    pub async fn status_all(&mut self) -> Result<Vec<StatusReport>, WatchdogError> {
+        self.check_drained()?;
+
+        let (reply, receiver) = oneshot::channel();
+        self.send(ControlCommand::StatusAll {
+            reply: Reply(reply),
+        })
+        .await;
+
+        match receiver.await {
+            Ok(v) => v,
+            Err(reason) => {
+                tracing::error!(%reason, context = "status all query", "The watchdog didn't reply");
+                Err(WatchdogError::NoReply {
+                    reason,
+                    context: "status all query",
+                })
+            }
+        }
+    }
+
+    /// query the current status of a given service
+    ///
+    /// cheaper than the full [`status`](#method.status) report as the
+    /// intercom stats are not gathered.
+    pub async fn current_status<T: Service>(&mut self) -> Result<Status, WatchdogError> {
+        self.check_drained()?;
+
+        let (reply, receiver) = oneshot::channel();
+        self.send(ControlCommand::CurrentStatus {
+            service_identifier: T::SERVICE_IDENTIFIER,
+            reply: Reply(reply),
+        })
+        .await;
+
+        match receiver.await {
+            Ok(v) => v,
+            Err(reason) => {
+                tracing::error!(%reason, context = "current status query", "The watchdog didn't reply");
+                Err(WatchdogError::NoReply {
+                    reason,
+                    context: "current status query",
+                })
+            }
+        }
+    }
+
+    /// check whether the service is `Started`, see
+    /// [`current_status`](#method.current_status)
+    pub async fn is_running<T: Service>(&mut self) -> Result<bool, WatchdogError> {
+        self.current_status::<T>()
+            .await
+            .map(|status| status.is_started())
+    }
+
+    /// see [`current_status`](#method.current_status)
+    pub async fn is_starting<T: Service>(&mut self) -> Result<bool, WatchdogError> {
+        self.current_status::<T>()
+            .await
+            .map(|status| status.is_starting())
+    }
+
+    /// see [`current_status`](#method.current_status)
+    pub async fn is_shutting_down<T: Service>(&mut self) -> Result<bool, WatchdogError> {
+        self.current_status::<T>()
+            .await
+            .map(|status| status.is_shutting_down())
+    }
+
+    /// the reader of the status of the given service, notified every time
+    /// the status is updated (across the restarts too)
+    async fn subscribe_status(
+        &mut self,
+        service_identifier: ServiceIdentifier,
+    ) -> Result<StatusReader, WatchdogError> {
+        self.check_drained()?;
+
+        let (reply, receiver) = oneshot::channel();
+        self.send(ControlCommand::SubscribeStatus {
+            service_identifier,
+            reply: Reply(reply),
+        })
+        .await;
+
+        match receiver.await {
+            Ok(v) => v,
+            Err(reason) => {
+                tracing::error!(%reason, context = "subscribe status query", "The watchdog didn't reply");
+                Err(WatchdogError::NoReply {
+                    reason,
+                    context: "subscribe status query",
+                })
+            }
+        }
+    }
+
+    /// wait for all the given `services` to be `Started`
+    ///
+    /// the services are not started: use it to gate the readiness of the app
+    /// on a subset of its services, started elsewhere. Fails with
+    /// [`WatchdogError::NotReady`] listing the services that did not reach
+    /// `Started` within the `timeout`.
+    ///
+    /// This requires the `time` driver on the caller's runtime.
+    ///
+    /// [`WatchdogError::NotReady`]: ./enum.WatchdogError.html#variant.NotReady
+    pub async fn await_ready(
+        &mut self,
+        services: &[ServiceIdentifier],
+        timeout: Duration,
+    ) -> Result<(), WatchdogError> {
+        let mut readers = Vec::with_capacity(services.len());
+        for &service_identifier in services {
+            readers.push((
+                service_identifier,
+                self.subscribe_status(service_identifier).await?,
+            ));
+        }
+
+        let started = join_all(readers.iter_mut().map(|(_, reader)| async move {
+            while !reader.status().is_started() {
+                if reader.updated().await.is_none() {
+                    // the service is gone
+                    break;
+                }
+            }
+        }));
+        if tokio::time::timeout(timeout, started).await.is_err() {
+            // the services not started are reported below
+        }
+
+        let services: Vec<_> = readers
+            .iter()
+            .filter(|(_, reader)| !reader.status().is_started())
+            .map(|(service_identifier, _)| *service_identifier)
+            .collect();
+        if services.is_empty() {
+            Ok(())
+        } else {
+            tracing::error!(?services, "services not ready in time");
+            Err(WatchdogError::NotReady { services })
+        }
+    }
+
+    /// same as [`status`] but fails with [`WatchdogError::Timeout`] if the
+    /// watchdog did not reply within the given `timeout`
+    ///
+    /// This requires the `time` driver on the caller's runtime.
+    ///
+    /// [`status`]: #method.status
+    /// [`WatchdogError::Timeout`]: ./enum.WatchdogError.html#variant.Timeout
+    pub async fn status_timeout<T: Service>(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<StatusReport, WatchdogError> {
+        with_timeout(timeout, "status query", self.status::<T>()).await
+    }
+
+    /// the `IntercomSender` of the service `service_identifier`, whose
+    /// intercom messages are `M`
+    ///
+    /// used by the `{App}Handles` generated by `#[derive(Organix)]`, see
+    /// `intercom` for the typed version.
+    #[doc(hidden)]
+    pub async fn intercom_sender<M: IntercomMsg>(
+        &mut self,
+        service_identifier: ServiceIdentifier,
+    ) -> Result<IntercomSender<M>, WatchdogError> {
+        self.check_drained()?;
+
+        let (reply, receiver) = oneshot::channel();
+        self.send(ControlCommand::Intercom {
+            service_identifier,
+            reply: Reply(reply),
+        })
+        .await;
+
+        let intercom = match receiver.await {
+            Ok(result) => result?,
+            Err(reason) => {
+                tracing::error!(%reason, context = "intercom query", "The watchdog didn't reply");
+                return Err(WatchdogError::NoReply {
+                    reason,
+                    context: "intercom query",
+                });
+            }
+        };
+        match intercom.downcast::<IntercomSender<M>>() {
+            Ok(intercom) => Ok(*intercom),
+            Err(_) => unreachable!(
+                "cannot downcast the intercom object of {} to {}",
+                service_identifier,
+                std::any::type_name::<IntercomSender<M>>(),
+            ),
+        }
+    }
+
+    /// send the admin command `cmd` to the service `T` and wait for its
+    /// reply, see `ServiceState::admin_rx`
+    ///
+    /// fails with [`WatchdogError::CannotConnectToService`] if the service
+    /// is not running, did not open its admin channel or stopped without
+    /// replying.
+    ///
+    /// [`WatchdogError::CannotConnectToService`]: ./enum.WatchdogError.html#variant.CannotConnectToService
+    pub async fn admin<T: ServiceAdmin>(
+        &mut self,
+        cmd: T::AdminCmd,
+    ) -> Result<T::AdminReply, WatchdogError> {
+        self.check_drained()?;
+
+        let (reply, receiver) = oneshot::channel();
+        self.send(ControlCommand::Admin {
+            service_identifier: T::SERVICE_IDENTIFIER,
+            reply: Reply(reply),
+        })
+        .await;
+
+        let admin = match receiver.await {
+            Ok(result) => result?,
+            Err(reason) => {
+                tracing::error!(%reason, context = "admin query", "The watchdog didn't reply");
+                return Err(WatchdogError::NoReply {
+                    reason,
+                    context: "admin query",
+                });
+            }
+        };
+        let mut admin = match admin.downcast::<mpsc::Sender<AdminRequest<T>>>() {
+            Ok(admin) => *admin,
+            Err(_) => unreachable!(
+                "cannot downcast the admin object to the channel of {}",
+                std::any::type_name::<T>(),
+            ),
+        };
+
+        let cannot_connect = WatchdogError::CannotConnectToService {
+            service_identifier: T::SERVICE_IDENTIFIER,
+            retry_attempted: false,
+        };
+        let (reply, replied) = oneshot::channel();
+        if admin.send(AdminRequest::new(cmd, reply)).await.is_err() {
+            return Err(cannot_connect);
+        }
+        replied.await.map_err(|_| cannot_connect)
+    }
+
+    /// check the watchdog is responsive, returns the round-trip time of a
+    /// command through the watchdog
+    ///
+    /// the watchdog answers the ping as soon as it receives it, but it
+    /// processes the commands one after the other: a slow command (e.g. a
+    /// status query waiting on a busy service) delays the ping too. Fails
+    /// with [`WatchdogError::Timeout`] if the watchdog did not reply within
+    /// the given `timeout`.
+    ///
+    /// This requires the `time` driver on the caller's runtime.
+    ///
+    /// [`WatchdogError::Timeout`]: ./enum.WatchdogError.html#variant.Timeout
+    pub async fn ping(&mut self, timeout: Duration) -> Result<Duration, WatchdogError> {
+        let sent_at = Instant::now();
+        let (reply, receiver) = oneshot::channel();
+
+        with_timeout(timeout, "ping", async {
+            self.send(ControlCommand::Ping {
+                reply: Reply(reply),
+            })
+            .await;
+
+            match receiver.await {
+                Ok(result) => result,
+                Err(reason) => {
+                    tracing::error!(%reason, context = "ping", "The watchdog didn't reply");
+                    Err(WatchdogError::NoReply {
+                        reason,
+                        context: "ping",
+                    })
+                }
+            }
+        })
+        .await?;
+
+        Ok(sent_at.elapsed())
+    }
+
+    /// send all the `commands` to the watchdog in one round-trip
+    ///
+    /// The commands are executed sequentially, in the given order. A failing
+    /// command does not prevent the next commands to be executed: the result
+    /// of every command is returned, in the same order as the commands.
+    pub async fn batch(
+        &mut self,
+        commands: Vec<BatchCommand>,
+    ) -> Result<Vec<Result<(), WatchdogError>>, WatchdogError> {
+        self.check_drained()?;
+
+        let (reply, receiver) = oneshot::channel();
+
+        let command = ControlCommand::Batch {
+            commands,
+            reply: Reply(reply),
+        };
+        self.send(command).await;
+
+        match receiver.await {
+            Ok(result) => result,
+            Err(reason) => {
+                tracing::error!(%reason, context = "batch query", "The watchdog didn't reply");
+                Err(WatchdogError::NoReply {
+                    reason,
+                    context: "batch query",
+                })
+            }
+        }
+    }
+
     pub(crate) async fn send(&mut self, cc: ControlCommand) {
         if self.sender.send(cc).await.is_err() {
             // ignore the case where the watchdog is already gone
@@ -110,7 +890,9 @@ impl WatchdogQuery {
 
     /// shutdown the watchdog
     ///
-    /// Reminder: calling this function will shutdown all the services
+    /// Reminder: calling this function will shutdown all the services. They
+    /// are only given time to stop with
+    /// `WatchdogBuilder::total_shutdown_timeout`.
     pub async fn shutdown(&mut self) {
         self.send(ControlCommand::Shutdown).await
     }
@@ -122,8 +904,106 @@ impl WatchdogQuery {
         self.send(ControlCommand::Kill).await
     }
 
+    /// stop all the running services and start them again, without
+    /// stopping the watchdog
+    ///
+    /// every service is prepared again from a new `ServiceState`, picking up
+    /// its new settings. The services are stopped with
+    /// `ShutdownReason::Reload` and are given `timeout` to stop, the ones
+    /// still running are then killed. The stopped services are not started.
+    ///
+    /// the watchdog does not process any other command until the reload is
+    /// complete. A service failing to start again does not prevent the other
+    /// services to be started: the reload fails with
+    /// [`WatchdogError::ReloadFailed`] listing the services left stopped.
+    ///
+    /// [`WatchdogError::ReloadFailed`]: ./enum.WatchdogError.html#variant.ReloadFailed
+    pub async fn reload(&mut self, timeout: Duration) -> Result<(), WatchdogError> {
+        self.check_drained()?;
+
+        let (reply, receiver) = oneshot::channel();
+        self.send(ControlCommand::Reload {
+            timeout,
+            reply: Reply(reply),
+        })
+        .await;
+
+        match receiver.await {
+            Ok(v) => v,
+            Err(reason) => {
+                tracing::error!(%reason, context = "reload query", "The watchdog didn't reply");
+                Err(WatchdogError::NoReply {
+                    reason,
+                    context: "reload query",
+                })
+            }
+        }
+    }
+
+    /// hold the services transitions until [`unfreeze`], e.g. for a
+    /// consistent backup of the services' states
+    ///
+    /// while frozen the start, stop, kill, reload, batch and register
+    /// commands are queued: their queries only return once the watchdog is
+    /// unfrozen and processed them, in order. The other queries (status,
+    /// ping, intercom...) are still answered. Shutting down or killing the
+    /// watchdog is not held: the queued commands are then dropped and their
+    /// queries fail with [`WatchdogError::NoReply`].
+    ///
+    /// the restarts of the services (see `ServiceState::request_restart`)
+    /// are held too. Freezing a frozen watchdog does nothing.
+    ///
+    /// [`unfreeze`]: #method.unfreeze
+    /// [`WatchdogError::NoReply`]: ./enum.WatchdogError.html#variant.NoReply
+    pub async fn freeze(&mut self) -> Result<(), WatchdogError> {
+        self.check_drained()?;
+
+        let (reply, receiver) = oneshot::channel();
+        self.send(ControlCommand::Freeze {
+            reply: Reply(reply),
+        })
+        .await;
+
+        match receiver.await {
+            Ok(v) => v,
+            Err(reason) => {
+                tracing::error!(%reason, context = "freeze query", "The watchdog didn't reply");
+                Err(WatchdogError::NoReply {
+                    reason,
+                    context: "freeze query",
+                })
+            }
+        }
+    }
+
+    /// process the commands queued since [`freeze`], and the new ones
+    ///
+    /// [`freeze`]: #method.freeze
+    pub async fn unfreeze(&mut self) -> Result<(), WatchdogError> {
+        self.check_drained()?;
+
+        let (reply, receiver) = oneshot::channel();
+        self.send(ControlCommand::Unfreeze {
+            reply: Reply(reply),
+        })
+        .await;
+
+        match receiver.await {
+            Ok(v) => v,
+            Err(reason) => {
+                tracing::error!(%reason, context = "unfreeze query", "The watchdog didn't reply");
+                Err(WatchdogError::NoReply {
+                    reason,
+                    context: "unfreeze query",
+                })
+            }
+        }
+    }
+
     /// require the watchdog to start the given service if not already started
     pub async fn start<T: Service>(&mut self) -> Result<(), WatchdogError> {
+        self.check_drained()?;
+
         let (reply, receiver) = oneshot::channel();
 
         let command = ControlCommand::Start {
@@ -144,12 +1024,94 @@ impl WatchdogQuery {
         }
     }
 
+    /// add the service managed by `service_manager` to the running watchdog
+    ///
+    /// the service can then be started, stopped, queried and connected to
+    /// like the services of the app. It is stopped when the watchdog is
+    /// shutdown, after the services of the app. The runtime the
+    /// `service_manager` was created with must outlive the watchdog.
+    ///
+    /// fails with [`WatchdogError::ServiceAlreadyRegistered`] if a service
+    /// with the same identifier is already managed by the watchdog.
+    ///
+    /// [`WatchdogError::ServiceAlreadyRegistered`]: ./enum.WatchdogError.html#variant.ServiceAlreadyRegistered
+    pub async fn register<T>(
+        &mut self,
+        service_manager: ServiceManager<T>,
+    ) -> Result<(), WatchdogError>
+    where
+        T: Service,
+        T::IntercomMsg: Send,
+    {
+        self.check_drained()?;
+
+        let (reply, receiver) = oneshot::channel();
+
+        let command = ControlCommand::Register {
+            service: DynamicService(Box::new(service_manager)),
+            reply: Reply(reply),
+        };
+        self.send(command).await;
+
+        match receiver.await {
+            Ok(result) => result,
+            Err(reason) => {
+                tracing::error!(%reason, context = "register query", "The watchdog didn't reply");
+                Err(WatchdogError::NoReply {
+                    reason,
+                    context: "register query",
+                })
+            }
+        }
+    }
+
+    /// same as [`start`] but fails with [`WatchdogError::Timeout`] if the
+    /// watchdog did not reply within the given `timeout`
+    ///
+    /// This requires the `time` driver on the caller's runtime.
+    ///
+    /// [`start`]: #method.start
+    /// [`WatchdogError::Timeout`]: ./enum.WatchdogError.html#variant.Timeout
+    pub async fn start_timeout<T: Service>(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<(), WatchdogError> {
+        with_timeout(timeout, "start query", self.start::<T>()).await
+    }
+
     /// require the watchdog to stop the given service if not already stopped
     pub async fn stop<T: Service>(&mut self) -> Result<(), WatchdogError> {
+        self.stop_with_reason::<T>(ShutdownReason::UserRequested)
+            .await
+    }
+
+    /// same as [`stop`](#method.stop) but with the `reason` the service is
+    /// stopped for (see `ServiceState::shutdown_reason`)
+    pub async fn stop_with_reason<T: Service>(
+        &mut self,
+        reason: ShutdownReason,
+    ) -> Result<(), WatchdogError> {
+        self.stop_by_identifier(T::SERVICE_IDENTIFIER, reason).await
+    }
+
+    /// same as [`stop_with_reason`] but with the identifier of the service,
+    /// to stop the services listed by [`status_all`] without knowing their
+    /// types
+    ///
+    /// [`stop_with_reason`]: #method.stop_with_reason
+    /// [`status_all`]: #method.status_all
+    pub async fn stop_by_identifier(
+        &mut self,
+        service_identifier: ServiceIdentifier,
+        reason: ShutdownReason,
+    ) -> Result<(), WatchdogError> {
+        self.check_drained()?;
+
         let (reply, receiver) = oneshot::channel();
 
         let command = ControlCommand::Stop {
-            service_identifier: T::SERVICE_IDENTIFIER,
+            service_identifier,
+            reason,
             reply: Reply(reply),
         };
         self.send(command).await;
@@ -165,6 +1127,65 @@ impl WatchdogQuery {
             }
         }
     }
+
+    /// same as [`stop`] but fails with [`WatchdogError::Timeout`] if the
+    /// watchdog did not reply within the given `timeout`
+    ///
+    /// This requires the `time` driver on the caller's runtime.
+    ///
+    /// [`stop`]: #method.stop
+    /// [`WatchdogError::Timeout`]: ./enum.WatchdogError.html#variant.Timeout
+    pub async fn stop_timeout<T: Service>(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<(), WatchdogError> {
+        with_timeout(timeout, "stop query", self.stop::<T>()).await
+    }
+
+    /// require the watchdog to kill the given service right away
+    ///
+    /// unlike [`stop`](#method.stop) this does not wait for the service to
+    /// shutdown gracefully: use it on a service that is wedged, ignoring
+    /// its shutdown. The other services are not affected.
+    pub async fn kill_service<T: Service>(&mut self) -> Result<(), WatchdogError> {
+        self.check_drained()?;
+
+        let (reply, receiver) = oneshot::channel();
+
+        let command = ControlCommand::KillService {
+            service_identifier: T::SERVICE_IDENTIFIER,
+            reply: Reply(reply),
+        };
+        self.send(command).await;
+
+        match receiver.await {
+            Ok(result) => result,
+            Err(reason) => {
+                tracing::error!(%reason, context = "kill query", "The watchdog didn't reply");
+                Err(WatchdogError::NoReply {
+                    reason,
+                    context: "kill query",
+                })
+            }
+        }
+    }
+}
+
+async fn with_timeout<F, R>(
+    timeout: Duration,
+    context: &'static str,
+    query: F,
+) -> Result<R, WatchdogError>
+where
+    F: Future<Output = Result<R, WatchdogError>>,
+{
+    match tokio::time::timeout(timeout, query).await {
+        Ok(result) => result,
+        Err(_) => {
+            tracing::error!(context, "The watchdog didn't reply in time");
+            Err(WatchdogError::Timeout { context })
+        }
+    }
 }
 
 impl fmt::Display for ControlCommand {
@@ -178,12 +1199,118 @@ impl fmt::Display for ControlCommand {
             Self::Stop {
                 service_identifier, ..
             } => write!(f, "stop service '{}'", service_identifier),
+            Self::KillService {
+                service_identifier, ..
+            } => write!(f, "kill service '{}'", service_identifier),
             Self::Status {
                 service_identifier, ..
             } => write!(f, "get status of service '{}'", service_identifier),
+            Self::HasEverStarted {
+                service_identifier, ..
+            } => write!(f, "check service '{}' has ever started", service_identifier),
+            Self::CurrentStatus {
+                service_identifier, ..
+            } => write!(f, "get current status of service '{}'", service_identifier),
+            Self::StatusAll { .. } => f.write_str("get status of all the services"),
+            Self::SubscribeStatus {
+                service_identifier, ..
+            } => write!(
+                f,
+                "subscribe to the status of service '{}'",
+                service_identifier
+            ),
             Self::Intercom {
                 service_identifier, ..
             } => write!(f, "get intercom with service '{}'", service_identifier),
+            Self::Reload { .. } => f.write_str("reload"),
+            Self::Batch { commands, .. } => write!(f, "batch of {} commands", commands.len()),
+            Self::Register { service, .. } => {
+                write!(f, "register service '{}'", service.0.service_identifier())
+            }
+            Self::Ping { .. } => f.write_str("ping"),
+            Self::Admin {
+                service_identifier, ..
+            } => write!(f, "get admin channel of service '{}'", service_identifier),
+            Self::Freeze { .. } => f.write_str("freeze"),
+            Self::Unfreeze { .. } => f.write_str("unfreeze"),
         }
     }
 }
+
+impl BatchCommand {
+    /// start the service `T`, see [`WatchdogQuery::start`]
+    ///
+    /// [`WatchdogQuery::start`]: ./struct.WatchdogQuery.html#method.start
+    pub fn start<T: Service>() -> Self {
+        Self::Start {
+            service_identifier: T::SERVICE_IDENTIFIER,
+        }
+    }
+
+    /// stop the service `T`, see [`WatchdogQuery::stop`]
+    ///
+    /// [`WatchdogQuery::stop`]: ./struct.WatchdogQuery.html#method.stop
+    pub fn stop<T: Service>() -> Self {
+        Self::Stop {
+            service_identifier: T::SERVICE_IDENTIFIER,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status() -> ControlCommand {
+        ControlCommand::Status {
+            service_identifier: "service",
+            reply: Reply(oneshot::channel().0),
+        }
+    }
+
+    #[tokio::test]
+    async fn control_commands_received_first() {
+        let (mut sender, mut receiver) = command_channel(4);
+
+        for _ in 0..4 {
+            sender.send(status()).await.unwrap();
+        }
+        sender.send(ControlCommand::Shutdown).await.unwrap();
+
+        assert!(matches!(
+            receiver.recv().await,
+            Some(ControlCommand::Shutdown)
+        ));
+        for _ in 0..4 {
+            assert!(matches!(
+                receiver.recv().await,
+                Some(ControlCommand::Status { .. })
+            ));
+        }
+    }
+
+    /// the queries of a busy app only wait for room in the command channel
+    /// once every service has 4 of them in flight
+    #[test]
+    fn command_channel_back_pressure() {
+        use crate::watchdog::{command_channel_capacity, COMMAND_CHANNEL_MIN_CAPACITY};
+        use futures_util::future::FutureExt as _;
+
+        const SERVICES: usize = 25;
+
+        // number of queries sent before the senders have to wait for the
+        // watchdog, which is not receiving them
+        let in_flight = |capacity| {
+            let (mut sender, _receiver) = command_channel(capacity);
+            (0..)
+                .take_while(|_| sender.send(status()).now_or_never().is_some())
+                .count()
+        };
+
+        assert_eq!(in_flight(command_channel_capacity(SERVICES)), 4 * SERVICES);
+        assert_eq!(
+            in_flight(COMMAND_CHANNEL_MIN_CAPACITY),
+            COMMAND_CHANNEL_MIN_CAPACITY
+        );
+    }
+}
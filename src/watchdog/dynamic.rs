@@ -0,0 +1,99 @@
+use crate::{
+    service::{ManageServiceDyn, ShutdownReason},
+    watchdog::WatchdogError,
+    ServiceIdentifier,
+};
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, RwLock},
+};
+
+/// services registered in a running watchdog, see
+/// [`WatchdogQuery::register`]
+///
+/// the watchdog only looks for a service here if it is not one of the
+/// services of the `Organix` app.
+///
+/// [`WatchdogQuery::register`]: ./struct.WatchdogQuery.html#method.register
+#[derive(Default)]
+pub(crate) struct DynamicServices {
+    services: HashMap<ServiceIdentifier, Box<dyn ManageServiceDyn>>,
+    registered: Registered,
+}
+
+/// the identifiers of the services registered in the running watchdog,
+/// shared with the `WatchdogQuery`s so they know them without querying
+/// the watchdog
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Registered(Arc<RwLock<Vec<ServiceIdentifier>>>);
+
+/// the manager of a service to register in the watchdog
+pub(crate) struct DynamicService(pub(crate) Box<dyn ManageServiceDyn>);
+
+impl DynamicServices {
+    /// add the service, fails if a service with the same identifier is
+    /// already registered
+    pub(crate) fn register(&mut self, service: DynamicService) -> Result<(), WatchdogError> {
+        let service_identifier = service.0.service_identifier();
+        if self.services.contains_key(service_identifier) {
+            return Err(WatchdogError::ServiceAlreadyRegistered { service_identifier });
+        }
+
+        self.services.insert(service_identifier, service.0);
+        self.registered.0.write().unwrap().push(service_identifier);
+        Ok(())
+    }
+
+    pub(crate) fn registered(&self) -> Registered {
+        self.registered.clone()
+    }
+
+    pub(crate) fn get(
+        &self,
+        service_identifier: ServiceIdentifier,
+    ) -> Option<&dyn ManageServiceDyn> {
+        self.services.get(service_identifier).map(AsRef::as_ref)
+    }
+
+    pub(crate) fn get_mut(
+        &mut self,
+        service_identifier: ServiceIdentifier,
+    ) -> Option<&mut (dyn ManageServiceDyn + 'static)> {
+        self.services.get_mut(service_identifier).map(AsMut::as_mut)
+    }
+
+    pub(crate) fn services(&self) -> impl Iterator<Item = &dyn ManageServiceDyn> + '_ {
+        self.services.values().map(AsRef::as_ref)
+    }
+
+    pub(crate) fn identifiers(&self) -> impl Iterator<Item = ServiceIdentifier> + '_ {
+        self.services.keys().copied()
+    }
+
+    pub(crate) fn stop_all(&mut self, reason: ShutdownReason) {
+        for service in self.services.values_mut() {
+            service.stop(reason);
+        }
+    }
+
+    pub(crate) fn kill_all(&mut self) {
+        for service in self.services.values_mut() {
+            service.kill();
+        }
+    }
+}
+
+impl Registered {
+    pub(crate) fn contains(&self, service_identifier: ServiceIdentifier) -> bool {
+        self.0.read().unwrap().contains(&service_identifier)
+    }
+}
+
+impl fmt::Debug for DynamicService {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("DynamicService")
+            .field(&self.0.service_identifier())
+            .finish()
+    }
+}
@@ -0,0 +1,88 @@
+use crate::service::{BudgetResource, ServiceIdentifier};
+use std::{fmt, time::Duration};
+use tokio::sync::broadcast;
+
+/// capacity of the channel broadcasting the watchdog's events
+pub(crate) const EVENTS_CAPACITY: usize = 64;
+
+/// notable events happening to the services of the app
+///
+/// subscribe to the events with [`WatchdogQuery::subscribe`].
+///
+/// [`WatchdogQuery::subscribe`]: ./struct.WatchdogQuery.html#method.subscribe
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WatchdogEvent {
+    /// a service went over its `ResourceBudget`
+    BudgetExceeded {
+        service_identifier: ServiceIdentifier,
+        resource: BudgetResource,
+        limit: usize,
+        usage: usize,
+    },
+    /// a service panicked, see `StatusReport::last_panic`
+    ServicePanicked {
+        service_identifier: ServiceIdentifier,
+        message: String,
+    },
+    /// a service restarted too often and was marked `Failed`, see
+    /// `service::CrashLoop`
+    CrashLooping {
+        service_identifier: ServiceIdentifier,
+        restarts: usize,
+        window: Duration,
+    },
+    /// messages are waiting in the intercom of a running service but none
+    /// were received within `window`, see `WatchdogBuilder::stalled_consumer`
+    StalledConsumer {
+        service_identifier: ServiceIdentifier,
+        queue_depth: usize,
+        window: Duration,
+    },
+}
+
+/// log the `event` and send it to the subscribers, if any
+pub(crate) fn emit(events: &broadcast::Sender<WatchdogEvent>, event: WatchdogEvent) {
+    tracing::warn!(%event);
+    if events.send(event).is_err() {
+        // nobody is subscribed to the events
+    }
+}
+
+impl fmt::Display for WatchdogEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::BudgetExceeded {
+                service_identifier,
+                resource,
+                limit,
+                usage,
+            } => write!(
+                f,
+                "service '{}' exceeded its {} budget ({} > {})",
+                service_identifier, resource, usage, limit
+            ),
+            Self::ServicePanicked {
+                service_identifier,
+                message,
+            } => write!(f, "service '{}' panicked: {}", service_identifier, message),
+            Self::CrashLooping {
+                service_identifier,
+                restarts,
+                window,
+            } => write!(
+                f,
+                "service '{}' is crash looping ({} restarts within {:?})",
+                service_identifier, restarts, window
+            ),
+            Self::StalledConsumer {
+                service_identifier,
+                queue_depth,
+                window,
+            } => write!(
+                f,
+                "service '{}' is not receiving its intercom messages ({} queued, none received within {:?})",
+                service_identifier, queue_depth, window
+            ),
+        }
+    }
+}
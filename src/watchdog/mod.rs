@@ -1,28 +1,111 @@
+mod blocking;
 mod control_command;
+mod dynamic;
+mod event;
 mod monitor;
+mod read_only;
+mod stalled;
 
-pub(crate) use self::control_command::{ControlCommand, Reply};
-pub use self::{control_command::WatchdogQuery, monitor::WatchdogMonitor};
+pub use self::{
+    blocking::BlockingWatchdogQuery,
+    control_command::{BatchCommand, WatchdogQuery, WeakWatchdogQuery},
+    event::WatchdogEvent,
+    monitor::{WatchdogMonitor, WatchdogSnapshot},
+    read_only::ReadOnlyWatchdogQuery,
+};
+pub(crate) use self::{
+    control_command::{command_channel, CommandReceiver, CommandSender, ControlCommand, Reply},
+    event::emit,
+};
+use self::{dynamic::DynamicServices, stalled::StalledConsumer};
 use crate::{
-    runtime::Runtimes,
-    service::{ServiceError, ServiceIdentifier, StatusReport},
+    runtime::{ConfigureRuntime, RuntimeConfig, RuntimePlacement, Runtimes},
+    service::{
+        ManageServiceDyn, OnServiceError, ServiceError, ServiceErrorInfo, ServiceIdentifier,
+        ShutdownReason, Status, StatusReader, StatusReport,
+    },
+    trace,
 };
 use async_trait::async_trait;
-use std::{any::Any, fmt};
+use std::{
+    any::Any,
+    collections::VecDeque,
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 use thiserror::Error;
-use tokio::sync::{mpsc, oneshot};
+use tokio::sync::{broadcast, oneshot, watch};
 
 /// trait to define the different core services and their
 /// associated metadata
 #[async_trait]
 pub trait Organix: Send + Sync {
+    /// the number of services managed by the app (skipped fields
+    /// are not counted)
+    const SERVICE_COUNT: usize;
+
+    /// the identifiers of the services managed by the app
+    const SERVICE_IDENTIFIERS: &'static [ServiceIdentifier];
+
     fn new(_: &mut Runtimes) -> Self;
 
-    fn stop(&mut self, service_identifier: ServiceIdentifier) -> Result<(), WatchdogError>;
+    /// the shutdown group of the service, see
+    /// [`WatchdogBuilder::shutdown_order`]
+    ///
+    /// [`WatchdogBuilder::shutdown_order`]: ./struct.WatchdogBuilder.html#method.shutdown_order
+    fn shutdown_group(_service_identifier: ServiceIdentifier) -> Option<&'static str> {
+        None
+    }
+
+    /// the runtime every service has been placed on
+    fn placement(&self) -> Vec<(ServiceIdentifier, RuntimePlacement)>;
+
+    /// every services as a `ManageServiceDyn`, in the order of the fields,
+    /// to query them without knowing their types
+    ///
+    /// once the app is owned by a watchdog, use `WatchdogQuery::status_all`
+    /// and `WatchdogQuery::stop_by_identifier` instead.
+    fn services_dyn(&self) -> Vec<&dyn ManageServiceDyn>;
+
+    /// same as `services_dyn`, to also start or stop them
+    fn services_dyn_mut(&mut self) -> Vec<&mut dyn ManageServiceDyn>;
+
+    fn stop(
+        &mut self,
+        service_identifier: ServiceIdentifier,
+        reason: ShutdownReason,
+    ) -> Result<(), WatchdogError>;
+
+    /// ask all the services to shutdown
+    fn stop_all(&mut self, reason: ShutdownReason);
+
+    /// kill all the services that are still running
+    fn kill_all(&mut self);
+
+    /// kill the service, even if it is already shutting down
+    fn kill(&mut self, service_identifier: ServiceIdentifier) -> Result<(), WatchdogError>;
     async fn status(
         &mut self,
         service_identifier: ServiceIdentifier,
     ) -> Result<StatusReport, WatchdogError>;
+    fn has_ever_started(
+        &self,
+        service_identifier: ServiceIdentifier,
+    ) -> Result<bool, WatchdogError>;
+    /// the current status of the service, without its intercom stats
+    fn current_status(
+        &self,
+        service_identifier: ServiceIdentifier,
+    ) -> Result<Status, WatchdogError>;
+    /// the reader of the status of the service, notified of its updates
+    fn status_reader(
+        &self,
+        service_identifier: ServiceIdentifier,
+    ) -> Result<StatusReader, WatchdogError>;
     fn start(
         &mut self,
         service_identifier: ServiceIdentifier,
@@ -32,17 +115,52 @@ pub trait Organix: Send + Sync {
         &mut self,
         service_identifier: ServiceIdentifier,
     ) -> Result<Box<dyn Any + Send + 'static>, WatchdogError>;
+    /// the admin channel of the service, see `ServiceManager::admin`
+    fn admin(
+        &self,
+        service_identifier: ServiceIdentifier,
+    ) -> Result<Box<dyn Any + Send + 'static>, WatchdogError>;
 }
 
+/// minimal capacity of the watchdog's command channel
+const COMMAND_CHANNEL_MIN_CAPACITY: usize = 10;
+/// number of slots reserved in the command channel for every service
+const COMMAND_CHANNEL_CAPACITY_PER_SERVICE: usize = 4;
+/// default time given to a shutdown group to stop before stopping the
+/// next group, see `WatchdogBuilder::shutdown_group_timeout`
+const DEFAULT_SHUTDOWN_GROUP_TIMEOUT: Duration = Duration::from_secs(5);
+
 pub struct Watchdog<T: Organix> {
     services: T,
+    dynamic: DynamicServices,
+    shutdown_order: Vec<&'static str>,
+    shutdown_group_timeout: Duration,
+    total_shutdown_timeout: Option<Duration>,
     on_drop_send: oneshot::Sender<()>,
+    running: Arc<AtomicBool>,
+    shutting_down: watch::Sender<bool>,
+    /// see `WatchdogQuery::freeze`
+    frozen: bool,
+    /// the transitions received while frozen, processed once unfrozen
+    pending: VecDeque<ControlCommand>,
 }
 
 pub struct WatchdogBuilder<T>
 where
     T: Organix,
 {
+    shutdown_order: Vec<&'static str>,
+    shutdown_group_timeout: Duration,
+    total_shutdown_timeout: Option<Duration>,
+    max_individual_runtimes: Option<usize>,
+    watchdog_io: Option<bool>,
+    watchdog_time: Option<bool>,
+    tracing_prefix: Option<&'static str>,
+    on_service_error: Option<OnServiceError>,
+    stalled_consumer: Option<StalledConsumer>,
+    configure_runtime: Option<ConfigureRuntime>,
+    #[cfg(feature = "log-dir")]
+    per_service_log_dir: Option<std::path::PathBuf>,
     _marker: std::marker::PhantomData<T>,
 }
 
@@ -51,12 +169,18 @@ pub enum WatchdogError {
     #[error("Unknown service {service_identifier}, available services are {possible_values:?}")]
     UnknownService {
         service_identifier: ServiceIdentifier,
-        possible_values: &'static [ServiceIdentifier],
+        possible_values: Vec<ServiceIdentifier>,
+    },
+
+    #[error("Service {service_identifier} is already registered")]
+    ServiceAlreadyRegistered {
+        service_identifier: ServiceIdentifier,
     },
 
     #[error("Cannot start service {service_identifier}: {source}")]
     CannotStartService {
         service_identifier: ServiceIdentifier,
+        #[source]
         source: ServiceError,
     },
 
@@ -66,8 +190,27 @@ pub enum WatchdogError {
         retry_attempted: bool,
     },
 
+    #[error("The watchdog query is draining, no new commands are accepted")]
+    Draining,
+
+    #[error("Timed out waiting for the {context}")]
+    Timeout { context: &'static str },
+
+    #[error("Timed out waiting for the services to be started, not ready: {services:?}")]
+    NotReady { services: Vec<ServiceIdentifier> },
+
+    #[error("Cannot start the services {services:?} again, they are left stopped")]
+    ReloadFailed { services: Vec<ServiceIdentifier> },
+
+    #[error("Cannot block on the watchdog from within a runtime, use the WatchdogQuery instead")]
+    BlockingInRuntime,
+
+    #[error("The watchdog is gone before running the {context}")]
+    WatchdogGone { context: &'static str },
+
     #[error("The watchdog didn't reply to the {context}: {reason}")]
     NoReply {
+        #[source]
         reason: oneshot::error::RecvError,
         context: &'static str,
     },
@@ -80,96 +223,444 @@ where
     #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
         Self {
+            shutdown_order: Vec::new(),
+            shutdown_group_timeout: DEFAULT_SHUTDOWN_GROUP_TIMEOUT,
+            total_shutdown_timeout: None,
+            max_individual_runtimes: None,
+            watchdog_io: None,
+            watchdog_time: None,
+            tracing_prefix: None,
+            on_service_error: None,
+            stalled_consumer: None,
+            configure_runtime: None,
+            #[cfg(feature = "log-dir")]
+            per_service_log_dir: None,
             _marker: std::marker::PhantomData,
         }
     }
 
+    /// bound the whole graceful shutdown of the watchdog
+    ///
+    /// once shutdown, the watchdog waits for all the services to stop (in
+    /// the [`shutdown_order`]) for at most `timeout`. The services still
+    /// running are then killed.
+    ///
+    /// Without it the watchdog does not wait for the services, except
+    /// between the shutdown groups: the services are asked to stop and the
+    /// ones still running (the services without a shutdown group, the
+    /// last group, or all of them without a shutdown order) are killed
+    /// right away. Set it for the services that need time to stop, e.g. to
+    /// drain their intercom (see `ServiceState::drain_on_shutdown`).
+    ///
+    /// [`shutdown_order`]: #method.shutdown_order
+    pub fn total_shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.total_shutdown_timeout = Some(timeout);
+        self
+    }
+
+    /// warn if the app has more than `max` individual runtimes, see
+    /// `Runtimes::set_max_individuals`
+    pub fn max_individual_runtimes(mut self, max: usize) -> Self {
+        self.max_individual_runtimes = Some(max);
+        self
+    }
+
+    /// stop the services by groups when the watchdog is shutdown
+    ///
+    /// the services are put in groups with `#[shutdown(group = "...")]`.
+    /// The groups are stopped one after the other, in the given `order`,
+    /// the services of a group are stopped concurrently. A group is given
+    /// the [`shutdown_group_timeout`] to stop before the next one is
+    /// stopped. The services without a group (or with a group not listed)
+    /// are stopped last.
+    ///
+    /// [`shutdown_group_timeout`]: #method.shutdown_group_timeout
+    pub fn shutdown_order(mut self, order: &[&'static str]) -> Self {
+        self.shutdown_order = order.to_vec();
+        self
+    }
+
+    /// the time given to every shutdown group to stop before the next one
+    /// is stopped, 5 seconds by default, see [`shutdown_order`]
+    ///
+    /// [`shutdown_order`]: #method.shutdown_order
+    pub fn shutdown_group_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_group_timeout = timeout;
+        self
+    }
+
+    /// enable the `io` driver of the watchdog's runtime, to run tasks
+    /// needing it with `WatchdogMonitor::spawn`
+    ///
+    /// by default it is only enabled with the `signal` feature, which needs
+    /// it to listen for `ctrl-c`. Ignored by [`build_with_runtimes`].
+    ///
+    /// [`build_with_runtimes`]: #method.build_with_runtimes
+    pub fn watchdog_io(mut self, enabled: bool) -> Self {
+        self.watchdog_io = Some(enabled);
+        self
+    }
+
+    /// enable the `time` driver of the watchdog's runtime, enabled by default
+    ///
+    /// the watchdog needs it to time out the status queries, the graceful
+    /// shutdown and the reload: only disable it if the app uses none of
+    /// them. Ignored by [`build_with_runtimes`].
+    ///
+    /// [`build_with_runtimes`]: #method.build_with_runtimes
+    pub fn watchdog_time(mut self, enabled: bool) -> Self {
+        self.watchdog_time = Some(enabled);
+        self
+    }
+
+    /// name the app in the traces, to tell apart the logs of the `Organix`
+    /// apps of a same process
+    ///
+    /// the targets and the names of the `tracing` spans are static: the
+    /// `prefix` is recorded as the `app` field of the spans of the watchdog
+    /// and of the services instead, e.g. filter on `watchdog{app=myapp}`.
+    pub fn tracing_prefix(mut self, prefix: &'static str) -> Self {
+        self.tracing_prefix = Some(prefix);
+        self
+    }
+
+    /// report the panics of the services, and their tasks that could not
+    /// be joined, to `callback` (e.g. to forward them to an error-reporting
+    /// service)
+    ///
+    /// the callback is run by the watchdog: keep it short. Without it the
+    /// join failures are logged. Either way the panics are recorded in the
+    /// `StatusReport` and sent as `WatchdogEvent::ServicePanicked`.
+    pub fn on_service_error<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(ServiceIdentifier, ServiceErrorInfo) + Send + Sync + 'static,
+    {
+        self.on_service_error = Some(OnServiceError::new(callback));
+        self
+    }
+
+    /// detect the services that stopped receiving their intercom messages
+    ///
+    /// every `window` the intercoms are sampled: a running service with at
+    /// least `threshold` messages waiting in its intercom, and that did not
+    /// receive any since the previous sample, is reported once with a
+    /// `WatchdogEvent::StalledConsumer`. Disabled by default, it needs the
+    /// `time` driver of the watchdog's runtime.
+    pub fn stalled_consumer(mut self, window: Duration, threshold: usize) -> Self {
+        self.stalled_consumer = Some(StalledConsumer { window, threshold });
+        self
+    }
+
+    /// write the logs of every service to `<dir>/<service_identifier>.log`,
+    /// and the other logs to `<dir>/organix.log`
+    ///
+    /// the `organix::tracing::PerServiceLogs` layer is installed as the
+    /// global subscriber when the watchdog is built. If a global subscriber
+    /// is already set, a warning is logged and the layer should be added to
+    /// that subscriber instead.
+    #[cfg(feature = "log-dir")]
+    pub fn per_service_log_dir(mut self, dir: impl Into<std::path::PathBuf>) -> Self {
+        self.per_service_log_dir = Some(dir.into());
+        self
+    }
+
+    /// customize the config of the individual runtime of every service
+    /// before it is built, e.g. to size it from the number of CPUs
+    ///
+    /// `configure` is given the identifier of the service and its config as
+    /// set by the `#[runtime(...)]` attributes. The shared runtimes are not
+    /// affected, use [`build_with_runtimes`] to configure them.
+    ///
+    /// if the resulting config is invalid (e.g. `core_threads = Some(0)`),
+    /// the error is logged with the service identifier and the runtime is
+    /// built from the config set by the attributes instead.
+    ///
+    /// [`build_with_runtimes`]: #method.build_with_runtimes
+    pub fn configure_runtime<F>(mut self, configure: F) -> Self
+    where
+        F: Fn(ServiceIdentifier, &mut RuntimeConfig) + Send + Sync + 'static,
+    {
+        self.configure_runtime = Some(Box::new(configure));
+        self
+    }
+
     pub fn build(self) -> WatchdogMonitor
     where
         T: Organix + 'static,
     {
-        let mut runtimes = Runtimes::new().unwrap();
+        let mut config = RuntimeConfig::watchdog();
+        if let Some(io_driver) = self.watchdog_io {
+            config.io_driver = io_driver;
+        }
+        if let Some(time_driver) = self.watchdog_time {
+            config.time_driver = time_driver;
+        }
+
+        self.build_with_runtimes(Runtimes::with_watchdog(config).unwrap())
+    }
 
+    /// build the watchdog using the given `runtimes`
+    ///
+    /// This allows to prepare the runtimes beforehand (for example a shared
+    /// runtime with a specific number of threads). The `watchdog` and the
+    /// `shared` runtimes of the given `Runtimes` are used as is. The services'
+    /// individual runtimes are still created from the `Organix` app definition.
+    pub fn build_with_runtimes(self, mut runtimes: Runtimes) -> WatchdogMonitor
+    where
+        T: Organix + 'static,
+    {
+        #[cfg(feature = "log-dir")]
+        {
+            if let Some(dir) = &self.per_service_log_dir {
+                crate::tracing::install(dir);
+            }
+        }
+        if let Some(max) = self.max_individual_runtimes {
+            runtimes.set_max_individuals(max);
+        }
+        if let Some(configure) = self.configure_runtime {
+            runtimes.set_configure_runtime(configure);
+        }
         let services = T::new(&mut runtimes);
+        let placement = services.placement();
 
-        let (sender, receiver) = mpsc::channel(10);
+        let (sender, receiver) = command_channel(command_channel_capacity(T::SERVICE_COUNT));
         let (on_drop_send, on_drop_receive) = oneshot::channel();
+        let running = Arc::new(AtomicBool::new(true));
+        let (events, _) = broadcast::channel(event::EVENTS_CAPACITY);
+        let (shutting_down_sender, shutting_down) = watch::channel(false);
 
         let watchdog = Watchdog {
             on_drop_send,
             services,
+            dynamic: DynamicServices::default(),
+            shutdown_order: self.shutdown_order,
+            shutdown_group_timeout: self.shutdown_group_timeout,
+            total_shutdown_timeout: self.total_shutdown_timeout,
+            running: Arc::clone(&running),
+            shutting_down: shutting_down_sender,
+            frozen: false,
+            pending: VecDeque::new(),
         };
 
         let watchdog_query_handle = runtimes.watchdog().handle().clone();
+        let registered = watchdog.dynamic.registered();
 
-        let query = WatchdogQuery::new(watchdog_query_handle, sender.clone());
+        let mut query = WatchdogQuery::new(
+            watchdog_query_handle,
+            sender.clone(),
+            T::SERVICE_IDENTIFIERS,
+            registered.clone(),
+            events.clone(),
+            shutting_down.clone(),
+        );
+        query.set_tracing_prefix(self.tracing_prefix);
+        query.set_on_service_error(self.on_service_error);
 
+        if let Some(stalled_consumer) = self.stalled_consumer {
+            runtimes
+                .watchdog()
+                .handle()
+                .spawn(stalled_consumer.detect(query.clone()));
+        }
         runtimes
             .watchdog()
             .handle()
             .spawn(async move { watchdog.watchdog(receiver, query).await });
 
-        WatchdogMonitor::new(runtimes, sender, on_drop_receive)
+        WatchdogMonitor::new(
+            runtimes,
+            sender,
+            on_drop_receive,
+            running,
+            T::SERVICE_IDENTIFIERS,
+            registered,
+            placement,
+            events,
+            shutting_down,
+        )
     }
 }
 
+/// size the watchdog's command channel proportionally to the number of
+/// services so that a busy app does not serialize on a small channel
+fn command_channel_capacity(service_count: usize) -> usize {
+    std::cmp::max(
+        COMMAND_CHANNEL_MIN_CAPACITY,
+        COMMAND_CHANNEL_CAPACITY_PER_SERVICE * service_count,
+    )
+}
+
 impl<T> Watchdog<T>
 where
     T: Organix,
 {
-    #[tracing::instrument(skip(self, cc, watchdog_query), target = "watchdog", level = "info")]
-    async fn watchdog(
-        mut self,
-        mut cc: mpsc::Receiver<ControlCommand>,
-        watchdog_query: WatchdogQuery,
-    ) {
-        while let Some(command) = cc.recv().await {
-            match command {
-                ControlCommand::Shutdown | ControlCommand::Kill => {
-                    // TODO: for now we assume shutdown and kill are the same
-                    //       but on the long run it will need to send a Shutdown
-                    //       signal to every services so they can save state and
-                    //       release resources properly
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip(self, cc, watchdog_query),
+            target = "watchdog",
+            level = "info",
+            fields(app = tracing::field::Empty)
+        )
+    )]
+    async fn watchdog(mut self, mut cc: CommandReceiver, watchdog_query: WatchdogQuery) {
+        trace::with_prefix(tracing::Span::current(), watchdog_query.tracing_prefix());
 
+        // the channel is never closed: the `WatchdogQuery` clones (including
+        // the one given to the services here) can outlive the watchdog. The
+        // loop only ends on `Shutdown` or `Kill`, the commands still queued
+        // are then dropped and their senders get `WatchdogError::NoReply`.
+        loop {
+            // the transitions queued while frozen are processed first
+            let pending = if self.frozen {
+                None
+            } else {
+                self.pending.pop_front()
+            };
+            let command = match pending {
+                Some(command) => command,
+                None => match cc.recv().await {
+                    Some(command) => command,
+                    None => break,
+                },
+            };
+            if self.frozen && command.is_transition() {
+                tracing::debug!(%command, "frozen, command queued");
+                self.pending.push_back(command);
+                continue;
+            }
+
+            match command {
+                ControlCommand::Shutdown => {
+                    // let the services know why they are shutting down, they
+                    // are only given time to stop with a total shutdown
+                    // timeout: the ones still running are killed when the
+                    // watchdog is dropped
                     tracing::warn!(%command, "stopping watchdog");
+                    self.notify_shutting_down();
+                    match self.total_shutdown_timeout {
+                        None => self.stop_services().await,
+                        Some(timeout) => {
+                            let services: Vec<_> = T::SERVICE_IDENTIFIERS
+                                .iter()
+                                .copied()
+                                .chain(self.dynamic.identifiers())
+                                .collect();
+                            let graceful = async {
+                                self.stop_services().await;
+                                self.stopped(&services).await;
+                            };
+                            if tokio::time::timeout(timeout, graceful).await.is_err() {
+                                tracing::warn!("services not stopped in time, killing them");
+                            }
+                            self.services.kill_all();
+                            self.dynamic.kill_all();
+                        }
+                    }
+                    break;
+                }
+                ControlCommand::Kill => {
+                    tracing::warn!(%command, "stopping watchdog");
+                    self.notify_shutting_down();
                     break;
                 }
                 ControlCommand::Status {
                     service_identifier,
                     reply,
                 } => {
-                    let status_report = self.services.status(service_identifier).await;
-                    if let Ok(status_report) = &status_report {
+                    let status_report = self.status(service_identifier).await;
+                    if let Ok(StatusReport {
+                        identifier,
+                        status,
+                        started,
+                        intercom,
+                        uptime,
+                        ..
+                    }) = &status_report
+                    {
                         tracing::info!(
-                            %status_report.identifier,
-                            status_report.number_restart = status_report.started,
-                            %status_report.status,
-                            %status_report.intercom.number_sent,
-                            %status_report.intercom.number_received,
-                            %status_report.intercom.number_connections,
-                            %status_report.intercom.processing_speed_mean,
-                            %status_report.intercom.processing_speed_variance,
-                            %status_report.intercom.processing_speed_standard_derivation,
+                            status_report.identifier = %identifier,
+                            status_report.number_restart = started,
+                            status_report.status = %status,
+                            status_report.uptime = ?uptime,
+                            status_report.intercom.number_sent = %intercom.number_sent,
+                            status_report.intercom.number_received = %intercom.number_received,
+                            status_report.intercom.number_connections = %intercom.number_connections,
+                            status_report.intercom.capacity = %intercom.capacity,
+                            status_report.intercom.utilization = %intercom.utilization,
+                            status_report.intercom.processing_speed_mean = %intercom.processing_speed_mean,
+                            status_report.intercom.processing_speed_variance = %intercom.processing_speed_variance,
+                            status_report.intercom.processing_speed_standard_derivation = %intercom.processing_speed_standard_derivation,
                         );
                     }
                     reply.reply(status_report);
                 }
+                ControlCommand::HasEverStarted {
+                    service_identifier,
+                    reply,
+                } => {
+                    reply.reply(self.has_ever_started(service_identifier));
+                }
+                ControlCommand::Ping { reply } => reply.reply(Ok(())),
+                ControlCommand::Freeze { reply } => {
+                    tracing::info!("freezing the services transitions");
+                    self.frozen = true;
+                    reply.reply(Ok(()));
+                }
+                ControlCommand::Unfreeze { reply } => {
+                    tracing::info!(
+                        pending = self.pending.len(),
+                        "unfreezing the services transitions"
+                    );
+                    self.frozen = false;
+                    reply.reply(Ok(()));
+                }
+                ControlCommand::StatusAll { reply } => {
+                    let services = self
+                        .services
+                        .services_dyn()
+                        .into_iter()
+                        .chain(self.dynamic.services());
+                    let mut reports = Vec::with_capacity(T::SERVICE_COUNT);
+                    for service in services {
+                        reports.push(service.status().await);
+                    }
+                    reply.reply(Ok(reports));
+                }
+                ControlCommand::CurrentStatus {
+                    service_identifier,
+                    reply,
+                } => {
+                    reply.reply(self.current_status(service_identifier));
+                }
+                ControlCommand::SubscribeStatus {
+                    service_identifier,
+                    reply,
+                } => {
+                    reply.reply(self.status_reader(service_identifier));
+                }
                 ControlCommand::Start {
                     service_identifier,
                     reply,
                 } => {
                     tracing::info!(%service_identifier, "start");
-                    reply.reply(
-                        self.services
-                            .start(service_identifier, watchdog_query.clone()),
-                    );
+                    reply.reply(self.start(service_identifier, &watchdog_query));
                 }
                 ControlCommand::Stop {
+                    service_identifier,
+                    reason,
+                    reply,
+                } => {
+                    tracing::info!(%service_identifier, %reason, "stop");
+                    reply.reply(self.stop(service_identifier, reason));
+                }
+                ControlCommand::KillService {
                     service_identifier,
                     reply,
                 } => {
-                    tracing::info!(%service_identifier, "stop");
-                    reply.reply(self.services.stop(service_identifier));
+                    tracing::warn!(%service_identifier, "kill");
+                    reply.reply(self.kill(service_identifier));
                 }
                 ControlCommand::Intercom {
                     service_identifier,
@@ -178,17 +669,326 @@ where
                     tracing::trace!(%service_identifier, "query intercom");
                     // TODO: surround the operation with a timeout and
                     //       result to success
-                    reply.reply(self.services.intercoms(service_identifier));
+                    reply.reply(self.intercoms(service_identifier));
+                }
+                ControlCommand::Admin {
+                    service_identifier,
+                    reply,
+                } => {
+                    tracing::trace!(%service_identifier, "query admin");
+                    reply.reply(self.admin(service_identifier));
+                }
+                ControlCommand::Reload { timeout, reply } => {
+                    tracing::warn!(?timeout, "reload");
+                    reply.reply(self.reload(timeout, &watchdog_query).await);
+                }
+                ControlCommand::Batch { commands, reply } => {
+                    tracing::info!(number_commands = commands.len(), "batch");
+                    let results = commands
+                        .into_iter()
+                        .map(|command| match command {
+                            BatchCommand::Start { service_identifier } => {
+                                tracing::info!(%service_identifier, "start");
+                                self.start(service_identifier, &watchdog_query)
+                            }
+                            BatchCommand::Stop { service_identifier } => {
+                                tracing::info!(%service_identifier, "stop");
+                                self.stop(service_identifier, ShutdownReason::UserRequested)
+                            }
+                        })
+                        .collect();
+                    reply.reply(Ok(results));
+                }
+                ControlCommand::Register { service, reply } => {
+                    let service_identifier = service.0.service_identifier();
+                    tracing::info!(%service_identifier, "register");
+                    if T::SERVICE_IDENTIFIERS.contains(&service_identifier) {
+                        reply.reply(Err(WatchdogError::ServiceAlreadyRegistered {
+                            service_identifier,
+                        }));
+                    } else {
+                        reply.reply(self.dynamic.register(service));
+                    }
                 }
             }
         }
 
+        self.running.store(false, Ordering::SeqCst);
+
         if self.on_drop_send.send(()).is_err() {
             // ignore error for now
         }
     }
 }
 
+impl<T: Organix> Watchdog<T> {
+    /// ask all the services to stop, group after group if there is a
+    /// shutdown order, without waiting for the last group
+    async fn stop_services(&mut self) {
+        if self.shutdown_order.is_empty() {
+            self.services.stop_all(ShutdownReason::WatchdogShutdown);
+        } else {
+            self.stop_by_groups().await;
+        }
+        self.dynamic.stop_all(ShutdownReason::WatchdogShutdown);
+    }
+
+    /// fall through to the services registered in the running watchdog if
+    /// the service is not one of the app
+    fn fall_through<R>(
+        &mut self,
+        service_identifier: ServiceIdentifier,
+        result: Result<R, WatchdogError>,
+        dynamic: impl FnOnce(&mut dyn ManageServiceDyn) -> Result<R, WatchdogError>,
+    ) -> Result<R, WatchdogError> {
+        match (result, self.dynamic.get_mut(service_identifier)) {
+            (Err(WatchdogError::UnknownService { .. }), Some(service)) => dynamic(service),
+            (result, _) => self.list_registered(result),
+        }
+    }
+
+    /// the services registered in the running watchdog are possible values
+    /// too when the service is unknown
+    fn list_registered<R>(&self, result: Result<R, WatchdogError>) -> Result<R, WatchdogError> {
+        match result {
+            Err(WatchdogError::UnknownService {
+                service_identifier,
+                mut possible_values,
+            }) => {
+                possible_values.extend(self.dynamic.identifiers());
+                Err(WatchdogError::UnknownService {
+                    service_identifier,
+                    possible_values,
+                })
+            }
+            result => result,
+        }
+    }
+
+    fn start(
+        &mut self,
+        service_identifier: ServiceIdentifier,
+        watchdog_query: &WatchdogQuery,
+    ) -> Result<(), WatchdogError> {
+        let result = self
+            .services
+            .start(service_identifier, watchdog_query.clone());
+        self.fall_through(service_identifier, result, |service| {
+            service.start(watchdog_query.clone()).map_err(|source| {
+                WatchdogError::CannotStartService {
+                    service_identifier,
+                    source,
+                }
+            })
+        })
+    }
+
+    fn stop(
+        &mut self,
+        service_identifier: ServiceIdentifier,
+        reason: ShutdownReason,
+    ) -> Result<(), WatchdogError> {
+        let result = self.services.stop(service_identifier, reason);
+        self.fall_through(service_identifier, result, |service| {
+            service.stop(reason);
+            Ok(())
+        })
+    }
+
+    fn kill(&mut self, service_identifier: ServiceIdentifier) -> Result<(), WatchdogError> {
+        let result = self.services.kill(service_identifier);
+        self.fall_through(service_identifier, result, |service| {
+            service.kill();
+            Ok(())
+        })
+    }
+
+    fn has_ever_started(
+        &mut self,
+        service_identifier: ServiceIdentifier,
+    ) -> Result<bool, WatchdogError> {
+        let result = self.services.has_ever_started(service_identifier);
+        self.fall_through(service_identifier, result, |service| {
+            Ok(service.has_ever_started())
+        })
+    }
+
+    fn current_status(
+        &mut self,
+        service_identifier: ServiceIdentifier,
+    ) -> Result<Status, WatchdogError> {
+        let result = self.services.current_status(service_identifier);
+        self.fall_through(service_identifier, result, |service| {
+            Ok(service.current_status())
+        })
+    }
+
+    fn status_reader(
+        &mut self,
+        service_identifier: ServiceIdentifier,
+    ) -> Result<StatusReader, WatchdogError> {
+        let result = self.services.status_reader(service_identifier);
+        self.fall_through(service_identifier, result, |service| {
+            Ok(service.status_reader())
+        })
+    }
+
+    fn intercoms(
+        &mut self,
+        service_identifier: ServiceIdentifier,
+    ) -> Result<Box<dyn Any + Send + 'static>, WatchdogError> {
+        let result = self.services.intercoms(service_identifier);
+        self.fall_through(service_identifier, result, |service| Ok(service.intercom()))
+    }
+
+    fn admin(
+        &mut self,
+        service_identifier: ServiceIdentifier,
+    ) -> Result<Box<dyn Any + Send + 'static>, WatchdogError> {
+        let result = self.services.admin(service_identifier);
+        self.fall_through(service_identifier, result, |service| service.admin())
+    }
+
+    async fn status(
+        &mut self,
+        service_identifier: ServiceIdentifier,
+    ) -> Result<StatusReport, WatchdogError> {
+        let result = self.services.status(service_identifier).await;
+        match (result, self.dynamic.get(service_identifier)) {
+            (Err(WatchdogError::UnknownService { .. }), Some(service)) => {
+                Ok(service.status().await)
+            }
+            (result, _) => self.list_registered(result),
+        }
+    }
+
+    /// stop the services group after group, see
+    /// [`WatchdogBuilder::shutdown_order`]
+    async fn stop_by_groups(&mut self) {
+        let order = &self.shutdown_order;
+        let rank = |service_identifier| {
+            T::shutdown_group(service_identifier)
+                .and_then(|group| order.iter().position(|ordered| *ordered == group))
+                .unwrap_or(order.len())
+        };
+        let ranks: Vec<_> = T::SERVICE_IDENTIFIERS
+            .iter()
+            .map(|service_identifier| (*service_identifier, rank(*service_identifier)))
+            .collect();
+        let last = self.shutdown_order.len();
+
+        for current in 0..=last {
+            let group: Vec<_> = ranks
+                .iter()
+                .filter(|(_, rank)| *rank == current)
+                .map(|(service_identifier, _)| *service_identifier)
+                .collect();
+
+            for service_identifier in group.iter().copied() {
+                if let Err(error) = self
+                    .services
+                    .stop(service_identifier, ShutdownReason::WatchdogShutdown)
+                {
+                    tracing::error!(%error, "cannot stop the service");
+                }
+            }
+
+            // the services of the last group are killed with the watchdog
+            if current < last {
+                let stopped =
+                    tokio::time::timeout(self.shutdown_group_timeout, self.stopped(&group));
+                if stopped.await.is_err() {
+                    tracing::warn!(
+                        group = self.shutdown_order[current],
+                        "shutdown group not stopped in time"
+                    );
+                }
+            }
+        }
+    }
+
+    /// wait for all the given services to be stopped
+    async fn stopped(&mut self, services: &[ServiceIdentifier]) {
+        for service_identifier in services.iter().copied() {
+            while let Ok(report) = self.status(service_identifier).await {
+                if report.status.is_stopped() {
+                    break;
+                }
+                tokio::time::delay_for(Duration::from_millis(10)).await;
+            }
+        }
+    }
+
+    /// see `WatchdogQuery::reload`
+    async fn reload(
+        &mut self,
+        timeout: Duration,
+        watchdog_query: &WatchdogQuery,
+    ) -> Result<(), WatchdogError> {
+        let mut services = Vec::new();
+        let all: Vec<_> = T::SERVICE_IDENTIFIERS
+            .iter()
+            .copied()
+            .chain(self.dynamic.identifiers())
+            .collect();
+        for service_identifier in all {
+            if let Ok(status) = self.current_status(service_identifier) {
+                if !status.is_stopped() {
+                    services.push(service_identifier);
+                }
+            }
+        }
+
+        for service_identifier in services.iter().copied() {
+            tracing::info!(%service_identifier, "stop for reload");
+            if let Err(error) = self.stop(service_identifier, ShutdownReason::Reload) {
+                tracing::error!(%service_identifier, %error, "cannot stop service");
+            }
+        }
+        if tokio::time::timeout(timeout, self.stopped(&services))
+            .await
+            .is_err()
+        {
+            tracing::warn!("services not stopped in time, killing them");
+            for service_identifier in services.iter().copied() {
+                let stopped = matches!(self.current_status(service_identifier), Ok(status) if status.is_stopped());
+                if !stopped && self.kill(service_identifier).is_err() {
+                    // the service is known, it was just stopped
+                }
+            }
+            if tokio::time::timeout(timeout, self.stopped(&services))
+                .await
+                .is_err()
+            {
+                tracing::error!("services not stopped once killed");
+            }
+        }
+
+        let mut failed = Vec::new();
+        for service_identifier in services {
+            tracing::info!(%service_identifier, "start after reload");
+            if let Err(error) = self.start(service_identifier, watchdog_query) {
+                tracing::error!(%service_identifier, %error, "cannot start service again");
+                failed.push(service_identifier);
+            }
+        }
+
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(WatchdogError::ReloadFailed { services: failed })
+        }
+    }
+
+    /// let the services know the watchdog is going away, before they are
+    /// asked to shutdown themselves
+    fn notify_shutting_down(&self) {
+        if self.shutting_down.broadcast(true).is_err() {
+            // no one is listening
+        }
+    }
+}
+
 impl<T: Organix> fmt::Debug for Watchdog<T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("Watchdog").finish()
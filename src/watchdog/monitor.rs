@@ -1,36 +1,165 @@
 use crate::{
-    runtime::Runtimes,
-    watchdog::{ControlCommand, WatchdogQuery},
+    runtime::{RuntimeMetrics, RuntimePlacement, Runtimes},
+    service::{ServiceIdentifier, StatusReport},
+    watchdog::{
+        dynamic::Registered, BlockingWatchdogQuery, CommandSender, WatchdogError, WatchdogEvent,
+        WatchdogQuery, WeakWatchdogQuery,
+    },
+};
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
 };
-use std::future::Future;
 use tokio::{
-    sync::{mpsc, oneshot},
+    sync::{broadcast, oneshot, watch},
     task::JoinHandle,
 };
 
 pub struct WatchdogMonitor {
     runtimes: Runtimes,
-    control_command: mpsc::Sender<ControlCommand>,
+    control_command: Arc<CommandSender>,
     watchdog_finished: oneshot::Receiver<()>,
+    running: Arc<AtomicBool>,
+    services: &'static [ServiceIdentifier],
+    registered: Registered,
+    placement: Vec<(ServiceIdentifier, RuntimePlacement)>,
+    events: broadcast::Sender<WatchdogEvent>,
+    shutting_down: watch::Receiver<bool>,
+}
+
+/// the state of the watchdog and of all of its services at a given time,
+/// see [`WatchdogMonitor::snapshot`]
+///
+/// [`WatchdogMonitor::snapshot`]: ./struct.WatchdogMonitor.html#method.snapshot
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct WatchdogSnapshot {
+    /// the watchdog is still processing commands
+    pub running: bool,
+    /// the status report of every services, empty if the watchdog is not
+    /// running anymore
+    pub services: Vec<StatusReport>,
+    /// the status reports could be gathered, `false` if the watchdog did
+    /// not reply (e.g. it is not running anymore)
+    pub complete: bool,
+    /// the runtime every service of the app has been placed on
+    pub placement: Vec<(ServiceIdentifier, RuntimePlacement)>,
 }
 
 impl WatchdogMonitor {
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         runtimes: Runtimes,
-        control_command: mpsc::Sender<ControlCommand>,
+        control_command: CommandSender,
         watchdog_finished: oneshot::Receiver<()>,
+        running: Arc<AtomicBool>,
+        services: &'static [ServiceIdentifier],
+        registered: Registered,
+        placement: Vec<(ServiceIdentifier, RuntimePlacement)>,
+        events: broadcast::Sender<WatchdogEvent>,
+        shutting_down: watch::Receiver<bool>,
     ) -> Self {
         WatchdogMonitor {
             runtimes,
-            control_command,
+            control_command: Arc::new(control_command),
             watchdog_finished,
+            running,
+            services,
+            registered,
+            placement,
+            events,
+            shutting_down,
+        }
+    }
+
+    /// check whether the watchdog is still processing commands
+    ///
+    /// once the watchdog has been shutdown or killed this returns `false`.
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    /// capture the state of the watchdog and of all of its services
+    ///
+    /// the status reports are gathered by the watchdog in one go so they
+    /// are consistent with each other. If the watchdog does not reply the
+    /// snapshot is marked as not `complete`.
+    pub async fn snapshot(&self) -> WatchdogSnapshot {
+        let (services, complete) = match self.control().status_all().await {
+            Ok(services) => (services, true),
+            Err(error) => {
+                tracing::debug!(%error, "cannot gather the status of the services");
+                (Vec::new(), false)
+            }
+        };
+
+        WatchdogSnapshot {
+            running: self.is_running(),
+            services,
+            complete,
+            placement: self.placement(),
         }
     }
 
+    /// the number of services managed by the watchdog
+    pub fn service_count(&self) -> usize {
+        self.services.len()
+    }
+
+    /// the runtime every service has been placed on
+    ///
+    /// useful to check a service is running on its individual runtime
+    /// and with the expected drivers.
+    pub fn placement(&self) -> Vec<(ServiceIdentifier, RuntimePlacement)> {
+        self.placement.clone()
+    }
+
+    /// the thread counts of the watchdog's runtime and of the runtimes of
+    /// the services, see [`Runtimes::metrics`]
+    ///
+    /// [`Runtimes::metrics`]: ../runtime/struct.Runtimes.html#method.metrics
+    pub fn runtime_metrics(&self) -> Vec<RuntimeMetrics> {
+        self.runtimes.metrics()
+    }
+
     pub fn control(&self) -> WatchdogQuery {
         WatchdogQuery::new(
             self.runtimes.watchdog().handle().clone(),
-            self.control_command.clone(),
+            CommandSender::clone(&self.control_command),
+            self.services,
+            self.registered.clone(),
+            self.events.clone(),
+            self.shutting_down.clone(),
+        )
+    }
+
+    /// get a handle to the watchdog for the synchronous code, see
+    /// [`BlockingWatchdogQuery`]
+    ///
+    /// [`BlockingWatchdogQuery`]: ./struct.BlockingWatchdogQuery.html
+    pub fn control_blocking(&self) -> BlockingWatchdogQuery {
+        BlockingWatchdogQuery::new(self.control())
+    }
+
+    /// get a handle to the watchdog that does not keep it reachable
+    ///
+    /// [`WeakWatchdogQuery::upgrade`] only returns a `WatchdogQuery` while
+    /// the watchdog is running and this monitor has not been dropped. Useful
+    /// for long lived subsystems that must not hold on the watchdog.
+    ///
+    /// [`WeakWatchdogQuery::upgrade`]: ./struct.WeakWatchdogQuery.html#method.upgrade
+    pub fn control_weak(&self) -> WeakWatchdogQuery {
+        WeakWatchdogQuery::new(
+            self.runtimes.watchdog().handle().clone(),
+            &self.control_command,
+            Arc::clone(&self.running),
+            self.services,
+            self.registered.clone(),
+            self.events.clone(),
+            self.shutting_down.clone(),
         )
     }
 
@@ -42,15 +171,129 @@ impl WatchdogMonitor {
         self.runtimes.watchdog().handle().spawn(future)
     }
 
+    /// run the app until the watchdog is shutdown
+    ///
+    /// the `start` future is given a `WatchdogQuery` to start the services.
+    /// If it fails the watchdog is shutdown and the error is returned. With
+    /// the `signal` feature the watchdog is also shutdown on `ctrl-c`.
+    ///
+    /// see the [`run!`] macro for the common case of starting a list of
+    /// services in order.
+    ///
+    /// [`run!`]: ../macro.run.html
+    pub fn run<F, Fut>(self, start: F) -> Result<(), WatchdogError>
+    where
+        F: FnOnce(WatchdogQuery) -> Fut,
+        Fut: Future<Output = Result<(), WatchdogError>> + Send + 'static,
+    {
+        let failure = Arc::new(Mutex::new(None));
+
+        let starting = start(self.control());
+        let mut controller = self.control();
+        let start_failure = Arc::clone(&failure);
+        self.spawn(async move {
+            if let Err(error) = starting.await {
+                tracing::error!(%error, "cannot start the app");
+                *start_failure.lock().unwrap() = Some(error);
+                controller.shutdown().await;
+            }
+        });
+
+        #[cfg(feature = "signal")]
+        {
+            let mut controller = self.control();
+            self.spawn(async move {
+                if tokio::signal::ctrl_c().await.is_ok() {
+                    tracing::warn!("ctrl-c received");
+                    controller.shutdown().await;
+                }
+            });
+        }
+
+        self.wait_finished();
+
+        let failure = failure.lock().unwrap().take();
+        match failure {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    /// emergency stop: kill the watchdog and abort the task of every
+    /// service right away
+    ///
+    /// this is the last resort, when the services cannot be trusted to
+    /// stop anymore (e.g. a corruption has been detected). Unlike
+    /// `WatchdogQuery::kill` the services are aborted without waiting for
+    /// the watchdog to process the command, the in-flight work of the
+    /// services is lost. A service only stops at its next await point.
+    ///
+    /// use [`wait_finished`] to wait for the watchdog to be finished.
+    ///
+    /// [`wait_finished`]: #method.wait_finished
+    pub fn abort_all(&self) {
+        let mut control = self.control();
+        self.runtimes
+            .watchdog()
+            .handle()
+            .spawn(async move { control.kill().await });
+
+        self.control_command.aborts.abort_all();
+    }
+
+    /// block until the watchdog is finished
+    ///
+    /// returns as well if the watchdog stopped without notifying it, e.g.
+    /// because it panicked.
     pub fn wait_finished(self) {
         let Self {
             mut runtimes,
             watchdog_finished,
+            running,
             ..
         } = self;
 
-        runtimes
-            .watchdog_mut()
-            .block_on(async move { watchdog_finished.await.unwrap() })
+        let finished = runtimes.watchdog_mut().block_on(watchdog_finished);
+        if finished.is_err() {
+            tracing::error!("the watchdog stopped unexpectedly");
+            running.store(false, Ordering::SeqCst);
+        }
     }
 }
+
+/// build the app, start the given services in order and block until the
+/// watchdog is shutdown
+///
+/// this is a shortcut for `WatchdogMonitor::run`, it returns the error of
+/// the first service that failed to start.
+///
+/// ```no_run
+/// # use organix::{service, Organix, Service, ServiceIdentifier, ServiceState};
+/// # struct Writer;
+/// # #[async_trait::async_trait]
+/// # impl Service for Writer {
+/// #     const SERVICE_IDENTIFIER: ServiceIdentifier = "writer";
+/// #     type IntercomMsg = service::NoIntercom;
+/// #     fn prepare(_: ServiceState<Self>) -> Self { Self }
+/// #     async fn start(self) {}
+/// # }
+/// #[derive(Organix)]
+/// struct App {
+///     writer: service::ServiceManager<Writer>,
+/// }
+///
+/// fn main() {
+///     organix::run!(App, start: [Writer]).unwrap();
+/// }
+/// ```
+#[macro_export]
+macro_rules! run {
+    ($app:ty, start: [ $( $service:ty ),* $(,)? ]) => {
+        $crate::WatchdogBuilder::<$app>::new()
+            .build()
+            .run(|mut _controller| async move {
+                $( _controller.start::<$service>().await?; )*
+                Ok(())
+            })
+    };
+}
@@ -0,0 +1,79 @@
+use crate::{
+    service::{Service, ServiceIdentifier, Status, StatusReport},
+    watchdog::{WatchdogError, WatchdogEvent, WatchdogQuery},
+};
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+/// a handle to the watchdog that can only query the state of the services
+///
+/// unlike the [`WatchdogQuery`] it wraps, it cannot start, stop or kill
+/// the services, nor shutdown the watchdog or send intercom messages. Give
+/// it to the components that only need the introspection (e.g. a metrics
+/// exporter).
+///
+/// see [`WatchdogQuery::read_only`].
+///
+/// [`WatchdogQuery`]: ./struct.WatchdogQuery.html
+/// [`WatchdogQuery::read_only`]: ./struct.WatchdogQuery.html#method.read_only
+#[derive(Clone, Debug)]
+pub struct ReadOnlyWatchdogQuery {
+    query: WatchdogQuery,
+}
+
+impl ReadOnlyWatchdogQuery {
+    pub(crate) fn new(query: WatchdogQuery) -> Self {
+        Self { query }
+    }
+
+    /// see [`WatchdogQuery::subscribe`](./struct.WatchdogQuery.html#method.subscribe)
+    pub fn subscribe(&self) -> broadcast::Receiver<WatchdogEvent> {
+        self.query.subscribe()
+    }
+
+    /// the identifiers of the services of the app
+    pub fn services(&self) -> &'static [ServiceIdentifier] {
+        self.query.services()
+    }
+
+    pub fn is_registered(&self, service_identifier: ServiceIdentifier) -> bool {
+        self.query.is_registered(service_identifier)
+    }
+
+    pub fn is_registered_service<T: Service>(&self) -> bool {
+        self.query.is_registered_service::<T>()
+    }
+
+    pub async fn status<T: Service>(&mut self) -> Result<StatusReport, WatchdogError> {
+        self.query.status::<T>().await
+    }
+
+    pub async fn status_all(&mut self) -> Result<Vec<StatusReport>, WatchdogError> {
+        self.query.status_all().await
+    }
+
+    pub async fn current_status<T: Service>(&mut self) -> Result<Status, WatchdogError> {
+        self.query.current_status::<T>().await
+    }
+
+    pub async fn has_ever_started<T: Service>(&mut self) -> Result<bool, WatchdogError> {
+        self.query.has_ever_started::<T>().await
+    }
+
+    pub async fn is_running<T: Service>(&mut self) -> Result<bool, WatchdogError> {
+        self.query.is_running::<T>().await
+    }
+
+    pub async fn is_starting<T: Service>(&mut self) -> Result<bool, WatchdogError> {
+        self.query.is_starting::<T>().await
+    }
+
+    /// see [`WatchdogQuery::ping`](./struct.WatchdogQuery.html#method.ping)
+    pub async fn ping(&mut self, timeout: Duration) -> Result<Duration, WatchdogError> {
+        self.query.ping(timeout).await
+    }
+
+    pub async fn is_shutting_down<T: Service>(&mut self) -> Result<bool, WatchdogError> {
+        self.query.is_shutting_down::<T>().await
+    }
+}
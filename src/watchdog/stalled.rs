@@ -0,0 +1,61 @@
+use crate::{
+    service::ServiceIdentifier,
+    watchdog::{WatchdogEvent, WatchdogQuery},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+/// see `WatchdogBuilder::stalled_consumer`
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct StalledConsumer {
+    pub(crate) window: Duration,
+    pub(crate) threshold: usize,
+}
+
+impl StalledConsumer {
+    /// sample the intercoms of the services every `window` until the
+    /// watchdog is shutdown, emitting `WatchdogEvent::StalledConsumer` for
+    /// the running services that did not receive any of the messages
+    /// waiting for them
+    pub(crate) async fn detect(self, mut query: WatchdogQuery) {
+        let shutdown = query.shutdown_signal();
+        let detect = async move {
+            let mut received: HashMap<ServiceIdentifier, u64> = HashMap::new();
+            let mut stalled = HashSet::new();
+            let mut interval = tokio::time::interval(self.window);
+
+            loop {
+                interval.tick().await;
+                let reports = match query.status_all().await {
+                    Ok(reports) => reports,
+                    Err(_) => break,
+                };
+
+                for report in reports {
+                    let intercom = report.intercom;
+                    let previous = received.insert(report.identifier, intercom.number_received);
+                    if previous != Some(intercom.number_received) {
+                        stalled.remove(report.identifier);
+                    } else if report.status.is_started()
+                        && intercom.queue_depth >= self.threshold
+                        && stalled.insert(report.identifier)
+                    {
+                        // only reported once per stall
+                        query.emit(WatchdogEvent::StalledConsumer {
+                            service_identifier: report.identifier,
+                            queue_depth: intercom.queue_depth,
+                            window: self.window,
+                        });
+                    }
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = shutdown => {}
+            _ = detect => {}
+        }
+    }
+}
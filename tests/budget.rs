@@ -0,0 +1,257 @@
+//! test the soft resource budget of the services
+//!
+
+use async_trait::async_trait;
+use organix::{
+    service::{self, BudgetResource},
+    IntercomMsg, Organix, Service, ServiceIdentifier, ServiceState, WatchdogBuilder, WatchdogEvent,
+};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::time::{delay_for, timeout};
+
+/// spawns more tasks than its budget allows
+struct Spawner {
+    state: ServiceState<Self>,
+}
+
+#[async_trait]
+impl Service for Spawner {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "spawner";
+
+    type IntercomMsg = service::NoIntercom;
+
+    fn prepare(state: ServiceState<Self>) -> Self {
+        Self { state }
+    }
+
+    async fn start(mut self) {
+        for _ in 0..3 {
+            self.state.spawn(futures_util::future::pending::<()>());
+        }
+        while self.state.intercom_mut().recv().await.is_some() {}
+    }
+}
+
+#[derive(Debug, IntercomMsg)]
+struct Msg;
+
+/// never reads its intercom
+struct Sleeper {
+    state: ServiceState<Self>,
+}
+
+#[async_trait]
+impl Service for Sleeper {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "sleeper";
+
+    type IntercomMsg = Msg;
+
+    fn prepare(state: ServiceState<Self>) -> Self {
+        Self { state }
+    }
+
+    async fn start(self) {
+        let _state = self.state;
+        futures_util::future::pending::<()>().await
+    }
+}
+
+/// never reads its intercom either, with a budget as large as its queue
+struct Deaf {
+    state: ServiceState<Self>,
+}
+
+#[async_trait]
+impl Service for Deaf {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "deaf";
+
+    type IntercomMsg = Msg;
+
+    fn prepare(state: ServiceState<Self>) -> Self {
+        Self { state }
+    }
+
+    async fn start(self) {
+        let _state = self.state;
+        futures_util::future::pending::<()>().await
+    }
+}
+
+/// spawns more limited tasks than it is allowed to run
+struct Admission {
+    state: ServiceState<Self>,
+}
+
+#[async_trait]
+impl Service for Admission {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "admission";
+
+    type IntercomMsg = service::NoIntercom;
+
+    fn prepare(state: ServiceState<Self>) -> Self {
+        Self { state }
+    }
+
+    async fn start(self) {
+        let spawned = self.state.metrics().counter("spawned");
+
+        // the permit of a panicking task is released
+        let panicked = self.state.spawn_limited(async { panic!("admitted") }).await;
+        spawned.increment();
+        assert!(panicked.await.is_err());
+
+        loop {
+            self.state
+                .spawn_limited(futures_util::future::pending::<()>())
+                .await;
+            spawned.increment();
+        }
+    }
+}
+
+#[derive(Organix)]
+#[runtime(shared)]
+struct App {
+    #[budget(max_tasks = 2)]
+    spawner: service::ServiceManager<Spawner>,
+    #[budget(max_intercom_queue = 2)]
+    sleeper: service::ServiceManager<Sleeper>,
+    #[budget(max_intercom_queue = 10)]
+    deaf: service::ServiceManager<Deaf>,
+    #[runtime(max_concurrent_tasks = 2)]
+    admission: service::ServiceManager<Admission>,
+}
+
+#[test]
+fn max_tasks() {
+    let watchdog = WatchdogBuilder::<App>::new().build();
+    let mut controller = watchdog.control();
+
+    watchdog.spawn(async move {
+        let mut events = controller.subscribe();
+        controller.start::<Spawner>().await.unwrap();
+
+        let event = timeout(Duration::from_secs(1), events.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            event,
+            WatchdogEvent::BudgetExceeded {
+                service_identifier: "spawner",
+                resource: BudgetResource::Tasks,
+                limit: 2,
+                usage: 3,
+            }
+        );
+
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+}
+
+#[test]
+fn max_intercom_queue() {
+    let watchdog = WatchdogBuilder::<App>::new().build();
+    let mut controller = watchdog.control();
+
+    watchdog.spawn(async move {
+        let mut events = controller.subscribe();
+        controller.start::<Sleeper>().await.unwrap();
+
+        let mut sleeper = controller.intercom::<Sleeper>();
+        for _ in 0..3 {
+            sleeper.send(Msg).await.unwrap();
+        }
+
+        let event = timeout(Duration::from_secs(1), events.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            event,
+            WatchdogEvent::BudgetExceeded {
+                service_identifier: "sleeper",
+                resource: BudgetResource::IntercomQueue,
+                limit: 2,
+                usage: 3,
+            }
+        );
+
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+}
+
+/// the messages rejected by a full intercom do not count in the budget
+#[test]
+fn max_intercom_queue_full() {
+    let watchdog = WatchdogBuilder::<App>::new().build();
+    let mut controller = watchdog.control();
+    let result = Arc::new(Mutex::new(None));
+
+    let exceeded = Arc::clone(&result);
+    watchdog.spawn(async move {
+        let mut events = controller.subscribe();
+        controller.start::<Deaf>().await.unwrap();
+
+        let mut deaf = controller.intercom::<Deaf>();
+        deaf.send(Msg).await.unwrap();
+        while deaf.try_send(Msg).is_ok() {}
+        let rejected = (0..3).filter(|_| deaf.try_send(Msg).is_err()).count();
+
+        let queue_depth = controller
+            .status::<Deaf>()
+            .await
+            .unwrap()
+            .intercom
+            .queue_depth;
+        let event = timeout(Duration::from_millis(100), events.recv()).await;
+        *exceeded.lock().unwrap() = Some((rejected, queue_depth, event.is_err()));
+
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+
+    assert_eq!(*result.lock().unwrap(), Some((3, 10, true)));
+}
+
+#[test]
+fn max_concurrent_tasks() {
+    let watchdog = WatchdogBuilder::<App>::new().build();
+    let mut controller = watchdog.control();
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let result = Arc::clone(&reports);
+
+    watchdog.spawn(async move {
+        controller.start::<Admission>().await.unwrap();
+        timeout(Duration::from_secs(1), async {
+            while controller
+                .status::<Admission>()
+                .await
+                .unwrap()
+                .concurrent_tasks
+                < 2
+            {
+                delay_for(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        delay_for(Duration::from_millis(50)).await;
+
+        let report = controller.status::<Admission>().await.unwrap();
+        *result.lock().unwrap() = vec![report.concurrent_tasks as i64, report.custom["spawned"]];
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+
+    assert_eq!(*reports.lock().unwrap(), vec![2, 3]);
+}
@@ -0,0 +1,364 @@
+//! stress the watchdog's command channel with many concurrent
+//! queries coming from many different services
+//!
+
+use async_trait::async_trait;
+use organix::{
+    runtime::RuntimePlacement, service, BatchCommand, Organix, Service, ServiceIdentifier,
+    ServiceState, WatchdogBuilder, WatchdogError,
+};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::time::timeout;
+
+macro_rules! idle_service {
+    ($name:ident, $identifier:expr) => {
+        struct $name {
+            state: ServiceState<Self>,
+        }
+
+        #[async_trait]
+        impl Service for $name {
+            const SERVICE_IDENTIFIER: ServiceIdentifier = $identifier;
+
+            type IntercomMsg = service::NoIntercom;
+
+            fn prepare(state: ServiceState<Self>) -> Self {
+                Self { state }
+            }
+
+            async fn start(mut self) {
+                while self.state.intercom_mut().recv().await.is_some() {}
+            }
+        }
+    };
+}
+
+idle_service!(Alpha, "alpha");
+idle_service!(Beta, "beta");
+idle_service!(Gamma, "gamma");
+idle_service!(Delta, "delta");
+
+#[derive(Organix)]
+#[runtime(shared)]
+struct ManyServices {
+    alpha: service::ServiceManager<Alpha>,
+    beta: service::ServiceManager<Beta>,
+    gamma: service::ServiceManager<Gamma>,
+    delta: service::ServiceManager<Delta>,
+}
+
+#[test]
+fn service_count() {
+    assert_eq!(<ManyServices as Organix>::SERVICE_COUNT, 4);
+}
+
+/// flood the watchdog with concurrent status queries for every services
+/// and check they are all served
+#[test]
+fn concurrent_status_queries() {
+    const QUERIES_PER_SERVICE: usize = 250;
+
+    let watchdog = WatchdogBuilder::<ManyServices>::new().build();
+    let mut controller = watchdog.control();
+
+    watchdog.spawn(async move {
+        controller.start::<Alpha>().await.unwrap();
+        controller.start::<Beta>().await.unwrap();
+        controller.start::<Gamma>().await.unwrap();
+        controller.start::<Delta>().await.unwrap();
+
+        let mut queries = Vec::new();
+        for _ in 0..QUERIES_PER_SERVICE {
+            let mut alpha = controller.clone();
+            let mut beta = controller.clone();
+            let mut gamma = controller.clone();
+            let mut delta = controller.clone();
+            queries.push(tokio::spawn(async move { alpha.status::<Alpha>().await }));
+            queries.push(tokio::spawn(async move { beta.status::<Beta>().await }));
+            queries.push(tokio::spawn(async move { gamma.status::<Gamma>().await }));
+            queries.push(tokio::spawn(async move { delta.status::<Delta>().await }));
+        }
+
+        let all = futures_util::future::join_all(queries);
+        let results = timeout(Duration::from_secs(10), all)
+            .await
+            .expect("all the status queries should have been served");
+
+        for result in results {
+            assert!(result.unwrap().is_ok());
+        }
+
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+}
+
+/// the status queries cannot delay the control commands: a stop sent
+/// after a flood of status queries is served before them
+#[test]
+fn status_flood_does_not_delay_stop() {
+    const QUERIES: usize = 2_000;
+
+    let watchdog = WatchdogBuilder::<ManyServices>::new().build();
+    let mut controller = watchdog.control();
+    let served_before_stop = Arc::new(Mutex::new(None));
+    let result = Arc::clone(&served_before_stop);
+
+    watchdog.spawn(async move {
+        controller.start::<Alpha>().await.unwrap();
+        controller.start::<Beta>().await.unwrap();
+
+        let served = Arc::new(AtomicUsize::new(0));
+        let mut queries = Vec::new();
+        for _ in 0..QUERIES {
+            let mut alpha = controller.clone();
+            let served = Arc::clone(&served);
+            queries.push(tokio::spawn(async move {
+                let _ = alpha.status::<Alpha>().await;
+                served.fetch_add(1, Ordering::SeqCst);
+            }));
+        }
+        tokio::time::delay_for(Duration::from_millis(1)).await;
+
+        let stopped = timeout(Duration::from_secs(1), controller.stop::<Beta>()).await;
+        *result.lock().unwrap() = Some((stopped.is_ok(), served.load(Ordering::SeqCst)));
+
+        futures_util::future::join_all(queries).await;
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+
+    let (stopped, served) = served_before_stop.lock().unwrap().take().unwrap();
+    assert!(stopped, "the stop command should have been served promptly");
+    assert!(
+        served < QUERIES,
+        "the stop command waited for all the status queries"
+    );
+}
+
+#[test]
+fn registered_services() {
+    let watchdog = WatchdogBuilder::<ManyServices>::new().build();
+    let controller = watchdog.control();
+
+    assert!(controller.is_registered("alpha"));
+    assert!(controller.is_registered_service::<Delta>());
+    assert!(!controller.is_registered("omega"));
+    assert_eq!(watchdog.service_count(), 4);
+    assert!(watchdog.placement().iter().all(|(_, placement)| {
+        *placement
+            == RuntimePlacement::Shared {
+                pool: None,
+                io_driver: true,
+                time_driver: true,
+            }
+    }));
+}
+
+/// the commands of a batch are all executed, in order, even if
+/// one of them fails
+#[test]
+fn batch() {
+    let watchdog = WatchdogBuilder::<ManyServices>::new().build();
+    let mut controller = watchdog.control();
+
+    watchdog.spawn(async move {
+        let results = controller
+            .batch(vec![
+                BatchCommand::start::<Alpha>(),
+                BatchCommand::start::<Beta>(),
+                BatchCommand::start::<Alpha>(),
+                BatchCommand::stop::<Beta>(),
+            ])
+            .await
+            .unwrap();
+
+        assert_eq!(results.len(), 4);
+        assert!(results[0].is_ok());
+        assert!(results[1].is_ok());
+        match &results[2] {
+            Err(WatchdogError::CannotStartService {
+                service_identifier, ..
+            }) => assert_eq!(*service_identifier, "alpha"),
+            other => panic!("expected alpha to fail to start, got {:?}", other),
+        }
+        assert!(results[3].is_ok());
+
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+}
+
+#[test]
+fn query_timeouts() {
+    let watchdog = WatchdogBuilder::<ManyServices>::new().build();
+    let mut controller = watchdog.control();
+
+    watchdog.spawn(async move {
+        let timeout = Duration::from_secs(1);
+
+        controller.start_timeout::<Alpha>(timeout).await.unwrap();
+        let report = controller.status_timeout::<Alpha>(timeout).await.unwrap();
+        assert_eq!(report.identifier, "alpha");
+        controller.stop_timeout::<Alpha>(timeout).await.unwrap();
+
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+}
+
+/// once drained, the queries fail immediately from every clones
+#[test]
+fn drain() {
+    let watchdog = WatchdogBuilder::<ManyServices>::new().build();
+    let mut controller = watchdog.control();
+
+    watchdog.spawn(async move {
+        controller.start::<Alpha>().await.unwrap();
+
+        let mut clone = controller.clone();
+        controller.drain();
+        assert!(clone.is_drained());
+
+        assert_eq!(
+            clone.status::<Alpha>().await.unwrap_err(),
+            WatchdogError::Draining
+        );
+        assert_eq!(
+            controller.start::<Beta>().await.unwrap_err(),
+            WatchdogError::Draining
+        );
+        assert_eq!(
+            controller
+                .batch(vec![BatchCommand::stop::<Alpha>()])
+                .await
+                .unwrap_err(),
+            WatchdogError::Draining
+        );
+
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+}
+
+/// the read only query sees the services the other queries start
+#[test]
+fn read_only() {
+    let watchdog = WatchdogBuilder::<ManyServices>::new().build();
+    let mut controller = watchdog.control();
+    let mut read_only = controller.read_only();
+
+    assert_eq!(read_only.services(), &["alpha", "beta", "gamma", "delta"]);
+    assert!(read_only.is_registered_service::<Beta>());
+
+    watchdog.spawn(async move {
+        controller.start::<Alpha>().await.unwrap();
+
+        assert!(!read_only.has_ever_started::<Beta>().await.unwrap());
+        assert!(!read_only
+            .current_status::<Alpha>()
+            .await
+            .unwrap()
+            .is_stopped());
+        assert_eq!(
+            read_only.status::<Alpha>().await.unwrap().identifier,
+            "alpha"
+        );
+        assert_eq!(read_only.status_all().await.unwrap().len(), 4);
+
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+}
+
+/// the blocking query drives the watchdog from the synchronous code, but
+/// refuses to block a runtime
+#[test]
+fn control_blocking() {
+    let watchdog = WatchdogBuilder::<ManyServices>::new().build();
+    let controller = watchdog.control_blocking();
+
+    controller.start::<Alpha>().unwrap();
+    assert_eq!(controller.status::<Alpha>().unwrap().identifier, "alpha");
+    assert_eq!(controller.status_all().unwrap().len(), 4);
+    controller.stop::<Alpha>().unwrap();
+
+    let in_runtime = controller.clone();
+    let result = watchdog.spawn(async move { in_runtime.start::<Beta>() });
+    let mut runtime = tokio::runtime::Runtime::new().unwrap();
+    assert_eq!(
+        runtime.block_on(result).unwrap(),
+        Err(WatchdogError::BlockingInRuntime)
+    );
+
+    controller.shutdown().unwrap();
+    watchdog.wait_finished();
+}
+
+/// the snapshot reports every services at once
+#[test]
+fn snapshot() {
+    let watchdog = WatchdogBuilder::<ManyServices>::new().build();
+    let mut controller = watchdog.control();
+    let mut runtime = tokio::runtime::Runtime::new().unwrap();
+
+    runtime.block_on(controller.start::<Alpha>()).unwrap();
+    let snapshot = runtime.block_on(watchdog.snapshot());
+
+    assert!(snapshot.running);
+    assert!(snapshot.complete);
+    assert_eq!(snapshot.placement, watchdog.placement());
+    let identifiers: Vec<_> = snapshot
+        .services
+        .iter()
+        .map(|report| report.identifier)
+        .collect();
+    assert_eq!(identifiers, vec!["alpha", "beta", "gamma", "delta"]);
+    assert!(!snapshot.services[0].status.is_stopped());
+    assert!(snapshot.services[1].status.is_shutdown());
+
+    #[cfg(feature = "serde")]
+    {
+        let json = serde_json::to_value(&snapshot).unwrap();
+        assert_eq!(json["services"][0]["identifier"], "alpha");
+        assert_eq!(json["running"], true);
+        assert_eq!(json["complete"], true);
+    }
+
+    runtime.block_on(controller.shutdown());
+    watchdog.wait_finished();
+}
+
+/// the snapshot is marked as incomplete once the watchdog stopped replying
+#[test]
+fn snapshot_after_kill() {
+    let watchdog = WatchdogBuilder::<ManyServices>::new().build();
+    let mut controller = watchdog.control();
+    let mut runtime = tokio::runtime::Runtime::new().unwrap();
+
+    runtime.block_on(controller.kill());
+    // the snapshot is served before a pending `Kill`, wait for it to be processed
+    while watchdog.is_running() {
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    let snapshot = runtime.block_on(watchdog.snapshot());
+
+    assert!(!snapshot.running);
+    assert!(!snapshot.complete);
+    assert!(snapshot.services.is_empty());
+    assert_eq!(snapshot.placement, watchdog.placement());
+
+    watchdog.wait_finished();
+}
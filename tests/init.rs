@@ -0,0 +1,415 @@
+//! test the asynchronous initialization of the services
+//!
+
+use async_trait::async_trait;
+use organix::{
+    service::{self, PrepareError, ServiceError, ServiceErrorInfo, Status},
+    Organix, Service, ServiceIdentifier, ServiceState, WatchdogBuilder, WatchdogError,
+    WatchdogEvent, WatchdogQuery,
+};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::time::{delay_for, timeout};
+
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+static LISTENING: AtomicBool = AtomicBool::new(false);
+
+struct Warmup {
+    state: ServiceState<Self>,
+}
+
+#[async_trait]
+impl Service for Warmup {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "warmup";
+
+    type IntercomMsg = service::NoIntercom;
+
+    fn prepare(state: ServiceState<Self>) -> Self {
+        Self { state }
+    }
+
+    async fn init(&mut self) {
+        delay_for(Duration::from_millis(100)).await;
+        self.state.metrics().counter("initialized").increment();
+        INITIALIZED.store(true, Ordering::SeqCst);
+    }
+
+    async fn start(mut self) {
+        while self.state.intercom_mut().recv().await.is_some() {}
+    }
+}
+
+struct Broken {
+    state: ServiceState<Self>,
+}
+
+#[async_trait]
+impl Service for Broken {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "broken";
+
+    type IntercomMsg = service::NoIntercom;
+
+    fn prepare(state: ServiceState<Self>) -> Self {
+        Self { state }
+    }
+
+    async fn init(&mut self) {
+        panic!("cannot initialize {}", self.state.identifier())
+    }
+
+    async fn start(self) {}
+}
+
+/// panics as soon as it is started, like `Broken`
+struct Flaky;
+
+#[async_trait]
+impl Service for Flaky {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "flaky";
+
+    type IntercomMsg = service::NoIntercom;
+
+    fn prepare(_: ServiceState<Self>) -> Self {
+        Self
+    }
+
+    async fn start(self) {
+        panic!("flaky")
+    }
+}
+
+struct Misconfigured;
+
+#[async_trait]
+impl Service for Misconfigured {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "misconfigured";
+
+    type IntercomMsg = service::NoIntercom;
+
+    fn prepare(_: ServiceState<Self>) -> Self {
+        unreachable!("the watchdog calls `try_prepare`")
+    }
+
+    fn try_prepare(_: ServiceState<Self>) -> Result<Self, PrepareError> {
+        Err("missing configuration".into())
+    }
+
+    async fn start(self) {}
+}
+
+/// binds its listener while being prepared
+struct Listener {
+    listener: tokio::net::TcpListener,
+}
+
+#[async_trait]
+impl Service for Listener {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "listener";
+
+    type IntercomMsg = service::NoIntercom;
+
+    fn prepare(_: ServiceState<Self>) -> Self {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        Self {
+            listener: tokio::net::TcpListener::from_std(listener).unwrap(),
+        }
+    }
+
+    async fn start(mut self) {
+        LISTENING.store(true, Ordering::SeqCst);
+        while self.listener.accept().await.is_ok() {}
+    }
+}
+
+#[derive(Organix)]
+#[runtime(shared)]
+struct App {
+    warmup: service::ServiceManager<Warmup>,
+    broken: service::ServiceManager<Broken>,
+    misconfigured: service::ServiceManager<Misconfigured>,
+    #[restart(crash_loop(max = 1, window = "60s"))]
+    flaky: service::ServiceManager<Flaky>,
+    listener: service::ServiceManager<Listener>,
+}
+
+async fn wait_status<T: Service>(controller: &mut WatchdogQuery, expected: fn(&Status) -> bool) {
+    timeout(Duration::from_secs(2), async {
+        while !expected(&controller.status::<T>().await.unwrap().status) {
+            delay_for(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("the service did not reach the expected status");
+}
+
+#[test]
+fn started_once_initialized() {
+    let watchdog = WatchdogBuilder::<App>::new().build();
+    let mut controller = watchdog.control();
+
+    watchdog.spawn(async move {
+        assert!(!controller.has_ever_started::<Warmup>().await.unwrap());
+        controller.start::<Warmup>().await.unwrap();
+        let report = controller.status::<Warmup>().await.unwrap();
+        assert!(matches!(report.status, Status::Starting { .. }));
+        assert_eq!(report.uptime, Duration::default());
+        assert!(controller.is_starting::<Warmup>().await.unwrap());
+        assert!(!controller.is_running::<Warmup>().await.unwrap());
+
+        wait_status::<Warmup>(&mut controller, |status| {
+            matches!(status, Status::Started { .. })
+        })
+        .await;
+        assert!(INITIALIZED.load(Ordering::SeqCst));
+        assert!(controller.is_running::<Warmup>().await.unwrap());
+        assert!(controller
+            .current_status::<Warmup>()
+            .await
+            .unwrap()
+            .is_started());
+
+        delay_for(Duration::from_millis(20)).await;
+        let report = controller.status::<Warmup>().await.unwrap();
+        assert!(report.uptime >= Duration::from_millis(20));
+        assert!(report.has_ever_started);
+        assert_eq!(report.custom.get("initialized"), Some(&1));
+        assert!(matches!(
+            report.history.as_slice(),
+            [Status::Starting { .. }, Status::Started { .. }]
+        ));
+        assert!(controller.has_ever_started::<Warmup>().await.unwrap());
+
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+}
+
+#[test]
+fn await_ready() {
+    let watchdog = WatchdogBuilder::<App>::new().build();
+    let mut controller = watchdog.control();
+
+    watchdog.spawn(async move {
+        controller.start::<Warmup>().await.unwrap();
+        controller.start::<Broken>().await.unwrap();
+
+        controller
+            .await_ready(&[Warmup::SERVICE_IDENTIFIER], Duration::from_secs(2))
+            .await
+            .unwrap();
+        assert_eq!(
+            controller
+                .await_ready(
+                    &[
+                        Warmup::SERVICE_IDENTIFIER,
+                        Broken::SERVICE_IDENTIFIER,
+                        Misconfigured::SERVICE_IDENTIFIER,
+                    ],
+                    Duration::from_millis(100),
+                )
+                .await,
+            Err(WatchdogError::NotReady {
+                services: vec![
+                    Broken::SERVICE_IDENTIFIER,
+                    Misconfigured::SERVICE_IDENTIFIER
+                ]
+            })
+        );
+        assert!(matches!(
+            controller
+                .await_ready(&["unknown"], Duration::from_millis(100))
+                .await,
+            Err(WatchdogError::UnknownService { .. })
+        ));
+
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+}
+
+#[test]
+fn failed_initialization() {
+    let watchdog = WatchdogBuilder::<App>::new().build();
+    let mut controller = watchdog.control();
+
+    watchdog.spawn(async move {
+        let mut events = controller.subscribe();
+        controller.start::<Broken>().await.unwrap();
+        wait_status::<Broken>(&mut controller, Status::is_failed).await;
+
+        // the panic is recorded for the post-mortem
+        let report = controller.status::<Broken>().await.unwrap();
+        assert_eq!(
+            report.last_panic.as_deref(),
+            Some("cannot initialize broken")
+        );
+        assert_eq!(
+            events.recv().await.unwrap(),
+            WatchdogEvent::ServicePanicked {
+                service_identifier: "broken",
+                message: "cannot initialize broken".to_owned(),
+            }
+        );
+
+        // a failed service can be started again
+        controller.start::<Broken>().await.unwrap();
+
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+}
+
+#[test]
+fn on_service_error() {
+    let errors = Arc::new(Mutex::new(Vec::new()));
+    let reported = Arc::clone(&errors);
+    let watchdog = WatchdogBuilder::<App>::new()
+        .on_service_error(move |service_identifier, info| {
+            reported.lock().unwrap().push((service_identifier, info))
+        })
+        .build();
+    let mut controller = watchdog.control();
+
+    watchdog.spawn(async move {
+        controller.start::<Broken>().await.unwrap();
+        wait_status::<Broken>(&mut controller, Status::is_failed).await;
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+    assert_eq!(
+        *errors.lock().unwrap(),
+        vec![(
+            "broken",
+            ServiceErrorInfo::Panicked {
+                message: "cannot initialize broken".to_owned()
+            }
+        )]
+    );
+}
+
+#[test]
+fn crash_loop() {
+    let watchdog = WatchdogBuilder::<App>::new().build();
+    let mut controller = watchdog.control();
+
+    watchdog.spawn(async move {
+        let mut events = controller.subscribe();
+
+        // started once and restarted once, within the crash loop budget
+        for _ in 0..2 {
+            controller.start::<Flaky>().await.unwrap();
+            wait_status::<Flaky>(&mut controller, Status::is_shutdown).await;
+        }
+
+        // the second restart is one too many, evaluated once it exits
+        controller.start::<Flaky>().await.unwrap();
+        wait_status::<Flaky>(&mut controller, Status::is_failed).await;
+
+        let window = Duration::from_secs(60);
+        let refused = Err(WatchdogError::CannotStartService {
+            service_identifier: "flaky",
+            source: ServiceError::CrashLooping {
+                restarts: 2,
+                window,
+            },
+        });
+        // the refused starts are not restarts
+        assert_eq!(controller.start::<Flaky>().await, refused);
+        assert_eq!(controller.start::<Flaky>().await, refused);
+
+        loop {
+            match events.recv().await.unwrap() {
+                WatchdogEvent::ServicePanicked { .. } => continue,
+                event => {
+                    assert_eq!(
+                        event,
+                        WatchdogEvent::CrashLooping {
+                            service_identifier: "flaky",
+                            restarts: 2,
+                            window,
+                        }
+                    );
+                    break;
+                }
+            }
+        }
+
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+}
+
+#[test]
+fn failed_preparation() {
+    let watchdog = WatchdogBuilder::<App>::new().build();
+    let mut controller = watchdog.control();
+
+    watchdog.spawn(async move {
+        assert_cannot_prepare(controller.start::<Misconfigured>().await);
+        let status = controller.status::<Misconfigured>().await.unwrap().status;
+        assert!(status.is_failed());
+        assert!(!controller
+            .has_ever_started::<Misconfigured>()
+            .await
+            .unwrap());
+
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+}
+
+/// `run!` returns the error of the service that failed to start
+#[test]
+fn run_failed_preparation() {
+    assert_cannot_prepare(organix::run!(App, start: [Warmup, Misconfigured]));
+}
+
+/// the error of `Misconfigured::try_prepare` is kept as the source of the
+/// start error
+fn assert_cannot_prepare(result: Result<(), WatchdogError>) {
+    use std::error::Error as _;
+
+    match result {
+        Err(WatchdogError::CannotStartService {
+            service_identifier: "misconfigured",
+            source: error @ ServiceError::CannotPrepare { .. },
+        }) => {
+            assert_eq!(error.source().unwrap().to_string(), "missing configuration");
+            assert_eq!(
+                error,
+                ServiceError::CannotPrepare {
+                    source: Arc::from(PrepareError::from("missing configuration"))
+                }
+            );
+        }
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+/// `prepare` runs within the service's runtime, with its drivers
+#[test]
+fn prepare_within_runtime() {
+    let watchdog = WatchdogBuilder::<App>::new().build();
+    let mut controller = watchdog.control();
+
+    watchdog.spawn(async move {
+        controller.start::<Listener>().await.unwrap();
+        wait_status::<Listener>(&mut controller, Status::is_started).await;
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+
+    assert!(LISTENING.load(Ordering::SeqCst));
+}
@@ -0,0 +1,796 @@
+//! test the intercom from outside of the services
+//!
+
+use async_trait::async_trait;
+use organix::{
+    service, IntercomMsg, Organix, Service, ServiceIdentifier, ServiceState, WatchdogBuilder,
+    WatchdogError, WatchdogEvent,
+};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+struct Echo {
+    state: ServiceState<Self>,
+}
+
+#[derive(Debug, IntercomMsg)]
+struct EchoMsg;
+
+#[async_trait]
+impl Service for Echo {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "echo";
+
+    type IntercomMsg = EchoMsg;
+
+    fn prepare(state: ServiceState<Self>) -> Self {
+        Self { state }
+    }
+
+    async fn start(mut self) {
+        while self.state.intercom_mut().recv().await.is_some() {}
+    }
+}
+
+static DRAINED: AtomicUsize = AtomicUsize::new(0);
+
+/// slowly process its messages, draining its queue on shutdown
+struct Drainer {
+    state: ServiceState<Self>,
+}
+
+#[async_trait]
+impl Service for Drainer {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "drainer";
+
+    type IntercomMsg = EchoMsg;
+
+    fn prepare(mut state: ServiceState<Self>) -> Self {
+        state.drain_on_shutdown(true);
+        Self { state }
+    }
+
+    async fn start(mut self) {
+        while self.state.intercom_mut().recv().await.is_some() {
+            tokio::time::delay_for(Duration::from_millis(10)).await;
+            DRAINED.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
+
+/// never finishes, even once shutting down
+struct Stuck {
+    state: ServiceState<Self>,
+}
+
+#[async_trait]
+impl Service for Stuck {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "stuck";
+
+    type IntercomMsg = EchoMsg;
+
+    fn prepare(mut state: ServiceState<Self>) -> Self {
+        state.drain_on_shutdown_with_timeout(Duration::from_millis(50));
+        Self { state }
+    }
+
+    async fn start(self) {
+        let _state = self.state;
+        futures_util::future::pending::<()>().await
+    }
+}
+
+/// a message for either lane of the intercom
+#[derive(Debug)]
+struct Prioritized(service::Priority);
+
+impl service::IntercomMsg for Prioritized {
+    fn priority(&self) -> service::Priority {
+        self.0
+    }
+}
+
+/// never receives its messages
+struct Clogged {
+    state: ServiceState<Self>,
+}
+
+#[async_trait]
+impl Service for Clogged {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "clogged";
+
+    type IntercomMsg = Prioritized;
+
+    fn prepare(state: ServiceState<Self>) -> Self {
+        Self { state }
+    }
+
+    async fn start(self) {
+        let _state = self.state;
+        futures_util::future::pending::<()>().await
+    }
+}
+
+#[derive(Debug)]
+struct Ping;
+
+#[derive(Debug)]
+struct Reset;
+
+organix::intercom_enum! {
+    #[derive(Debug)]
+    enum CounterMsg {
+        Ping(Ping),
+        Reset(Reset),
+    }
+}
+
+static PINGS: AtomicUsize = AtomicUsize::new(0);
+static RESETS: AtomicUsize = AtomicUsize::new(0);
+
+/// accepts different types of messages
+struct Counter {
+    state: ServiceState<Self>,
+}
+
+#[async_trait]
+impl Service for Counter {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "counter";
+
+    type IntercomMsg = CounterMsg;
+
+    fn prepare(state: ServiceState<Self>) -> Self {
+        Self { state }
+    }
+
+    async fn start(mut self) {
+        while let Some(msg) = self.state.intercom_mut().recv().await {
+            match msg {
+                CounterMsg::Ping(Ping) => PINGS.fetch_add(1, Ordering::SeqCst),
+                CounterMsg::Reset(Reset) => RESETS.fetch_add(1, Ordering::SeqCst),
+            };
+        }
+    }
+}
+
+static LOOPED: AtomicUsize = AtomicUsize::new(0);
+
+/// handles its messages with `run_loop`
+struct Looper {
+    state: ServiceState<Self>,
+}
+
+#[async_trait]
+impl Service for Looper {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "looper";
+
+    type IntercomMsg = EchoMsg;
+
+    fn prepare(state: ServiceState<Self>) -> Self {
+        Self { state }
+    }
+
+    async fn start(mut self) {
+        self.state
+            .run_loop(|EchoMsg| async {
+                LOOPED.fetch_add(1, Ordering::SeqCst);
+            })
+            .await
+    }
+}
+
+/// a message only worth processing within its time to live
+#[derive(Debug)]
+struct Timed {
+    id: usize,
+    ttl: Option<Duration>,
+}
+
+impl service::IntercomMsg for Timed {
+    fn ttl(&self) -> Option<Duration> {
+        self.ttl
+    }
+}
+
+static DELIVERED: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+
+/// takes its time to process every message
+struct Slow {
+    state: ServiceState<Self>,
+}
+
+#[async_trait]
+impl Service for Slow {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "slow";
+
+    type IntercomMsg = Timed;
+
+    fn prepare(state: ServiceState<Self>) -> Self {
+        Self { state }
+    }
+
+    async fn start(mut self) {
+        while let Some(Timed { id, .. }) = self.state.intercom_mut().recv().await {
+            DELIVERED.lock().unwrap().push(id);
+            tokio::time::delay_for(Duration::from_millis(100)).await;
+        }
+    }
+}
+
+#[derive(Debug, IntercomMsg)]
+struct Sample(&'static str);
+
+static AGGREGATED: Mutex<Vec<&str>> = Mutex::new(Vec::new());
+
+/// aggregates the samples of many producers, fairly
+struct Aggregator {
+    state: ServiceState<Self>,
+}
+
+#[async_trait]
+impl Service for Aggregator {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "aggregator";
+
+    type IntercomMsg = Sample;
+
+    fn prepare(state: ServiceState<Self>) -> Self {
+        Self { state }
+    }
+
+    async fn start(mut self) {
+        // let the producers queue their samples
+        tokio::time::delay_for(Duration::from_millis(100)).await;
+        while let Some(Sample(producer)) = self.state.intercom_mut().recv().await {
+            AGGREGATED.lock().unwrap().push(producer);
+        }
+    }
+}
+
+#[derive(Debug, IntercomMsg)]
+struct Ask(tokio::sync::oneshot::Sender<&'static str>);
+
+/// answers every questions
+struct Oracle {
+    state: ServiceState<Self>,
+}
+
+#[async_trait]
+impl Service for Oracle {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "oracle";
+
+    type IntercomMsg = Ask;
+
+    fn prepare(state: ServiceState<Self>) -> Self {
+        Self { state }
+    }
+
+    async fn start(mut self) {
+        while let Some(Ask(reply)) = self.state.intercom_mut().recv().await {
+            let _ = reply.send("42");
+        }
+    }
+}
+
+/// sums the admin commands it receives
+struct Accumulator {
+    admin: tokio::sync::mpsc::Receiver<service::AdminRequest<Self>>,
+}
+
+#[async_trait]
+impl Service for Accumulator {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "accumulator";
+
+    type IntercomMsg = service::NoIntercom;
+
+    fn prepare(state: ServiceState<Self>) -> Self {
+        Self {
+            admin: state.admin_rx(),
+        }
+    }
+
+    async fn start(mut self) {
+        let mut total = 0;
+        while let Some(request) = self.admin.recv().await {
+            total += *request.cmd();
+            request.reply(total);
+        }
+    }
+}
+
+impl service::ServiceAdmin for Accumulator {
+    type AdminCmd = u32;
+    type AdminReply = u32;
+}
+
+#[derive(Organix)]
+#[runtime(shared)]
+struct EchoApp {
+    echo: service::ServiceManager<Echo>,
+    drainer: service::ServiceManager<Drainer>,
+    stuck: service::ServiceManager<Stuck>,
+    counter: service::ServiceManager<Counter>,
+    looper: service::ServiceManager<Looper>,
+    slow: service::ServiceManager<Slow>,
+    #[intercom(fair)]
+    aggregator: service::ServiceManager<Aggregator>,
+    #[intercom(ordered)]
+    oracle: service::ServiceManager<Oracle>,
+    accumulator: service::ServiceManager<Accumulator>,
+    clogged: service::ServiceManager<Clogged>,
+}
+
+#[derive(Organix)]
+struct NoServices;
+
+async fn wait_shutdown<T: Service>(controller: &mut organix::WatchdogQuery) {
+    tokio::time::timeout(Duration::from_secs(2), async {
+        while !controller.status::<T>().await.unwrap().status.is_shutdown() {
+            tokio::time::delay_for(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("the service should have been shutdown");
+}
+
+#[test]
+fn wait_connected() {
+    let watchdog = WatchdogBuilder::<EchoApp>::new().build();
+    let mut controller = watchdog.control();
+
+    watchdog.spawn(async move {
+        let mut echo = controller.intercom::<Echo>();
+
+        assert!(!echo.is_connected().await);
+        assert_eq!(
+            echo.wait_connected(Duration::from_millis(100)).await,
+            Err(WatchdogError::Timeout {
+                context: "service to be started"
+            })
+        );
+
+        controller.start::<Echo>().await.unwrap();
+
+        echo.wait_connected(Duration::from_secs(1)).await.unwrap();
+        assert!(echo.is_connected().await);
+        echo.send(EchoMsg).await.unwrap();
+
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+}
+
+/// only a service not started yet is waited for
+#[test]
+fn wait_connected_unknown_service() {
+    let watchdog = WatchdogBuilder::<NoServices>::new().build();
+    let mut controller = watchdog.control();
+    let result = Arc::new(Mutex::new(None));
+
+    let waited = Arc::clone(&result);
+    watchdog.spawn(async move {
+        let mut echo = controller.intercom::<Echo>();
+        let error = echo.wait_connected(Duration::from_secs(60)).await;
+        *waited.lock().unwrap() = Some(error);
+
+        controller.shutdown().await;
+    });
+    watchdog.wait_finished();
+
+    assert!(matches!(
+        result.lock().unwrap().take(),
+        Some(Err(WatchdogError::UnknownService {
+            service_identifier: "echo",
+            ..
+        }))
+    ));
+}
+
+/// a service not receiving its messages is reported once
+#[test]
+fn stalled_consumer() {
+    let window = Duration::from_millis(20);
+    let watchdog = WatchdogBuilder::<EchoApp>::new()
+        .stalled_consumer(window, 2)
+        .build();
+    let mut controller = watchdog.control();
+    let reported = Arc::new(Mutex::new(Vec::new()));
+
+    let events = Arc::clone(&reported);
+    watchdog.spawn(async move {
+        let mut subscriber = controller.subscribe();
+        controller.start::<Stuck>().await.unwrap();
+        controller.start::<Echo>().await.unwrap();
+
+        let mut stuck = controller.intercom::<Stuck>().into_sender().await.unwrap();
+        let mut echo = controller.intercom::<Echo>().into_sender().await.unwrap();
+        for _ in 0..3 {
+            stuck.send(EchoMsg).await.unwrap();
+            echo.send(EchoMsg).await.unwrap();
+        }
+
+        tokio::time::delay_for(window * 10).await;
+        while let Ok(event) = subscriber.try_recv() {
+            events.lock().unwrap().push(event);
+        }
+
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+
+    assert_eq!(
+        *reported.lock().unwrap(),
+        vec![WatchdogEvent::StalledConsumer {
+            service_identifier: "stuck",
+            queue_depth: 3,
+            window,
+        }]
+    );
+}
+
+/// the message is given back when it cannot be delivered
+#[test]
+fn undelivered_message() {
+    let watchdog = WatchdogBuilder::<EchoApp>::new().build();
+    let mut controller = watchdog.control();
+    let undelivered = std::sync::Arc::new(Mutex::new(None));
+    let result = std::sync::Arc::clone(&undelivered);
+
+    watchdog.spawn(async move {
+        let mut oracle = controller.intercom::<Oracle>();
+        let (reply, _) = tokio::sync::oneshot::channel();
+
+        let error = oracle.send(Ask(reply)).await.unwrap_err();
+        let not_connected = matches!(
+            error.reason(),
+            WatchdogError::CannotConnectToService {
+                service_identifier: "oracle",
+                ..
+            }
+        );
+        let Ask(reply) = error.into_inner();
+        *result.lock().unwrap() = Some((not_connected, reply.send("undelivered").is_err()));
+
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+
+    assert_eq!(undelivered.lock().unwrap().take(), Some((true, true)));
+}
+
+#[test]
+fn drain_on_shutdown() {
+    let watchdog = WatchdogBuilder::<EchoApp>::new().build();
+    let mut controller = watchdog.control();
+
+    watchdog.spawn(async move {
+        controller.start::<Drainer>().await.unwrap();
+
+        let mut drainer = controller.intercom::<Drainer>();
+        for _ in 0..5 {
+            drainer.send(EchoMsg).await.unwrap();
+        }
+
+        controller.stop::<Drainer>().await.unwrap();
+        wait_shutdown::<Drainer>(&mut controller).await;
+        assert_eq!(DRAINED.load(Ordering::SeqCst), 5);
+
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+}
+
+#[test]
+fn drain_timeout() {
+    let watchdog = WatchdogBuilder::<EchoApp>::new().build();
+    let mut controller = watchdog.control();
+
+    watchdog.spawn(async move {
+        controller.start::<Stuck>().await.unwrap();
+        controller.stop::<Stuck>().await.unwrap();
+        wait_shutdown::<Stuck>(&mut controller).await;
+
+        // the status is final, the service can be started again
+        controller.start::<Stuck>().await.unwrap();
+
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+}
+
+#[test]
+fn send_as() {
+    let watchdog = WatchdogBuilder::<EchoApp>::new().build();
+    let mut controller = watchdog.control();
+
+    watchdog.spawn(async move {
+        controller.start::<Counter>().await.unwrap();
+
+        let mut counter = controller.intercom::<Counter>();
+        counter.send_as(Ping).await.unwrap();
+        counter.send_as(Ping).await.unwrap();
+        counter.send_as(Reset).await.unwrap();
+
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while PINGS.load(Ordering::SeqCst) < 2 || RESETS.load(Ordering::SeqCst) < 1 {
+                tokio::time::delay_for(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("all the messages should have been received");
+
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+}
+
+#[test]
+fn utilization() {
+    let watchdog = WatchdogBuilder::<EchoApp>::new().build();
+    let mut controller = watchdog.control();
+
+    watchdog.spawn(async move {
+        controller.start::<Stuck>().await.unwrap();
+
+        let mut stuck = controller.intercom::<Stuck>();
+        for _ in 0..5 {
+            stuck.send(EchoMsg).await.unwrap();
+        }
+
+        let intercom = controller.status::<Stuck>().await.unwrap().intercom;
+        assert_eq!(intercom.queue_depth, 5);
+        assert_eq!(intercom.capacity, 20);
+        assert!((intercom.utilization - 0.25).abs() < f64::EPSILON);
+
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+}
+
+/// the messages rejected by a full intercom are not counted as sent
+#[test]
+fn utilization_saturated() {
+    use service::Priority;
+    use tokio::sync::mpsc::error::TrySendError;
+
+    let watchdog = WatchdogBuilder::<EchoApp>::new().build();
+    let mut controller = watchdog.control();
+    let result = Arc::new(Mutex::new(None));
+
+    let status = Arc::clone(&result);
+    watchdog.spawn(async move {
+        controller.start::<Clogged>().await.unwrap();
+
+        let mut clogged = controller.intercom::<Clogged>();
+        clogged.send(Prioritized(Priority::Normal)).await.unwrap();
+        let mut full = 0;
+        for priority in [Priority::Normal, Priority::High].iter() {
+            while clogged.try_send(Prioritized(*priority)).is_ok() {}
+            for _ in 0..3 {
+                if let Err(TrySendError::Full(_)) = clogged.try_send(Prioritized(*priority)) {
+                    full += 1;
+                }
+            }
+        }
+
+        let intercom = controller.status::<Clogged>().await.unwrap().intercom;
+        *status.lock().unwrap() = Some((full, intercom));
+
+        controller.shutdown().await;
+    });
+    watchdog.wait_finished();
+
+    let (full, intercom) = result.lock().unwrap().take().unwrap();
+    assert_eq!(full, 6);
+    assert_eq!(intercom.number_sent, 20);
+    assert_eq!(intercom.queue_depth, intercom.capacity);
+    assert!((intercom.utilization - 1.0).abs() < f64::EPSILON);
+}
+
+/// the loop handles the messages and stops once the service is shutting down
+#[test]
+fn run_loop() {
+    let watchdog = WatchdogBuilder::<EchoApp>::new().build();
+    let mut controller = watchdog.control();
+
+    watchdog.spawn(async move {
+        controller.start::<Looper>().await.unwrap();
+
+        let mut looper = controller.intercom::<Looper>();
+        for _ in 0..3 {
+            looper.send(EchoMsg).await.unwrap();
+        }
+
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while LOOPED.load(Ordering::SeqCst) < 3 {
+                tokio::time::delay_for(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("all the messages should have been handled");
+
+        controller.stop::<Looper>().await.unwrap();
+        wait_shutdown::<Looper>(&mut controller).await;
+
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+}
+
+/// the raw sender can be cloned and handed out to many tasks
+#[test]
+fn into_sender() {
+    let watchdog = WatchdogBuilder::<EchoApp>::new().build();
+    let mut controller = watchdog.control();
+
+    watchdog.spawn(async move {
+        controller.start::<Stuck>().await.unwrap();
+
+        let sender = controller.intercom::<Stuck>().into_sender().await.unwrap();
+        let tasks = (0..3).map(|_| {
+            let mut sender = sender.clone();
+            tokio::spawn(async move { sender.send(EchoMsg).await.unwrap() })
+        });
+        futures_util::future::join_all(tasks).await;
+
+        let intercom = controller.status::<Stuck>().await.unwrap().intercom;
+        assert_eq!(intercom.queue_depth, 3);
+
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+}
+
+/// a message queued behind a slow one for longer than its time to live is
+/// dropped rather than delivered stale
+#[test]
+fn ttl() {
+    let watchdog = WatchdogBuilder::<EchoApp>::new().build();
+    let mut controller = watchdog.control();
+    let expired = std::sync::Arc::new(Mutex::new(None));
+    let result = std::sync::Arc::clone(&expired);
+
+    watchdog.spawn(async move {
+        controller.start::<Slow>().await.unwrap();
+
+        let mut slow = controller.intercom::<Slow>();
+        for &(id, ttl) in &[(1, None), (2, Some(Duration::from_millis(20))), (3, None)] {
+            slow.send(Timed { id, ttl }).await.unwrap();
+        }
+
+        let _ = tokio::time::timeout(Duration::from_secs(1), async {
+            while DELIVERED.lock().unwrap().len() < 2 {
+                tokio::time::delay_for(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+
+        let intercom = controller.status::<Slow>().await.unwrap().intercom;
+        *result.lock().unwrap() = Some((intercom.number_expired, intercom.queue_depth));
+
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+
+    assert_eq!(*DELIVERED.lock().unwrap(), vec![1, 3]);
+    assert_eq!(*expired.lock().unwrap(), Some((1, 0)));
+}
+
+/// the samples of a slow producer queued behind the ones of a chatty
+/// producer are not starved
+#[test]
+fn fair() {
+    let watchdog = WatchdogBuilder::<EchoApp>::new().build();
+    let mut controller = watchdog.control();
+
+    watchdog.spawn(async move {
+        controller.start::<Aggregator>().await.unwrap();
+
+        let mut chatty = controller
+            .intercom::<Aggregator>()
+            .into_sender()
+            .await
+            .unwrap();
+        let mut slow = controller
+            .intercom::<Aggregator>()
+            .into_sender()
+            .await
+            .unwrap();
+        for _ in 0..5 {
+            chatty.send(Sample("chatty")).await.unwrap();
+        }
+        slow.send(Sample("slow")).await.unwrap();
+        for _ in 0..4 {
+            chatty.send(Sample("chatty")).await.unwrap();
+        }
+
+        let _ = tokio::time::timeout(Duration::from_secs(1), async {
+            while AGGREGATED.lock().unwrap().len() < 10 {
+                tokio::time::delay_for(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+
+    let aggregated = AGGREGATED.lock().unwrap();
+    assert_eq!(aggregated.len(), 10);
+    assert_eq!(aggregated[..2], ["chatty", "slow"]);
+}
+
+/// send a one-off message without keeping the intercom around
+#[test]
+fn with_service() {
+    let watchdog = WatchdogBuilder::<EchoApp>::new().build();
+    let mut controller = watchdog.control();
+    let answer = std::sync::Arc::new(Mutex::new(None));
+    let result = std::sync::Arc::clone(&answer);
+
+    watchdog.spawn(async move {
+        controller.start::<Oracle>().await.unwrap();
+
+        let (reply, answer) = tokio::sync::oneshot::channel();
+        let sent = controller
+            .with_service::<Oracle, _, _, _>(|mut oracle| async move {
+                Ok(oracle.send(Ask(reply)).await?)
+            })
+            .await;
+        *result.lock().unwrap() = Some((sent, answer.await.ok()));
+
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+
+    assert_eq!(*answer.lock().unwrap(), Some((Ok(()), Some("42"))));
+}
+
+/// the admin commands are answered by the service while it is running
+#[test]
+fn admin() {
+    let watchdog = WatchdogBuilder::<EchoApp>::new().build();
+    let mut controller = watchdog.control();
+    let replies = Arc::new(Mutex::new(Vec::new()));
+
+    let results = Arc::clone(&replies);
+    watchdog.spawn(async move {
+        let stopped = controller.admin::<Accumulator>(1).await;
+        controller.start::<Accumulator>().await.unwrap();
+        let first = controller.admin::<Accumulator>(2).await;
+        let second = controller.admin::<Accumulator>(3).await;
+        controller.stop::<Accumulator>().await.unwrap();
+        wait_shutdown::<Accumulator>(&mut controller).await;
+        let shutdown = controller.admin::<Accumulator>(4).await;
+        *results.lock().unwrap() = vec![stopped, first, second, shutdown];
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+
+    let cannot_connect = || {
+        Err(WatchdogError::CannotConnectToService {
+            service_identifier: "accumulator",
+            retry_attempted: false,
+        })
+    };
+    assert_eq!(
+        *replies.lock().unwrap(),
+        vec![cannot_connect(), Ok(2), Ok(5), cannot_connect()]
+    );
+}
@@ -0,0 +1,63 @@
+//! test the logs written to a file per service, needs the `log-dir`
+//! feature
+//!
+//! the layer is installed as the global subscriber: keep this test alone
+//! in its own test binary.
+#![cfg(feature = "log-dir")]
+
+use async_trait::async_trait;
+use organix::{
+    service, Organix, Service, ServiceIdentifier, ServiceManager, ServiceState, WatchdogBuilder,
+};
+use std::fs;
+
+struct Chatty {
+    state: ServiceState<Self>,
+}
+
+#[async_trait]
+impl Service for Chatty {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "chatty";
+
+    type IntercomMsg = service::NoIntercom;
+
+    fn prepare(state: ServiceState<Self>) -> Self {
+        Self { state }
+    }
+
+    async fn start(mut self) {
+        tracing::info!(lines = 1, "hello from chatty");
+        self.state.watchdog_controller().clone().shutdown().await;
+    }
+}
+
+#[derive(Organix)]
+#[runtime(shared)]
+struct App {
+    chatty: ServiceManager<Chatty>,
+}
+
+#[test]
+fn per_service_log_dir() {
+    let dir = std::env::temp_dir().join(format!("organix-log-dir-{}", std::process::id()));
+
+    let watchdog = WatchdogBuilder::<App>::new()
+        .per_service_log_dir(&dir)
+        .build();
+    tracing::info!("hello from the test");
+
+    let mut controller = watchdog.control();
+    watchdog.spawn(async move {
+        controller.start::<Chatty>().await.unwrap();
+    });
+    watchdog.wait_finished();
+
+    let chatty = fs::read_to_string(dir.join("chatty.log")).unwrap();
+    let organix = fs::read_to_string(dir.join("organix.log")).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(chatty.contains("INFO log_dir: hello from chatty lines=1"));
+    assert!(!chatty.contains("hello from the test"));
+    assert!(organix.contains("hello from the test"));
+    assert!(!organix.contains("hello from chatty"));
+}
@@ -7,8 +7,11 @@ use async_trait::async_trait;
 use organix::{
     service, IntercomMsg, Organix, Service, ServiceIdentifier, ServiceState, WatchdogBuilder,
 };
-use std::time::Duration;
-use tokio::time::delay_for;
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{sync::oneshot, time::delay_for};
 
 struct Ping {
     state: ServiceState<Self>,
@@ -72,12 +75,38 @@ impl Service for Pong {
     }
 }
 
+/// answers every message it receives
+struct Replier {
+    state: ServiceState<Self>,
+}
+
+#[derive(Debug, IntercomMsg)]
+struct ReplyMsg(oneshot::Sender<()>);
+
+#[async_trait]
+impl Service for Replier {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "replier";
+
+    type IntercomMsg = ReplyMsg;
+
+    fn prepare(state: ServiceState<Self>) -> Self {
+        Self { state }
+    }
+
+    async fn start(mut self) {
+        while let Some(ReplyMsg(reply)) = self.state.intercom_mut().recv().await {
+            let _ = reply.send(());
+        }
+    }
+}
+
 #[derive(Organix)]
 #[runtime(shared)]
 struct PingPongServices {
     #[runtime(time)]
     ping: service::ServiceManager<Ping>,
     pong: service::ServiceManager<Pong>,
+    replier: service::ServiceManager<Replier>,
 }
 
 /// test that the execution of the watchdog will be stopped shortly
@@ -92,7 +121,9 @@ fn ping_pong() {
 
     tracing::subscriber::set_global_default(subscriber).expect("setting tracing default failed");
 
-    let watchdog = WatchdogBuilder::<PingPongServices>::new().build();
+    let watchdog = WatchdogBuilder::<PingPongServices>::new()
+        .tracing_prefix("ping_pong")
+        .build();
 
     let mut controller = watchdog.control();
     watchdog.spawn(async move {
@@ -104,3 +135,90 @@ fn ping_pong() {
 
     watchdog.wait_finished();
 }
+
+#[test]
+fn service_catalog() {
+    assert_eq!(
+        PingPongServices::service_catalog(),
+        &[("ping", "ping"), ("pong", "pong"), ("replier", "replier")]
+    );
+}
+
+/// the handles of the running app deliver the messages to the services
+#[test]
+fn handles() {
+    let watchdog = WatchdogBuilder::<PingPongServices>::new().build();
+    let mut controller = watchdog.control();
+    let replied = Arc::new(Mutex::new(None));
+
+    let result = Arc::clone(&replied);
+    watchdog.spawn(async move {
+        controller.start::<Replier>().await.unwrap();
+        let mut handles = PingPongServices::handles(&mut controller)
+            .await
+            .unwrap()
+            .clone();
+
+        let (reply, replied) = oneshot::channel();
+        let sent = handles.replier.send(ReplyMsg(reply)).await.is_ok();
+        let replied = tokio::time::timeout(Duration::from_secs(1), replied).await;
+        // ping is not started, nothing receives its messages
+        let not_started = handles.ping.try_send(PingMsg).is_err();
+
+        *result.lock().unwrap() = Some((sent, matches!(replied, Ok(Ok(()))), not_started));
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+    assert_eq!(*replied.lock().unwrap(), Some((true, true, true)));
+}
+
+/// the dynamic view of the running app, through the watchdog
+#[test]
+fn services_dyn() {
+    let watchdog = WatchdogBuilder::<PingPongServices>::new().build();
+    let mut controller = watchdog.control();
+    let views = Arc::new(Mutex::new(Vec::new()));
+
+    let result = Arc::clone(&views);
+    watchdog.spawn(async move {
+        let view = |reports: Vec<service::StatusReport>| -> Vec<_> {
+            reports
+                .into_iter()
+                .map(|report| (report.identifier, report.status.is_started()))
+                .collect()
+        };
+
+        controller.start::<Replier>().await.unwrap();
+        controller
+            .await_ready(&["replier"], Duration::from_secs(1))
+            .await
+            .unwrap();
+        let running = view(controller.status_all().await.unwrap());
+
+        controller
+            .stop_by_identifier("replier", service::ShutdownReason::UserRequested)
+            .await
+            .unwrap();
+        let mut stopped = Vec::new();
+        for _ in 0..100 {
+            stopped = view(controller.status_all().await.unwrap());
+            if stopped.iter().all(|(_, started)| !started) {
+                break;
+            }
+            delay_for(Duration::from_millis(10)).await;
+        }
+
+        result.lock().unwrap().extend(vec![running, stopped]);
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+    assert_eq!(
+        *views.lock().unwrap(),
+        vec![
+            vec![("ping", false), ("pong", false), ("replier", true)],
+            vec![("ping", false), ("pong", false), ("replier", false)],
+        ]
+    );
+}
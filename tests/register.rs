@@ -0,0 +1,170 @@
+//! test the services registered in a running watchdog
+//!
+
+use async_trait::async_trait;
+use organix::{
+    runtime::{Runtime, RuntimeConfig},
+    service::{self, Status},
+    IntercomMsg, Organix, Service, ServiceIdentifier, ServiceManager, ServiceState,
+    WatchdogBuilder, WatchdogError,
+};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{sync::oneshot, time::delay_for};
+
+struct Core {
+    state: ServiceState<Self>,
+}
+
+#[async_trait]
+impl Service for Core {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "core";
+
+    type IntercomMsg = service::NoIntercom;
+
+    fn prepare(state: ServiceState<Self>) -> Self {
+        Self { state }
+    }
+
+    async fn start(mut self) {
+        while self.state.intercom_mut().recv().await.is_some() {}
+    }
+}
+
+#[derive(Debug, IntercomMsg)]
+struct Ping(oneshot::Sender<()>);
+
+/// not part of the app, registered once the watchdog is running
+struct Plugin {
+    state: ServiceState<Self>,
+}
+
+#[async_trait]
+impl Service for Plugin {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "plugin";
+
+    type IntercomMsg = Ping;
+
+    fn prepare(state: ServiceState<Self>) -> Self {
+        Self { state }
+    }
+
+    async fn start(mut self) {
+        while let Some(Ping(reply)) = self.state.intercom_mut().recv().await {
+            let _ = reply.send(());
+        }
+    }
+}
+
+#[derive(Organix)]
+#[runtime(shared)]
+struct App {
+    core: service::ServiceManager<Core>,
+}
+
+#[derive(Debug, Default)]
+struct Outcome {
+    before_register: Option<Result<(), WatchdogError>>,
+    registered: Option<Result<(), WatchdogError>>,
+    registered_twice: Option<Result<(), WatchdogError>>,
+    registered_core: Option<Result<(), WatchdogError>>,
+    /// `is_registered` before and after the registration
+    known: (bool, bool),
+    unknown: Option<Result<(), WatchdogError>>,
+    started: Option<Result<(), WatchdogError>>,
+    pong: bool,
+    stopped: bool,
+}
+
+#[test]
+fn register() {
+    let mut runtime = Runtime::build(RuntimeConfig::new("plugin")).unwrap();
+    let plugin = ServiceManager::<Plugin>::with_runtime(&mut runtime);
+    let duplicate = ServiceManager::<Plugin>::with_runtime(&mut runtime);
+    let core = ServiceManager::<Core>::with_runtime(&mut runtime);
+
+    let watchdog = WatchdogBuilder::<App>::new().build();
+    let mut controller = watchdog.control();
+    let outcome = Arc::new(Mutex::new(Outcome::default()));
+    let result = Arc::clone(&outcome);
+
+    watchdog.spawn(async move {
+        let before_register = controller.start::<Plugin>().await;
+        let known_before = controller.is_registered("plugin");
+        let registered = controller.register(plugin).await;
+        let registered_twice = controller.register(duplicate).await;
+        let registered_core = controller.register(core).await;
+        let known = (known_before, controller.is_registered("plugin"));
+        let unknown = controller
+            .stop_by_identifier("unknown", service::ShutdownReason::UserRequested)
+            .await;
+        let started = controller.start::<Plugin>().await;
+
+        let (reply, pong) = oneshot::channel();
+        let _ = controller.intercom::<Plugin>().send(Ping(reply)).await;
+        let pong = pong.await.is_ok();
+
+        let _ = controller.stop::<Plugin>().await;
+        let mut stopped = false;
+        for _ in 0..100 {
+            let status = controller.status::<Plugin>().await.unwrap().status;
+            if matches!(status, Status::ShuttingDown { .. }) {
+                stopped = true;
+                break;
+            }
+            delay_for(Duration::from_millis(10)).await;
+        }
+
+        *result.lock().unwrap() = Outcome {
+            before_register: Some(before_register),
+            registered: Some(registered),
+            registered_twice: Some(registered_twice),
+            registered_core: Some(registered_core),
+            known,
+            unknown: Some(unknown),
+            started: Some(started),
+            pong,
+            stopped,
+        };
+
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+    drop(runtime);
+
+    let outcome = outcome.lock().unwrap();
+    assert!(matches!(
+        outcome.before_register,
+        Some(Err(WatchdogError::UnknownService {
+            service_identifier: "plugin",
+            ..
+        }))
+    ));
+    assert_eq!(outcome.registered, Some(Ok(())));
+    assert_eq!(
+        outcome.registered_twice,
+        Some(Err(WatchdogError::ServiceAlreadyRegistered {
+            service_identifier: "plugin"
+        }))
+    );
+    assert_eq!(
+        outcome.registered_core,
+        Some(Err(WatchdogError::ServiceAlreadyRegistered {
+            service_identifier: "core"
+        }))
+    );
+    assert_eq!(outcome.known, (false, true));
+    assert_eq!(
+        outcome.unknown,
+        Some(Err(WatchdogError::UnknownService {
+            service_identifier: "unknown",
+            possible_values: vec!["core", "plugin"],
+        }))
+    );
+    assert_eq!(outcome.started, Some(Ok(())));
+    assert!(outcome.pong);
+    assert!(outcome.stopped);
+}
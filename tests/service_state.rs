@@ -0,0 +1,498 @@
+//! test the helpers the `ServiceState` offers to the services
+//!
+
+use async_trait::async_trait;
+use organix::{
+    runtime::{Runtime, RuntimeConfig, RuntimePlacement, Runtimes},
+    service, Organix, Service, ServiceIdentifier, ServiceState, WatchdogBuilder,
+};
+use std::{
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::time::delay_for;
+
+static TICKED: AtomicBool = AtomicBool::new(false);
+static INTERVAL_REFUSED: AtomicBool = AtomicBool::new(false);
+static TICKER_DRIVERS: AtomicBool = AtomicBool::new(false);
+static NO_TIMER_DRIVERS: AtomicBool = AtomicBool::new(false);
+static RUNS: AtomicUsize = AtomicUsize::new(0);
+static SPINS: AtomicUsize = AtomicUsize::new(0);
+static BEACON: AtomicBool = AtomicBool::new(false);
+static WARMED_ON: Mutex<Option<String>> = Mutex::new(None);
+static DEFERRED: Mutex<Vec<&str>> = Mutex::new(Vec::new());
+static HEALED_RUNS: AtomicUsize = AtomicUsize::new(0);
+static BAILED_OUT: AtomicBool = AtomicBool::new(false);
+
+struct Ticker {
+    state: ServiceState<Self>,
+}
+
+struct NoTimer {
+    state: ServiceState<Self>,
+}
+
+#[async_trait]
+impl Service for Ticker {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "ticker";
+
+    type IntercomMsg = service::NoIntercom;
+
+    fn prepare(state: ServiceState<Self>) -> Self {
+        Self { state }
+    }
+
+    async fn start(mut self) {
+        TICKER_DRIVERS.store(
+            self.state.has_time() && !self.state.has_io(),
+            Ordering::SeqCst,
+        );
+        let mut interval = self.state.interval(Duration::from_millis(10));
+        for _ in 0..3 {
+            interval.tick().await;
+        }
+        TICKED.store(true, Ordering::SeqCst);
+    }
+}
+
+#[async_trait]
+impl Service for NoTimer {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "no_timer";
+
+    type IntercomMsg = service::NoIntercom;
+
+    fn prepare(state: ServiceState<Self>) -> Self {
+        Self { state }
+    }
+
+    async fn start(mut self) {
+        NO_TIMER_DRIVERS.store(
+            !self.state.has_time() && !self.state.has_io(),
+            Ordering::SeqCst,
+        );
+        let state = &self.state;
+        let interval = catch_unwind(AssertUnwindSafe(|| {
+            state.interval(Duration::from_millis(10));
+        }));
+        INTERVAL_REFUSED.store(interval.is_err(), Ordering::SeqCst);
+    }
+}
+
+/// counts its runs in its persistent state
+struct Restarter {
+    state: ServiceState<Self>,
+}
+
+#[async_trait]
+impl Service for Restarter {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "restarter";
+
+    type IntercomMsg = service::NoIntercom;
+
+    fn prepare(state: ServiceState<Self>) -> Self {
+        Self { state }
+    }
+
+    async fn start(self) {
+        let runs = self.state.persistent::<usize>().unwrap_or_default() + 1;
+        self.state.set_persistent(runs);
+        RUNS.store(runs, Ordering::SeqCst);
+    }
+}
+
+/// asks to be restarted on its first run
+struct SelfHealing {
+    state: ServiceState<Self>,
+}
+
+#[async_trait]
+impl Service for SelfHealing {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "self_healing";
+
+    type IntercomMsg = service::NoIntercom;
+
+    fn prepare(state: ServiceState<Self>) -> Self {
+        Self { state }
+    }
+
+    async fn start(self) {
+        let runs = self.state.persistent::<usize>().unwrap_or_default() + 1;
+        self.state.set_persistent(runs);
+        if runs == 1 {
+            self.state.request_restart();
+        }
+        HEALED_RUNS.store(runs, Ordering::SeqCst);
+    }
+}
+
+/// busy loops until it is asked to shutdown, only yielding through
+/// `maybe_yield`
+struct Spinner {
+    state: ServiceState<Self>,
+}
+
+#[async_trait]
+impl Service for Spinner {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "spinner";
+
+    type IntercomMsg = service::NoIntercom;
+
+    fn prepare(state: ServiceState<Self>) -> Self {
+        Self { state }
+    }
+
+    async fn start(mut self) {
+        while !self.state.status_reader().status().is_shutting_down() {
+            SPINS.fetch_add(1, Ordering::SeqCst);
+            self.state.maybe_yield().await;
+        }
+    }
+}
+
+struct Beacon;
+
+#[async_trait]
+impl Service for Beacon {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "beacon";
+
+    type IntercomMsg = service::NoIntercom;
+
+    fn prepare(_: ServiceState<Self>) -> Self {
+        Self
+    }
+
+    async fn start(self) {
+        BEACON.store(true, Ordering::SeqCst);
+    }
+}
+
+/// leaves a task on the watchdog's runtime and stops right away
+struct Warmer {
+    state: ServiceState<Self>,
+}
+
+#[async_trait]
+impl Service for Warmer {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "warmer";
+
+    type IntercomMsg = service::NoIntercom;
+
+    fn prepare(state: ServiceState<Self>) -> Self {
+        Self { state }
+    }
+
+    async fn start(self) {
+        self.state.watchdog_handle().spawn(async {
+            delay_for(Duration::from_millis(50)).await;
+            let thread = std::thread::current().name().map(str::to_owned);
+            *WARMED_ON.lock().unwrap() = thread;
+        });
+    }
+}
+
+#[derive(Debug, organix::IntercomMsg)]
+struct Retry(&'static str);
+
+/// defers a message and stops right away on its first run, then waits for
+/// the message it defers on its second run
+struct Deferring {
+    state: ServiceState<Self>,
+}
+
+#[async_trait]
+impl Service for Deferring {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "deferring";
+
+    type IntercomMsg = Retry;
+
+    fn prepare(state: ServiceState<Self>) -> Self {
+        Self { state }
+    }
+
+    async fn start(mut self) {
+        if self.state.persistent::<bool>().is_none() {
+            self.state.set_persistent(true);
+            self.state.defer(Retry("stale"), Duration::from_millis(20));
+            return;
+        }
+
+        self.state.defer(Retry("fresh"), Duration::from_millis(150));
+        while let Some(Retry(msg)) = self.state.intercom_mut().recv().await {
+            DEFERRED.lock().unwrap().push(msg);
+            if msg == "fresh" {
+                break;
+            }
+        }
+    }
+}
+
+#[derive(Organix)]
+struct Services {
+    #[runtime(time)]
+    ticker: service::ServiceManager<Ticker>,
+    no_timer: service::ServiceManager<NoTimer>,
+}
+
+#[derive(Organix)]
+#[runtime(shared)]
+struct Restartable {
+    restarter: service::ServiceManager<Restarter>,
+    deferring: service::ServiceManager<Deferring>,
+    self_healing: service::ServiceManager<SelfHealing>,
+}
+
+/// blocks its thread until asked to shutdown
+struct Blocking {
+    state: ServiceState<Self>,
+}
+
+#[async_trait]
+impl Service for Blocking {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "blocking";
+
+    type IntercomMsg = service::NoIntercom;
+
+    fn prepare(state: ServiceState<Self>) -> Self {
+        Self { state }
+    }
+
+    async fn start(self) {
+        let state = self.state;
+        tokio::task::block_in_place(|| {
+            while !state.is_shutting_down() {
+                std::thread::sleep(Duration::from_millis(5));
+            }
+        });
+        BAILED_OUT.store(true, Ordering::SeqCst);
+    }
+}
+
+#[derive(Organix)]
+#[runtime(shared)]
+struct Colocated {
+    spinner: service::ServiceManager<Spinner>,
+    beacon: service::ServiceManager<Beacon>,
+    warmer: service::ServiceManager<Warmer>,
+    blocking: service::ServiceManager<Blocking>,
+}
+
+#[test]
+fn interval_requires_time_driver() {
+    let watchdog = WatchdogBuilder::<Services>::new().build();
+    let mut controller = watchdog.control();
+
+    watchdog.spawn(async move {
+        controller.start::<Ticker>().await.unwrap();
+        controller.start::<NoTimer>().await.unwrap();
+        delay_for(Duration::from_millis(200)).await;
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+
+    assert!(TICKED.load(Ordering::SeqCst));
+    assert!(INTERVAL_REFUSED.load(Ordering::SeqCst));
+    assert!(TICKER_DRIVERS.load(Ordering::SeqCst));
+    assert!(NO_TIMER_DRIVERS.load(Ordering::SeqCst));
+}
+
+#[test]
+fn placement() {
+    let watchdog = WatchdogBuilder::<Services>::new().build();
+
+    assert_eq!(
+        watchdog.placement(),
+        vec![
+            (
+                "ticker",
+                RuntimePlacement::Individual {
+                    thread_name: "ticker",
+                    io_driver: false,
+                    time_driver: true,
+                }
+            ),
+            (
+                "no_timer",
+                RuntimePlacement::Individual {
+                    thread_name: "no_timer",
+                    io_driver: false,
+                    time_driver: false,
+                }
+            ),
+        ]
+    );
+}
+
+/// the persistent state is kept across the restarts of the service
+#[test]
+fn persistent_state() {
+    let watchdog = WatchdogBuilder::<Restartable>::new().build();
+    let mut controller = watchdog.control();
+
+    watchdog.spawn(async move {
+        for _ in 0..2 {
+            controller.start::<Restarter>().await.unwrap();
+            while !controller
+                .status::<Restarter>()
+                .await
+                .unwrap()
+                .status
+                .is_shutdown()
+            {
+                delay_for(Duration::from_millis(10)).await;
+            }
+        }
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+
+    assert_eq!(RUNS.load(Ordering::SeqCst), 2);
+}
+
+/// a service busy looping on the shared runtime does not starve the other
+/// services as long as it calls `maybe_yield`
+///
+/// the shared runtime has a single core thread: without yielding, the
+/// spinner would hold it and the beacon would never run.
+#[test]
+fn maybe_yield() {
+    let mut shared = RuntimeConfig::shared();
+    shared.core_threads = Some(1);
+    let runtimes = Runtimes::from_runtimes(
+        Runtime::build(RuntimeConfig::watchdog()).unwrap(),
+        Runtime::build(shared).unwrap(),
+    );
+
+    let watchdog = WatchdogBuilder::<Colocated>::new().build_with_runtimes(runtimes);
+    let mut controller = watchdog.control();
+
+    watchdog.spawn(async move {
+        controller.start::<Spinner>().await.unwrap();
+        while SPINS.load(Ordering::SeqCst) == 0 {
+            delay_for(Duration::from_millis(1)).await;
+        }
+
+        controller.start::<Beacon>().await.unwrap();
+        for _ in 0..200 {
+            if BEACON.load(Ordering::SeqCst) {
+                break;
+            }
+            delay_for(Duration::from_millis(10)).await;
+        }
+
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+
+    assert!(BEACON.load(Ordering::SeqCst));
+}
+
+/// the tasks spawned on the watchdog's runtime outlive the service
+#[test]
+fn watchdog_handle() {
+    let watchdog = WatchdogBuilder::<Colocated>::new().build();
+    let mut controller = watchdog.control();
+
+    watchdog.spawn(async move {
+        controller.start::<Warmer>().await.unwrap();
+        while !controller
+            .status::<Warmer>()
+            .await
+            .unwrap()
+            .status
+            .is_shutdown()
+        {
+            delay_for(Duration::from_millis(10)).await;
+        }
+        delay_for(Duration::from_millis(100)).await;
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+
+    assert_eq!(WARMED_ON.lock().unwrap().as_deref(), Some("watchdog"));
+}
+
+/// the deferred messages are delivered to the run of the service that
+/// deferred them only
+#[test]
+fn defer() {
+    let watchdog = WatchdogBuilder::<Restartable>::new().build();
+    let mut controller = watchdog.control();
+
+    watchdog.spawn(async move {
+        for _ in 0..2 {
+            controller.start::<Deferring>().await.unwrap();
+            while !controller
+                .status::<Deferring>()
+                .await
+                .unwrap()
+                .status
+                .is_shutdown()
+            {
+                delay_for(Duration::from_millis(10)).await;
+            }
+        }
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+
+    assert_eq!(*DEFERRED.lock().unwrap(), vec!["fresh"]);
+}
+
+/// the service is started again once it returns after `request_restart`
+#[test]
+fn request_restart() {
+    let watchdog = WatchdogBuilder::<Restartable>::new().build();
+    let mut controller = watchdog.control();
+    let started = Arc::new(Mutex::new(None));
+
+    let result = Arc::clone(&started);
+    watchdog.spawn(async move {
+        controller.start::<SelfHealing>().await.unwrap();
+        for _ in 0..100 {
+            let report = controller.status::<SelfHealing>().await.unwrap();
+            if HEALED_RUNS.load(Ordering::SeqCst) == 2 && report.status.is_shutdown() {
+                *result.lock().unwrap() = Some(report.started);
+                break;
+            }
+            delay_for(Duration::from_millis(10)).await;
+        }
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+
+    assert_eq!(*started.lock().unwrap(), Some(2));
+}
+
+/// a blocking service can check whether it has to stop
+#[test]
+fn is_shutting_down() {
+    let watchdog = WatchdogBuilder::<Colocated>::new().build();
+    let mut controller = watchdog.control();
+
+    watchdog.spawn(async move {
+        controller.start::<Blocking>().await.unwrap();
+        delay_for(Duration::from_millis(20)).await;
+        controller.stop::<Blocking>().await.unwrap();
+        for _ in 0..100 {
+            if BAILED_OUT.load(Ordering::SeqCst) {
+                break;
+            }
+            delay_for(Duration::from_millis(10)).await;
+        }
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+
+    assert!(BAILED_OUT.load(Ordering::SeqCst));
+}
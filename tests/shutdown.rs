@@ -0,0 +1,475 @@
+//! test the shutdown reason propagated to the services
+//!
+
+use async_trait::async_trait;
+use organix::{
+    service::{self, ShutdownReason, Status},
+    Organix, Service, ServiceIdentifier, ServiceState, WatchdogBuilder, WatchdogQuery,
+};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::time::{delay_for, timeout};
+
+static REASON: Mutex<Option<ShutdownReason>> = Mutex::new(None);
+
+/// records the reason it was asked to shutdown for
+struct Recorder {
+    state: ServiceState<Self>,
+}
+
+#[async_trait]
+impl Service for Recorder {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "recorder";
+
+    type IntercomMsg = service::NoIntercom;
+
+    fn prepare(mut state: ServiceState<Self>) -> Self {
+        state.drain_on_shutdown(true);
+        Self { state }
+    }
+
+    async fn start(mut self) {
+        while self.state.intercom_mut().recv().await.is_some() {}
+        *REASON.lock().unwrap() = self.state.shutdown_reason();
+    }
+}
+
+#[derive(Organix)]
+#[runtime(shared)]
+struct App {
+    recorder: service::ServiceManager<Recorder>,
+}
+
+static PERSISTED: Mutex<Option<ShutdownReason>> = Mutex::new(None);
+
+/// takes some time to persist its state once asked to shutdown
+struct Persister {
+    state: ServiceState<Self>,
+}
+
+#[async_trait]
+impl Service for Persister {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "persister";
+
+    type IntercomMsg = service::NoIntercom;
+
+    fn prepare(mut state: ServiceState<Self>) -> Self {
+        state.drain_on_shutdown(true);
+        Self { state }
+    }
+
+    async fn start(mut self) {
+        while self.state.intercom_mut().recv().await.is_some() {}
+        delay_for(Duration::from_millis(50)).await;
+        *PERSISTED.lock().unwrap() = self.state.shutdown_reason();
+    }
+}
+
+#[derive(Organix)]
+#[runtime(shared)]
+struct Persisted {
+    persister: service::ServiceManager<Persister>,
+}
+
+static STOPPING: Mutex<Vec<&str>> = Mutex::new(Vec::new());
+
+/// takes some time to stop once asked to shutdown
+struct Ingress {
+    state: ServiceState<Self>,
+}
+
+#[async_trait]
+impl Service for Ingress {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "ingress";
+
+    type IntercomMsg = service::NoIntercom;
+
+    fn prepare(mut state: ServiceState<Self>) -> Self {
+        state.drain_on_shutdown(true);
+        Self { state }
+    }
+
+    async fn start(mut self) {
+        while self.state.intercom_mut().recv().await.is_some() {}
+        delay_for(Duration::from_millis(50)).await;
+        STOPPING.lock().unwrap().push("ingress stopped");
+    }
+}
+
+struct Storage {
+    state: ServiceState<Self>,
+}
+
+#[async_trait]
+impl Service for Storage {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "storage";
+
+    type IntercomMsg = service::NoIntercom;
+
+    fn prepare(mut state: ServiceState<Self>) -> Self {
+        state.drain_on_shutdown(true);
+        Self { state }
+    }
+
+    async fn start(mut self) {
+        while self.state.intercom_mut().recv().await.is_some() {}
+        STOPPING.lock().unwrap().push("storage stopping");
+    }
+}
+
+#[derive(Organix)]
+#[runtime(shared)]
+struct Grouped {
+    #[shutdown(group = "storage")]
+    storage: service::ServiceManager<Storage>,
+    #[shutdown(group = "ingress")]
+    ingress: service::ServiceManager<Ingress>,
+}
+
+/// ignores the shutdown requests
+struct Hanging {
+    _state: ServiceState<Self>,
+}
+
+#[async_trait]
+impl Service for Hanging {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "hanging";
+
+    type IntercomMsg = service::NoIntercom;
+
+    fn prepare(state: ServiceState<Self>) -> Self {
+        Self { _state: state }
+    }
+
+    async fn start(self) {
+        futures_util::future::pending::<()>().await
+    }
+}
+
+static WEDGED_ABORTED: AtomicBool = AtomicBool::new(false);
+static WEDGED_CLEANED: AtomicBool = AtomicBool::new(false);
+
+/// set `WEDGED_ABORTED` once dropped
+struct AbortGuard;
+
+impl Drop for AbortGuard {
+    fn drop(&mut self) {
+        WEDGED_ABORTED.store(true, Ordering::SeqCst);
+    }
+}
+
+/// ignores the shutdown requests, until it is killed
+struct Wedged {
+    state: ServiceState<Self>,
+}
+
+#[async_trait]
+impl Service for Wedged {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "wedged";
+
+    type IntercomMsg = service::NoIntercom;
+
+    fn prepare(state: ServiceState<Self>) -> Self {
+        Self { state }
+    }
+
+    async fn start(self) {
+        self.state.on_cleanup(|| {
+            // the service's task is already gone
+            let aborted = WEDGED_ABORTED.load(Ordering::SeqCst);
+            WEDGED_CLEANED.store(aborted, Ordering::SeqCst);
+        });
+        let _guard = AbortGuard;
+        futures_util::future::pending::<()>().await
+    }
+}
+
+#[derive(Organix)]
+#[runtime(shared)]
+struct Stuck {
+    #[shutdown(group = "stuck")]
+    hanging: service::ServiceManager<Hanging>,
+    recorder: service::ServiceManager<Recorder>,
+    wedged: service::ServiceManager<Wedged>,
+}
+
+static FLUSHED: AtomicBool = AtomicBool::new(false);
+static FLUSHED_BEFORE_CLEANUP: Mutex<Option<bool>> = Mutex::new(None);
+
+/// flushes its buffer even when killed
+struct Flusher {
+    state: ServiceState<Self>,
+}
+
+#[async_trait]
+impl Service for Flusher {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "flusher";
+
+    type IntercomMsg = service::NoIntercom;
+
+    fn prepare(state: ServiceState<Self>) -> Self {
+        Self { state }
+    }
+
+    async fn start(self) {
+        self.state.on_abort(async {
+            delay_for(Duration::from_millis(10)).await;
+            FLUSHED.store(true, Ordering::SeqCst);
+        });
+        self.state.on_cleanup(|| {
+            *FLUSHED_BEFORE_CLEANUP.lock().unwrap() = Some(FLUSHED.load(Ordering::SeqCst));
+        });
+        futures_util::future::pending::<()>().await
+    }
+}
+
+#[derive(Organix)]
+#[runtime(shared)]
+struct Finalized {
+    #[shutdown(finalize_on_abort = "1s")]
+    flusher: service::ServiceManager<Flusher>,
+}
+
+async fn stopped_for(controller: &mut WatchdogQuery) -> Option<ShutdownReason> {
+    timeout(Duration::from_secs(2), async {
+        while !matches!(
+            controller.status::<Recorder>().await.unwrap().status,
+            Status::Shutdown { .. }
+        ) {
+            delay_for(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("the service did not shutdown");
+
+    REASON.lock().unwrap().take()
+}
+
+#[test]
+fn shutdown_reason() {
+    let watchdog = WatchdogBuilder::<App>::new().build();
+    let mut controller = watchdog.control();
+
+    watchdog.spawn(async move {
+        controller.start::<Recorder>().await.unwrap();
+        controller.stop::<Recorder>().await.unwrap();
+        let user_requested = stopped_for(&mut controller).await;
+
+        controller.start::<Recorder>().await.unwrap();
+        controller
+            .stop_with_reason::<Recorder>(ShutdownReason::DependencyFailed)
+            .await
+            .unwrap();
+        let dependency_failed = stopped_for(&mut controller).await;
+
+        controller.shutdown().await;
+
+        assert_eq!(user_requested, Some(ShutdownReason::UserRequested));
+        assert_eq!(dependency_failed, Some(ShutdownReason::DependencyFailed));
+    });
+
+    watchdog.wait_finished();
+}
+
+/// the services act on the watchdog shutdown before they are killed
+#[test]
+fn watchdog_shutdown_reason() {
+    let watchdog = WatchdogBuilder::<Persisted>::new()
+        .total_shutdown_timeout(Duration::from_secs(1))
+        .build();
+    let mut controller = watchdog.control();
+
+    watchdog.spawn(async move {
+        controller.start::<Persister>().await.unwrap();
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+
+    assert_eq!(
+        *PERSISTED.lock().unwrap(),
+        Some(ShutdownReason::WatchdogShutdown)
+    );
+}
+
+/// the groups are stopped one after the other
+#[test]
+fn shutdown_groups() {
+    let watchdog = WatchdogBuilder::<Grouped>::new()
+        .shutdown_order(&["ingress", "storage"])
+        .build();
+    let mut controller = watchdog.control();
+
+    watchdog.spawn(async move {
+        controller.start::<Storage>().await.unwrap();
+        controller.start::<Ingress>().await.unwrap();
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+
+    assert_eq!(
+        *STOPPING.lock().unwrap(),
+        vec!["ingress stopped", "storage stopping"]
+    );
+}
+
+/// a hanging group only delays the next groups by the shutdown group
+/// timeout
+#[test]
+fn shutdown_group_timeout() {
+    let watchdog = WatchdogBuilder::<Stuck>::new()
+        .shutdown_order(&["stuck"])
+        .shutdown_group_timeout(Duration::from_millis(100))
+        .build();
+    let mut controller = watchdog.control();
+
+    watchdog.spawn(async move {
+        controller.start::<Hanging>().await.unwrap();
+        controller.shutdown().await;
+    });
+
+    let started = Instant::now();
+    watchdog.wait_finished();
+    let elapsed = started.elapsed();
+
+    assert!(elapsed >= Duration::from_millis(100));
+    assert!(elapsed < Duration::from_secs(2));
+}
+
+/// a hanging service cannot delay the end of the watchdog past the total
+/// shutdown timeout
+#[test]
+fn total_shutdown_timeout() {
+    let watchdog = WatchdogBuilder::<Stuck>::new()
+        .shutdown_order(&["stuck"])
+        .total_shutdown_timeout(Duration::from_millis(200))
+        .build();
+    let mut controller = watchdog.control();
+
+    watchdog.spawn(async move {
+        controller.start::<Hanging>().await.unwrap();
+        controller.shutdown().await;
+    });
+
+    let started = Instant::now();
+    watchdog.wait_finished();
+    let elapsed = started.elapsed();
+
+    assert!(elapsed >= Duration::from_millis(200));
+    assert!(elapsed < Duration::from_secs(2));
+}
+
+/// a service ignoring its shutdown can be killed on its own
+#[test]
+fn kill_service() {
+    let watchdog = WatchdogBuilder::<Stuck>::new().build();
+    let mut controller = watchdog.control();
+    let statuses = Arc::new(Mutex::new(Vec::new()));
+    let result = Arc::clone(&statuses);
+
+    watchdog.spawn(async move {
+        controller.start::<Wedged>().await.unwrap();
+        controller.start::<Recorder>().await.unwrap();
+        controller.stop::<Wedged>().await.unwrap();
+        delay_for(Duration::from_millis(50)).await;
+        let stopping = controller.current_status::<Wedged>().await.unwrap();
+
+        controller.kill_service::<Wedged>().await.unwrap();
+        let killed = timeout(Duration::from_secs(2), async {
+            while !controller
+                .current_status::<Wedged>()
+                .await
+                .unwrap()
+                .is_shutdown()
+                || !WEDGED_CLEANED.load(Ordering::SeqCst)
+            {
+                delay_for(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .is_ok();
+        let recorder = controller.current_status::<Recorder>().await.unwrap();
+
+        *result.lock().unwrap() = vec![stopping.is_shutting_down(), killed, recorder.is_started()];
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+
+    assert_eq!(*statuses.lock().unwrap(), vec![true, true, true]);
+    assert!(WEDGED_ABORTED.load(Ordering::SeqCst));
+    assert!(WEDGED_CLEANED.load(Ordering::SeqCst));
+}
+
+/// the running services are stopped and started again, killed if they
+/// ignore the shutdown
+#[test]
+fn reload() {
+    let watchdog = WatchdogBuilder::<Stuck>::new().build();
+    let mut controller = watchdog.control();
+    let outcome = Arc::new(Mutex::new(None));
+    let result = Arc::clone(&outcome);
+
+    watchdog.spawn(async move {
+        controller.start::<Wedged>().await.unwrap();
+        controller.start::<Hanging>().await.unwrap();
+        delay_for(Duration::from_millis(50)).await;
+
+        let reloaded = controller.reload(Duration::from_millis(200)).await;
+        let mut reports = Vec::new();
+        for report in controller.status_all().await.unwrap() {
+            reports.push((
+                report.identifier,
+                report.started,
+                report.status.is_stopped(),
+            ));
+        }
+
+        *result.lock().unwrap() = Some((reloaded, reports));
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+
+    let (reloaded, reports) = outcome.lock().unwrap().take().unwrap();
+    assert_eq!(reloaded, Ok(()));
+    assert_eq!(
+        reports,
+        vec![
+            ("hanging", 2, false),
+            ("recorder", 0, true),
+            ("wedged", 2, false)
+        ]
+    );
+}
+
+/// the finalizer of a killed service runs before its cleanups
+#[test]
+fn finalize_on_abort() {
+    let watchdog = WatchdogBuilder::<Finalized>::new().build();
+    let mut controller = watchdog.control();
+
+    watchdog.spawn(async move {
+        controller.start::<Flusher>().await.unwrap();
+        delay_for(Duration::from_millis(20)).await;
+        controller.kill_service::<Flusher>().await.unwrap();
+
+        let _ = timeout(Duration::from_secs(1), async {
+            while FLUSHED_BEFORE_CLEANUP.lock().unwrap().is_none() {
+                delay_for(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+
+    assert_eq!(*FLUSHED_BEFORE_CLEANUP.lock().unwrap(), Some(true));
+}
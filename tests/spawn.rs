@@ -0,0 +1,79 @@
+//! test the tasks spawned by the services
+//!
+
+use async_trait::async_trait;
+use organix::{service, Organix, Service, ServiceIdentifier, ServiceState, WatchdogBuilder};
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+use tokio::time::{delay_for, timeout};
+
+static CHILD_DROPPED: AtomicBool = AtomicBool::new(false);
+
+/// set `CHILD_DROPPED` once the task holding it has been dropped
+struct DropGuard;
+
+impl Drop for DropGuard {
+    fn drop(&mut self) {
+        CHILD_DROPPED.store(true, Ordering::SeqCst);
+    }
+}
+
+struct Parent {
+    state: ServiceState<Self>,
+}
+
+#[async_trait]
+impl Service for Parent {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "parent";
+
+    type IntercomMsg = service::NoIntercom;
+
+    fn prepare(mut state: ServiceState<Self>) -> Self {
+        // stop receiving messages once shutting down
+        state.drain_on_shutdown(true);
+        Self { state }
+    }
+
+    async fn start(mut self) {
+        self.state.spawn_child(async move {
+            let _guard = DropGuard;
+            futures_util::future::pending::<()>().await
+        });
+
+        while self.state.intercom_mut().recv().await.is_some() {}
+    }
+}
+
+#[derive(Organix)]
+#[runtime(shared)]
+struct App {
+    parent: service::ServiceManager<Parent>,
+}
+
+#[test]
+fn children_cancelled_with_the_service() {
+    let watchdog = WatchdogBuilder::<App>::new().build();
+    let mut controller = watchdog.control();
+
+    watchdog.spawn(async move {
+        controller.start::<Parent>().await.unwrap();
+        delay_for(Duration::from_millis(50)).await;
+        assert!(!CHILD_DROPPED.load(Ordering::SeqCst));
+
+        controller.stop::<Parent>().await.unwrap();
+
+        timeout(Duration::from_secs(1), async {
+            while !CHILD_DROPPED.load(Ordering::SeqCst) {
+                delay_for(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the child task should have been cancelled");
+
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+}
@@ -0,0 +1,110 @@
+//! test that the `WatchdogBuilder::tracing_prefix` is recorded on the spans
+//! of the watchdog and of the services, needs the `tracing` feature
+//!
+//! the spans are created on the runtimes' threads so the capturing layer is
+//! installed as the global subscriber: keep this test alone in its own test
+//! binary.
+#![cfg(feature = "tracing")]
+
+use async_trait::async_trait;
+use organix::{
+    service, Organix, Service, ServiceIdentifier, ServiceManager, ServiceState, WatchdogBuilder,
+};
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+use tracing::{
+    field::{Field, Visit},
+    span, Subscriber,
+};
+use tracing_subscriber::{
+    layer::{Context, Layer, SubscriberExt as _},
+    registry::LookupSpan,
+    Registry,
+};
+
+/// records the `app` field of every span, along with the span's name
+#[derive(Clone, Default)]
+struct Apps(Arc<Mutex<Vec<(&'static str, String)>>>);
+
+/// find the `app` among the recorded fields of a span
+#[derive(Default)]
+struct AppVisitor(Option<String>);
+
+impl Visit for AppVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "app" {
+            self.0 = Some(value.to_owned());
+        }
+    }
+
+    fn record_debug(&mut self, _: &Field, _: &dyn fmt::Debug) {}
+}
+
+impl<S> Layer<S> for Apps
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+        let mut visitor = AppVisitor::default();
+        values.record(&mut visitor);
+
+        if let (Some(app), Some(span)) = (visitor.0, ctx.span(id)) {
+            self.0.lock().unwrap().push((span.name(), app));
+        }
+    }
+}
+
+struct Idle {
+    state: ServiceState<Self>,
+}
+
+#[async_trait]
+impl Service for Idle {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "idle";
+
+    type IntercomMsg = service::NoIntercom;
+
+    fn prepare(state: ServiceState<Self>) -> Self {
+        Self { state }
+    }
+
+    async fn start(mut self) {
+        tracing::info!("started");
+        self.state.watchdog_controller().clone().shutdown().await;
+    }
+}
+
+#[derive(Organix)]
+#[runtime(shared)]
+struct App {
+    idle: ServiceManager<Idle>,
+}
+
+#[test]
+fn tracing_prefix() {
+    let apps = Apps::default();
+    tracing::subscriber::set_global_default(Registry::default().with(apps.clone()))
+        .expect("setting tracing default failed");
+
+    let watchdog = WatchdogBuilder::<App>::new()
+        .tracing_prefix("tracing_prefix")
+        .build();
+
+    let mut controller = watchdog.control();
+    watchdog.spawn(async move {
+        controller.start::<Idle>().await.unwrap();
+    });
+    watchdog.wait_finished();
+
+    let apps = apps.0.lock().unwrap();
+    for name in &["watchdog", "service", "service control"] {
+        assert!(
+            apps.contains(&(*name, "tracing_prefix".to_owned())),
+            "no app=tracing_prefix on the {} span: {:?}",
+            name,
+            apps
+        );
+    }
+}
@@ -2,13 +2,165 @@
 //! services to add noises around.
 //!
 
-use organix::{Organix, WatchdogBuilder};
-use std::time::Duration;
+use async_trait::async_trait;
+use futures_util::FutureExt as _;
+use organix::{
+    runtime::{
+        Runtime, RuntimeConfig, RuntimeConfigError, RuntimeMetrics, RuntimePlacement, Runtimes,
+    },
+    service::{self, ServiceError, ServiceManager, Status},
+    Organix, Service, ServiceIdentifier, ServiceState, WatchdogBuilder, WatchdogError,
+};
+use std::{
+    error::Error as _,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::sleep,
+    time::{Duration, Instant},
+};
 use tokio::time::delay_for;
 
 #[derive(Organix)]
 struct NoServices;
 
+/// panics while being prepared, that is within the watchdog
+struct Exploding;
+
+#[async_trait]
+impl Service for Exploding {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "exploding";
+
+    type IntercomMsg = service::NoIntercom;
+
+    fn prepare(_: ServiceState<Self>) -> Self {
+        panic!("exploding")
+    }
+
+    async fn start(self) {}
+}
+
+#[derive(Organix)]
+#[runtime(shared)]
+struct Explosive {
+    exploding: ServiceManager<Exploding>,
+}
+
+/// holds on to clones of its `WatchdogQuery` until it is killed
+struct Lingering {
+    state: ServiceState<Self>,
+}
+
+#[async_trait]
+impl Service for Lingering {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "lingering";
+
+    type IntercomMsg = service::NoIntercom;
+
+    fn prepare(state: ServiceState<Self>) -> Self {
+        Self { state }
+    }
+
+    async fn start(self) {
+        let query = self.state.watchdog_controller().clone();
+        self.state.spawn(async move {
+            let _query = query;
+            futures_util::future::pending::<()>().await
+        });
+        futures_util::future::pending::<()>().await
+    }
+}
+
+#[derive(Organix)]
+#[runtime(shared)]
+struct Lingerer {
+    lingering: ServiceManager<Lingering>,
+}
+
+static STUCK_RUNNING: AtomicBool = AtomicBool::new(false);
+static STUCK_ABORTED: AtomicBool = AtomicBool::new(false);
+
+/// never finishes on its own, records when its task is dropped
+struct Stuck;
+
+struct AbortGuard;
+
+impl Drop for AbortGuard {
+    fn drop(&mut self) {
+        STUCK_ABORTED.store(true, Ordering::SeqCst);
+    }
+}
+
+#[async_trait]
+impl Service for Stuck {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "stuck";
+
+    type IntercomMsg = service::NoIntercom;
+
+    fn prepare(_: ServiceState<Self>) -> Self {
+        Self
+    }
+
+    async fn start(self) {
+        let _guard = AbortGuard;
+        STUCK_RUNNING.store(true, Ordering::SeqCst);
+        futures_util::future::pending::<()>().await
+    }
+}
+
+#[derive(Organix)]
+#[runtime(shared)]
+struct Emergency {
+    stuck: ServiceManager<Stuck>,
+}
+
+static POOL_THREAD: Mutex<Option<String>> = Mutex::new(None);
+
+/// records the name of the thread it is started on
+struct PoolThread;
+
+#[async_trait]
+impl Service for PoolThread {
+    const SERVICE_IDENTIFIER: ServiceIdentifier = "pool_thread";
+
+    type IntercomMsg = service::NoIntercom;
+
+    fn prepare(_: ServiceState<Self>) -> Self {
+        Self
+    }
+
+    async fn start(self) {
+        let name = std::thread::current().name().map(String::from);
+        *POOL_THREAD.lock().unwrap() = name;
+    }
+}
+
+#[derive(Organix)]
+#[runtime(shared = "io-pool")]
+struct Pools {
+    lingering: ServiceManager<Lingering>,
+    #[runtime(shared = "cpu-pool")]
+    pool_thread: ServiceManager<PoolThread>,
+    #[runtime(shared)]
+    exploding: ServiceManager<Exploding>,
+}
+
+/// an app generic over one of its services
+#[derive(Organix)]
+#[runtime(shared)]
+struct Generic<S: Service>
+where
+    S::IntercomMsg: Send,
+{
+    service: ServiceManager<S>,
+}
+
+#[derive(Organix)]
+struct Individual {
+    exploding: ServiceManager<Exploding>,
+}
+
 /// test that the execution of the watchdog will be stopped shortly
 /// after receiving the shutdown command from the controller
 #[test]
@@ -39,6 +191,100 @@ fn start_kill_watchdog() {
     watchdog.wait_finished();
 }
 
+/// the watchdog does not wait for all the `WatchdogQuery` clones to be
+/// dropped to finish
+#[test]
+fn shutdown_with_lingering_queries() {
+    let watchdog = WatchdogBuilder::<Lingerer>::new()
+        .total_shutdown_timeout(Duration::from_millis(100))
+        .build();
+    let lingering = watchdog.control();
+    let mut controller = watchdog.control();
+
+    let held = lingering.clone();
+    watchdog.spawn(async move {
+        let _held = held;
+        futures_util::future::pending::<()>().await
+    });
+    watchdog.spawn(async move {
+        controller.start::<Lingering>().await.unwrap();
+        controller.shutdown().await;
+    });
+
+    let started = Instant::now();
+    watchdog.wait_finished();
+    assert!(started.elapsed() < Duration::from_secs(2));
+    std::mem::drop(lingering);
+}
+
+/// `abort_all` aborts the services and stops the watchdog
+#[test]
+fn abort_all() {
+    let watchdog = WatchdogBuilder::<Emergency>::new().build();
+    watchdog.control_blocking().start::<Stuck>().unwrap();
+    while !STUCK_RUNNING.load(Ordering::SeqCst) {
+        sleep(Duration::from_millis(5));
+    }
+
+    watchdog.abort_all();
+
+    let started = Instant::now();
+    while !STUCK_ABORTED.load(Ordering::SeqCst) && started.elapsed() < Duration::from_secs(2) {
+        sleep(Duration::from_millis(5));
+    }
+    assert!(STUCK_ABORTED.load(Ordering::SeqCst));
+
+    watchdog.wait_finished();
+}
+
+/// the watchdog answers the pings until it is stopped
+#[test]
+fn ping() {
+    let watchdog = WatchdogBuilder::<NoServices>::new().build();
+    let mut controller = watchdog.control();
+    let mut stopped = watchdog.control().read_only();
+    let timeout = Duration::from_secs(1);
+
+    let latency = watchdog.control_blocking().ping(timeout).unwrap();
+    assert!(latency < timeout);
+
+    watchdog.spawn(async move { controller.kill().await });
+    watchdog.wait_finished();
+
+    let mut runtime = tokio::runtime::Builder::new()
+        .basic_scheduler()
+        .enable_time()
+        .build()
+        .unwrap();
+    assert!(matches!(
+        runtime.block_on(stopped.ping(timeout)),
+        Err(WatchdogError::NoReply { .. })
+    ));
+}
+
+/// the tasks spawned on the watchdog's runtime can use the `io` driver once
+/// enabled
+#[test]
+fn watchdog_io() {
+    let watchdog = WatchdogBuilder::<NoServices>::new()
+        .watchdog_io(true)
+        .build();
+    let mut controller = watchdog.control();
+    let bound = Arc::new(AtomicBool::new(false));
+    let result = Arc::clone(&bound);
+
+    let listener = watchdog.spawn(async {
+        tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    });
+    watchdog.spawn(async move {
+        result.store(listener.await.is_ok(), Ordering::SeqCst);
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+    assert!(bound.load(Ordering::SeqCst));
+}
+
 /// starting an unknown service will fail and the error will
 /// be appropriately reported back up to the monitor
 #[test]
@@ -53,3 +299,347 @@ fn start_unknown_service() {
 
     watchdog.wait_finished()
 }
+
+/// waiting for a watchdog that died without notifying it finished
+/// returns rather than panicking
+#[test]
+fn wait_finished_after_watchdog_panicked() {
+    let watchdog = WatchdogBuilder::<Explosive>::new().build();
+    let mut controller = watchdog.control();
+    let mut runtime = tokio::runtime::Runtime::new().unwrap();
+
+    assert!(runtime.block_on(controller.start::<Exploding>()).is_err());
+
+    watchdog.wait_finished();
+}
+
+/// the watchdog can be built with runtimes configured by the user
+#[test]
+fn build_with_runtimes() {
+    let watchdog = Runtime::build(RuntimeConfig::watchdog()).unwrap();
+    let mut shared = RuntimeConfig::shared();
+    shared.core_threads = Some(1);
+    let shared = Runtime::build(shared).unwrap();
+
+    let runtimes = Runtimes::from_runtimes(watchdog, shared);
+
+    let watchdog = WatchdogBuilder::<NoServices>::new().build_with_runtimes(runtimes);
+    let mut controller = watchdog.control();
+
+    watchdog.spawn(async move {
+        delay_for(Duration::from_millis(10)).await;
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+}
+
+/// the services are placed on their named shared pools, the ones
+/// configured by the user are used as is
+#[test]
+fn shared_pools() {
+    let mut runtimes = Runtimes::new().unwrap();
+    let mut cpu_pool = RuntimeConfig::pool("cpu-pool");
+    cpu_pool.io_driver = false;
+    runtimes.add_pool(Runtime::build(cpu_pool).unwrap());
+
+    let watchdog = WatchdogBuilder::<Pools>::new().build_with_runtimes(runtimes);
+    let mut controller = watchdog.control();
+
+    let shared = |pool, io_driver| RuntimePlacement::Shared {
+        pool,
+        io_driver,
+        time_driver: true,
+    };
+    assert_eq!(
+        watchdog.placement(),
+        vec![
+            ("lingering", shared(Some("io-pool"), true)),
+            ("pool_thread", shared(Some("cpu-pool"), false)),
+            ("exploding", shared(None, true)),
+        ]
+    );
+
+    watchdog.spawn(async move {
+        controller.start::<PoolThread>().await.unwrap();
+        while POOL_THREAD.lock().unwrap().is_none() {
+            delay_for(Duration::from_millis(5)).await;
+        }
+        controller.shutdown().await;
+    });
+
+    watchdog.wait_finished();
+
+    assert_eq!(POOL_THREAD.lock().unwrap().as_deref(), Some("cpu-pool"));
+}
+
+/// the watchdog of an app, generic or not, can be built from the app
+#[test]
+fn app_watchdog() {
+    let watchdog = Generic::<PoolThread>::watchdog();
+    let mut controller = watchdog.control();
+    assert_eq!(watchdog.service_count(), 1);
+    assert_eq!(
+        Generic::<PoolThread>::service_catalog(),
+        &[("service", "pool_thread")]
+    );
+
+    watchdog.spawn(async move { controller.shutdown().await });
+    watchdog.wait_finished();
+
+    assert_eq!(NoServices::watchdog().service_count(), 0);
+}
+
+/// the individual runtimes can be configured at runtime
+#[test]
+fn configure_runtime() {
+    let configured = Arc::new(Mutex::new(Vec::new()));
+    let services = Arc::clone(&configured);
+    let watchdog = WatchdogBuilder::<Individual>::new()
+        .configure_runtime(move |service_identifier, cfg| {
+            services.lock().unwrap().push(service_identifier);
+            cfg.io_driver = true;
+        })
+        .build();
+
+    assert_eq!(*configured.lock().unwrap(), vec!["exploding"]);
+    assert_eq!(
+        watchdog.placement(),
+        vec![(
+            "exploding",
+            RuntimePlacement::Individual {
+                thread_name: "exploding",
+                io_driver: true,
+                time_driver: false,
+            }
+        )]
+    );
+}
+
+/// an invalid config is ignored, the runtime is built from the attributes
+#[test]
+fn configure_runtime_invalid() {
+    let watchdog = WatchdogBuilder::<Individual>::new()
+        .configure_runtime(|_, cfg| {
+            cfg.io_driver = true;
+            cfg.core_threads = Some(0);
+        })
+        .build();
+
+    assert_eq!(
+        watchdog.placement(),
+        vec![(
+            "exploding",
+            RuntimePlacement::Individual {
+                thread_name: "exploding",
+                io_driver: false,
+                time_driver: false,
+            }
+        )]
+    );
+}
+
+/// the thread counts of every runtime are known, even when tokio picks
+/// them
+#[test]
+fn runtime_metrics() {
+    let watchdog = WatchdogBuilder::<Individual>::new()
+        .configure_runtime(|_, cfg| cfg.core_threads = Some(2))
+        .build();
+
+    let cpus = Runtime::build(RuntimeConfig::new("cpus"))
+        .unwrap()
+        .effective_core_threads();
+    assert!(cpus >= 1);
+
+    assert_eq!(
+        watchdog.runtime_metrics(),
+        vec![
+            RuntimeMetrics {
+                thread_name: "watchdog",
+                core_threads: cpus,
+                max_threads: 512,
+            },
+            RuntimeMetrics {
+                thread_name: "exploding",
+                core_threads: 2,
+                max_threads: 512,
+            },
+        ]
+    );
+}
+
+/// the monitor reports the watchdog is not running anymore once it
+/// has been shutdown, and can still wait for it to finish
+#[test]
+fn is_running() {
+    let watchdog = WatchdogBuilder::<NoServices>::new().build();
+    let mut controller = watchdog.control();
+
+    assert!(watchdog.is_running());
+    assert_eq!(watchdog.service_count(), 0);
+
+    watchdog.spawn(async move {
+        controller.shutdown().await;
+    });
+
+    let started = Instant::now();
+    while watchdog.is_running() {
+        assert!(started.elapsed() < Duration::from_secs(1));
+        sleep(Duration::from_millis(10));
+    }
+
+    watchdog.wait_finished();
+}
+
+/// the shared runtime is only built once a service needs it
+#[test]
+fn shared_runtime_is_lazy() {
+    let mut runtimes = Runtimes::new().unwrap();
+    assert!(runtimes.shared().is_none());
+
+    runtimes.shared_mut().unwrap();
+    assert!(runtimes.shared().is_some());
+}
+
+/// the individual runtimes above the limit are still added, only a
+/// warning is logged
+#[test]
+fn max_individual_runtimes() {
+    let mut runtimes = Runtimes::new().unwrap();
+    runtimes.set_max_individuals(1);
+
+    runtimes.add(Runtime::build(RuntimeConfig::new("first")).unwrap());
+    runtimes.add(Runtime::build(RuntimeConfig::new("second")).unwrap());
+
+    assert_eq!(runtimes.individuals_count(), 2);
+    assert!(runtimes.individual("second").is_some());
+
+    let mut names: Vec<_> = runtimes.iter_individuals().map(|(k, _)| k).collect();
+    names.sort();
+    assert_eq!(names, vec!["first", "second"]);
+
+    let watchdog = WatchdogBuilder::<Explosive>::new()
+        .max_individual_runtimes(0)
+        .build();
+    assert_eq!(watchdog.service_count(), 1);
+}
+
+/// a service started while the watchdog is frozen only starts once it is
+/// unfrozen, the status queries are still answered meanwhile
+#[test]
+fn freeze() {
+    let watchdog = WatchdogBuilder::<Lingerer>::new().build();
+    let mut controller = watchdog.control();
+    let observed = Arc::new(Mutex::new(None));
+
+    let results = Arc::clone(&observed);
+    watchdog.spawn(async move {
+        controller.freeze().await.unwrap();
+        let mut starter = controller.clone();
+        let start = tokio::spawn(async move { starter.start::<Lingering>().await });
+
+        delay_for(Duration::from_millis(50)).await;
+        let frozen = controller.current_status::<Lingering>().await.unwrap();
+        let ping = controller.ping(Duration::from_secs(1)).await.is_ok();
+
+        controller.unfreeze().await.unwrap();
+        let started = start.await.unwrap();
+        let unfrozen = controller.current_status::<Lingering>().await.unwrap();
+
+        *results.lock().unwrap() =
+            Some((frozen.is_stopped(), ping, started, unfrozen.is_stopped()));
+        controller.kill().await;
+    });
+
+    watchdog.wait_finished();
+
+    assert_eq!(*observed.lock().unwrap(), Some((true, true, Ok(()), false)));
+}
+
+/// the weak query can only be upgraded while the watchdog is running
+#[test]
+fn control_weak() {
+    let watchdog = WatchdogBuilder::<NoServices>::new().build();
+    let weak = watchdog.control_weak();
+
+    let mut controller = weak.upgrade().expect("the watchdog is running");
+    watchdog.spawn(async move {
+        controller.shutdown().await;
+    });
+
+    let started = Instant::now();
+    while watchdog.is_running() {
+        assert!(started.elapsed() < Duration::from_secs(1));
+        sleep(Duration::from_millis(10));
+    }
+
+    assert!(weak.upgrade().is_none());
+    watchdog.wait_finished();
+}
+
+/// the shutdown signal resolves once the watchdog is asked to shutdown
+#[test]
+fn shutdown_signal() {
+    let watchdog = WatchdogBuilder::<NoServices>::new().build();
+    let mut controller = watchdog.control();
+    let signal = controller.shutdown_signal();
+
+    watchdog.spawn(async move {
+        assert!(controller.shutdown_signal().now_or_never().is_none());
+        controller.shutdown().await;
+    });
+    watchdog.wait_finished();
+
+    assert!(signal.now_or_never().is_some());
+}
+
+/// invalid runtime configurations are reported instead of building
+/// the runtime
+#[test]
+fn invalid_runtime_config() {
+    let mut config = RuntimeConfig::shared();
+    config.core_threads = Some(4);
+    config.max_threads = Some(2);
+
+    assert_eq!(
+        config.validate(),
+        Err(RuntimeConfigError::TooManyCoreThreads {
+            thread_name: "shared",
+            core_threads: 4,
+            max_threads: 2,
+        })
+    );
+
+    let error = Runtime::build(config).err().unwrap();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+
+    assert!(RuntimeConfig::shared().validate().is_ok());
+}
+
+/// the errors expose their cause
+#[test]
+fn error_sources() {
+    let error = WatchdogError::CannotStartService {
+        service_identifier: "service",
+        source: ServiceError::CannotStart {
+            status: Status::started(),
+        },
+    };
+    let source = error.source().unwrap();
+    assert!(source.downcast_ref::<ServiceError>().is_some());
+
+    let (reply, receiver) = tokio::sync::oneshot::channel::<()>();
+    std::mem::drop(reply);
+    let reason = receiver.now_or_never().unwrap().unwrap_err();
+    let error = WatchdogError::NoReply {
+        reason,
+        context: "query",
+    };
+    let source = error.source().unwrap();
+    assert!(source
+        .downcast_ref::<tokio::sync::oneshot::error::RecvError>()
+        .is_some());
+
+    assert!(WatchdogError::Draining.source().is_none());
+}